@@ -0,0 +1,183 @@
+use std::io::Cursor;
+
+use serde_json::Value;
+
+use crate::parser::{next_word, Command};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Permission {
+    Read,
+    Write,
+    Subscribe,
+}
+
+/// Key patterns allowed for each permission. Patterns use the dot notation,
+/// where `*` matches one segment, or everything that follows when it's the
+/// last segment. `{id}` and `{user}` are replaced by the client id and the user
+/// name when authenticating:
+///
+/// ```json
+/// {
+///     "read": ["room.*", "player.*"],
+///     "write": ["player.{id}.*"],
+///     "subscribe": ["room.*"]
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Rules {
+    read: Vec<String>,
+    write: Vec<String>,
+    subscribe: Vec<String>,
+}
+
+impl Rules {
+    pub fn from_json(json: &Value) -> Rules {
+        Rules {
+            read: patterns(&json["read"]),
+            write: patterns(&json["write"]),
+            subscribe: patterns(&json["subscribe"]),
+        }
+    }
+
+    /// The rules for a connection, with the placeholders replaced.
+    pub fn resolve(&self, id: usize, user: &str) -> Rules {
+        let resolve = |patterns: &Vec<String>| {
+            patterns
+                .iter()
+                .map(|x| x.replace("{id}", &id.to_string()).replace("{user}", user))
+                .collect()
+        };
+
+        Rules {
+            read: resolve(&self.read),
+            write: resolve(&self.write),
+            subscribe: resolve(&self.subscribe),
+        }
+    }
+
+    /// True if the key is allowed.
+    pub fn allows(&self, permission: Permission, key: &str) -> bool {
+        self.patterns(permission).iter().any(|x| matches(x, key))
+    }
+
+    /// True if the key and all its children are allowed, for queries and
+    /// subscriptions that include the children. An empty key means everything.
+    pub fn allows_tree(&self, permission: Permission, key: &str) -> bool {
+        self.patterns(permission).iter().any(|x| covers(x, key))
+    }
+
+    /// True if the command is allowed with this key and data.
+    pub fn allows_command(&self, command: &Command, key: &str, data: &[u8]) -> bool {
+        let (permission, tree) = match permission(command) {
+            Some(needed) => needed,
+            None => return true,
+        };
+
+        match command {
+            // The keys are inside the data, after the separator.
+            Command::SetList | Command::GetList => {
+                let separator = key.as_bytes()[0];
+                data.split(|x| *x == separator).all(|key_val| {
+                    let key = String::from_utf8_lossy(next_word(&mut Cursor::new(key_val)));
+                    self.allows(permission, &key)
+                })
+            }
+
+            // The first or last message to subscribers is also a write.
+            Command::SubGet
+            | Command::SubKeyValue
+            | Command::SubFullKey
+            | Command::SubJson
+            | Command::Unsub
+                if !data.is_empty() =>
+            {
+                self.allows_tree(permission, key) && self.allows(Permission::Write, key)
+            }
+
+            _ if tree => self.allows_tree(permission, key),
+
+            _ => self.allows(permission, key),
+        }
+    }
+
+    fn patterns(&self, permission: Permission) -> &Vec<String> {
+        match permission {
+            Permission::Read => &self.read,
+            Permission::Write => &self.write,
+            Permission::Subscribe => &self.subscribe,
+        }
+    }
+}
+
+/// The permission needed for a command, and if the command includes the
+/// children of the key.
+pub fn permission(command: &Command) -> Option<(Permission, bool)> {
+    match command {
+        Command::No | Command::Auth | Command::Namespace | Command::Udp => None,
+
+        Command::Get | Command::GetList => Some((Permission::Read, false)),
+
+        Command::KeyValue | Command::Jtrim | Command::Json | Command::Keys | Command::Children => {
+            Some((Permission::Read, true))
+        }
+
+        Command::SetJson | Command::ReplaceJson | Command::MergePatch | Command::JsonPatch => {
+            Some((Permission::Write, true))
+        }
+
+        Command::Set
+        | Command::SetIfNone
+        | Command::SetList
+        | Command::Inc
+        | Command::Append
+        | Command::Delete
+        | Command::SubCall => Some((Permission::Write, false)),
+
+        Command::SubGet
+        | Command::SubKeyValue
+        | Command::SubFullKey
+        | Command::SubJson
+        | Command::Unsub => Some((Permission::Subscribe, true)),
+    }
+}
+
+fn patterns(json: &Value) -> Vec<String> {
+    match json.as_array() {
+        Some(array) => array
+            .iter()
+            .filter_map(|x| x.as_str())
+            .map(|x| x.to_owned())
+            .collect(),
+
+        None => Vec::new(),
+    }
+}
+
+/// "player.*" matches "player.1" and "player.1.name", but not "player".
+fn matches(pattern: &str, key: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('.').collect();
+    let key: Vec<&str> = key.split('.').collect();
+
+    for (i, segment) in pattern.iter().enumerate() {
+        let last = i == pattern.len() - 1;
+
+        match key.get(i) {
+            None => return false,
+            Some(_) if *segment == "*" && last => return true,
+            Some(_) if *segment == "*" => continue,
+            Some(k) if k == segment => continue,
+            Some(_) => return false,
+        }
+    }
+
+    pattern.len() == key.len()
+}
+
+/// "player.*" covers "player" and anything inside, "*" covers everything.
+fn covers(pattern: &str, key: &str) -> bool {
+    if key.is_empty() {
+        return pattern == "*";
+    }
+
+    pattern.ends_with('*') && matches(pattern, &format!("{key}.*"))
+}
@@ -0,0 +1,452 @@
+use std::{
+    collections::HashMap,
+    io::{self, Error, ErrorKind},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    sync::{self, oneshot},
+    task::JoinHandle,
+    time::{sleep, timeout},
+};
+
+use crate::{
+    client::{
+        command, data, depth_arg, disconnected, get_list, key_values, keys, number, ok, page,
+        set_list, sized_command, text, values, Handler, Message, Session, Sub, HEARTBEAT_TIMEOUT,
+        REQUEST_TIMEOUT,
+    },
+    message::{get_u32, stamp_header},
+    supervisor::lock,
+};
+
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// The [`Client`](crate::client::Client) for **tokio**, with the same
+/// commands, message handler and reconnection.
+pub struct AsyncClient {
+    shared: Arc<Shared>,
+    reading: JoinHandle<()>,
+    timeout: Duration,
+}
+
+struct Shared {
+    address: String,
+    id: AtomicU32,
+    msg_id: Mutex<u16>,
+    writer: sync::Mutex<Option<OwnedWriteHalf>>,
+    pending: Mutex<HashMap<u32, oneshot::Sender<Vec<u8>>>>,
+    session: Mutex<Session>,
+    handler: Mutex<Option<Handler>>,
+    closed: AtomicBool,
+}
+
+impl AsyncClient {
+    /// Connects and waits for the id from the server.
+    pub async fn connect(address: &str) -> io::Result<AsyncClient> {
+        let shared = Arc::new(Shared {
+            address: address.to_owned(),
+            id: AtomicU32::new(0),
+            msg_id: Mutex::new(0),
+            writer: sync::Mutex::new(None),
+            pending: Mutex::new(HashMap::new()),
+            session: Mutex::new(Session::default()),
+            handler: Mutex::new(None),
+            closed: AtomicBool::new(false),
+        });
+
+        let reader = shared.handshake().await?;
+        let reading = tokio::spawn(shared.clone().read_loop(reader));
+
+        Ok(AsyncClient {
+            shared,
+            reading,
+            timeout: REQUEST_TIMEOUT,
+        })
+    }
+
+    /// Time to wait for each reply.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// The id from the server, 0 while disconnected.
+    pub fn id(&self) -> u16 {
+        self.shared.id.load(Ordering::Relaxed) as u16
+    }
+
+    /// Receives the subscription updates and **!** calls, on the task that
+    /// reads from the server, so the handler shouldn't block.
+    pub fn on_message<F: FnMut(Message) + Send + 'static>(&self, handler: F) {
+        *lock(&self.shared.handler) = Some(Box::new(handler));
+    }
+
+    /// Sends the command with the key and the data, and waits for the reply.
+    pub async fn request(&self, command: &str, key: &str, data: &[u8]) -> io::Result<Vec<u8>> {
+        let rx = self.shared.send(self::command(command, key, data)).await?;
+        self.reply(rx).await
+    }
+
+    /// Like a request, with the value after its size so it arrives exactly as
+    /// it is.
+    async fn request_value(&self, command: &str, key: &str, value: &[u8]) -> io::Result<Vec<u8>> {
+        let rx = self.shared.send(sized_command(command, key, value)).await?;
+        self.reply(rx).await
+    }
+
+    async fn reply(&self, rx: oneshot::Receiver<Vec<u8>>) -> io::Result<Vec<u8>> {
+        match timeout(self.timeout, rx).await {
+            Ok(Ok(reply)) => Ok(reply),
+            Ok(Err(_)) => Err(disconnected()),
+            Err(_) => Err(Error::new(ErrorKind::TimedOut, "No reply")),
+        }
+    }
+
+    /// Authenticates with the shared secret, like **a**.
+    pub async fn auth(&self, secret: &str) -> io::Result<()> {
+        self.authenticate(command("a", secret, b"")).await
+    }
+
+    /// Authenticates with a user and a password, like **a**.
+    pub async fn login(&self, user: &str, password: &str) -> io::Result<()> {
+        self.authenticate(command("a", user, password.as_bytes()))
+            .await
+    }
+
+    async fn authenticate(&self, message: Vec<u8>) -> io::Result<()> {
+        let rx = self.shared.send(message.to_owned()).await?;
+
+        match self.reply(rx).await?.as_slice() {
+            b"OK" => {
+                lock(&self.shared.session).auth = Some(message);
+                Ok(())
+            }
+
+            _ => Err(Error::new(ErrorKind::PermissionDenied, "Wrong credentials")),
+        }
+    }
+
+    /// Moves to the namespace, or to the default one with an empty name, like
+    /// **n**.
+    pub async fn namespace(&self, name: &str) -> io::Result<()> {
+        ok(&self.request("n", name, b"").await?)?;
+
+        let mut session = lock(&self.shared.session);
+        session.namespace = match name.is_empty() {
+            true => None,
+            false => Some(name.to_owned()),
+        };
+
+        Ok(())
+    }
+
+    /// A token to bind a UDP socket, like **u**. It's only valid for this
+    /// connection, so it's needed again after a reconnection.
+    pub async fn udp_token(&self) -> io::Result<String> {
+        let reply = data(self.request("u", "", b"").await?)?;
+        Ok(String::from_utf8_lossy(&reply).into())
+    }
+
+    pub async fn set(&self, key: &str, value: &[u8]) -> io::Result<()> {
+        ok(&self.request_value("s", key, value).await?)
+    }
+
+    /// Sets the value only if the key doesn't exist, like **s?**.
+    pub async fn set_if_none(&self, key: &str, value: &[u8]) -> io::Result<()> {
+        ok(&self.request_value("s?", key, value).await?)
+    }
+
+    /// Sets all the keys and values in one operation, like **sl**.
+    pub async fn set_list(&self, list: &[(&str, &[u8])]) -> io::Result<()> {
+        let (separator, data) = set_list(list)?;
+        ok(&self.request("sl", &separator, &data).await?)
+    }
+
+    /// The fields of the JSON as children of the key, merged with the ones it
+    /// already has, like **sj**.
+    pub async fn set_json(&self, key: &str, json: &str) -> io::Result<()> {
+        ok(&self.request("sj", key, json.as_bytes()).await?)
+    }
+
+    /// The fields of the JSON as the only children of the key, like **sj!**.
+    pub async fn replace_json(&self, key: &str, json: &str) -> io::Result<()> {
+        ok(&self.request("sj!", key, json.as_bytes()).await?)
+    }
+
+    /// Changes the JSON of the key with a merge patch, like **jm**.
+    pub async fn merge_patch(&self, key: &str, patch: &str) -> io::Result<()> {
+        ok(&self.request("jm", key, patch.as_bytes()).await?)
+    }
+
+    /// Changes the JSON of the key with a JSON Patch, like **jp**. Nothing
+    /// changes and it fails when an operation does, like a **test**.
+    pub async fn json_patch(&self, key: &str, patch: &str) -> io::Result<()> {
+        ok(&self.request("jp", key, patch.as_bytes()).await?)
+    }
+
+    /// Increases the value by 1, like **+1**. The value becomes 0 if it isn't
+    /// a number.
+    pub async fn inc(&self, key: &str) -> io::Result<u64> {
+        number(&data(self.request("+1", key, b"").await?)?)
+    }
+
+    /// Appends to the value, like **+**.
+    pub async fn append(&self, key: &str, value: &[u8]) -> io::Result<()> {
+        ok(&self.request_value("+", key, value).await?)
+    }
+
+    pub async fn delete(&self, key: &str) -> io::Result<()> {
+        ok(&self.request("d", key, b"").await?)
+    }
+
+    /// The value, empty when the key doesn't exist. It comes from **gl**, so a
+    /// value like **NO** or **DENIED** is never taken for an error.
+    pub async fn get(&self, key: &str) -> io::Result<Vec<u8>> {
+        let value = self.get_list(&[key]).await?.pop().flatten();
+        Ok(value.unwrap_or_default())
+    }
+
+    /// The values of the keys in the same order, None when the key doesn't
+    /// exist, like **gl**.
+    pub async fn get_list(&self, keys: &[&str]) -> io::Result<Vec<Option<Vec<u8>>>> {
+        let (separator, list) = get_list(keys)?;
+        values(&data(self.request("gl", &separator, &list).await?)?)
+    }
+
+    /// The last segment of the keys and the values of the key and its
+    /// children, like **k**. Up to a depth below the key, or all of them.
+    pub async fn key_values(
+        &self,
+        key: &str,
+        depth: Option<usize>,
+    ) -> io::Result<Vec<(String, Vec<u8>)>> {
+        Ok(key_values(&data(
+            self.request("k", key, &depth_arg(depth)).await?,
+        )?))
+    }
+
+    /// The JSON without the full path, like **j**, with the depth of
+    /// [`AsyncClient::key_values`].
+    pub async fn jtrim(&self, key: &str, depth: Option<usize>) -> io::Result<String> {
+        text(data(self.request("j", key, &depth_arg(depth)).await?)?)
+    }
+
+    /// The JSON with the full path, like **js**, with the depth of
+    /// [`AsyncClient::key_values`].
+    pub async fn json(&self, key: &str, depth: Option<usize>) -> io::Result<String> {
+        text(data(self.request("js", key, &depth_arg(depth)).await?)?)
+    }
+
+    /// A page of the keys with a value of the key and its children, like
+    /// **gk**. Up to the size, after a key or from the start when it's empty.
+    /// An empty page is the end.
+    pub async fn keys(&self, key: &str, size: usize, after: &str) -> io::Result<Vec<String>> {
+        keys(&self.request("gk", key, &page(size, after)).await?)
+    }
+
+    /// A page of the direct children of the key, like **gc**, with or without
+    /// a value. It continues after a child, like [`AsyncClient::keys`].
+    pub async fn children(&self, key: &str, size: usize, after: &str) -> io::Result<Vec<String>> {
+        keys(&self.request("gc", key, &page(size, after)).await?)
+    }
+
+    /// Subscribes to the key and its children. The updates go to the handler
+    /// from [`AsyncClient::on_message`].
+    pub async fn subscribe(&self, sub: Sub, key: &str) -> io::Result<()> {
+        ok(&self.request(sub.command(), key, b"").await?)?;
+        lock(&self.shared.session).subscribe(sub, key);
+
+        Ok(())
+    }
+
+    /// Removes the subscriptions to the key, like **#-**.
+    pub async fn unsubscribe(&self, key: &str) -> io::Result<()> {
+        lock(&self.shared.session).unsubscribe(key);
+        ok(&self.request("#-", key, b"").await?)
+    }
+
+    /// Calls the subscriptions to the key without changing the data, like
+    /// **!**.
+    pub async fn call(&self, key: &str, value: &[u8]) -> io::Result<()> {
+        ok(&self.request_value("!", key, value).await?)
+    }
+
+    /// Stops reading and reconnecting, the connection closes with the client.
+    pub fn close(&self) {
+        self.shared.closed.store(true, Ordering::Relaxed);
+        self.reading.abort();
+        lock(&self.shared.pending).clear();
+    }
+}
+
+impl Drop for AsyncClient {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl Shared {
+    /// Connects, reads the id and restores the session. Returns the reader.
+    async fn handshake(&self) -> io::Result<OwnedReadHalf> {
+        let socket = TcpStream::connect(&self.address).await?;
+        socket.set_nodelay(true)?;
+
+        let (mut reader, writer) = socket.into_split();
+
+        let message = match timeout(REQUEST_TIMEOUT, read_message(&mut reader)).await {
+            Ok(message) => message?,
+            Err(_) => return Err(Error::new(ErrorKind::TimedOut, "No id")),
+        };
+
+        if message.id != 0 || message.from == 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "Expected an id"));
+        }
+
+        self.id.store(message.from, Ordering::Relaxed);
+        *self.writer.lock().await = Some(writer);
+
+        // The replies of the replay are ignored, their receivers are dropped.
+        let replay = lock(&self.session).replay();
+        for message in replay {
+            self.send(message).await?;
+        }
+
+        Ok(reader)
+    }
+
+    async fn send(&self, message: Vec<u8>) -> io::Result<oneshot::Receiver<Vec<u8>>> {
+        if message.len() + 6 > 65535 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Message too big"));
+        }
+
+        if self.closed.load(Ordering::Relaxed) {
+            return Err(Error::new(ErrorKind::NotConnected, "Closed"));
+        }
+
+        let mut writer = self.writer.lock().await;
+        let socket = match writer.as_mut() {
+            Some(socket) => socket,
+            None => return Err(Error::new(ErrorKind::NotConnected, "Reconnecting")),
+        };
+
+        let msg_id = self.next_msg_id();
+        let (tx, rx) = oneshot::channel();
+        lock(&self.pending).insert(msg_id, tx);
+
+        let id = self.id.load(Ordering::Relaxed);
+        if let Err(err) = socket.write_all(&stamp_header(message, id, msg_id)).await {
+            lock(&self.pending).remove(&msg_id);
+
+            // The reader notices and reconnects.
+            socket.shutdown().await.ok();
+            return Err(err);
+        }
+
+        Ok(rx)
+    }
+
+    /// Message ids wrap, skipping 0 that belongs to the id message.
+    fn next_msg_id(&self) -> u32 {
+        let mut msg_id = lock(&self.msg_id);
+        *msg_id = msg_id.wrapping_add(1).max(1);
+        *msg_id as u32
+    }
+
+    async fn read_loop(self: Arc<Self>, mut reader: OwnedReadHalf) {
+        let mut backoff = MIN_BACKOFF;
+
+        loop {
+            let received = match timeout(HEARTBEAT_TIMEOUT, read_message(&mut reader)).await {
+                Ok(received) => received,
+                Err(_) => Err(Error::new(ErrorKind::TimedOut, "No heartbeat")),
+            };
+
+            match received {
+                Ok(message) => self.dispatch(message),
+
+                Err(err) => {
+                    self.disconnect().await;
+
+                    if self.closed.load(Ordering::Relaxed) {
+                        return;
+                    }
+
+                    warn!("Disconnected from {}: {err}", self.address);
+
+                    loop {
+                        sleep(backoff).await;
+
+                        if self.closed.load(Ordering::Relaxed) {
+                            return;
+                        }
+
+                        match self.handshake().await {
+                            Ok(socket) => {
+                                info!("Reconnected to {}", self.address);
+                                reader = socket;
+                                backoff = MIN_BACKOFF;
+                                break;
+                            }
+
+                            Err(_) => backoff = (backoff * 2).min(MAX_BACKOFF),
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn dispatch(&self, message: Message) {
+        // Pings, the server checking that the connection is alive.
+        if message.from == 0 && message.data.is_empty() {
+            return;
+        }
+
+        if message.from == self.id.load(Ordering::Relaxed) {
+            if let Some(tx) = lock(&self.pending).remove(&message.id) {
+                tx.send(message.data).ok();
+                return;
+            }
+        }
+
+        if let Some(handler) = lock(&self.handler).as_mut() {
+            handler(message);
+        }
+    }
+
+    /// Fails the pending requests, dropping their senders.
+    async fn disconnect(&self) {
+        if let Some(mut writer) = self.writer.lock().await.take() {
+            writer.shutdown().await.ok();
+        }
+
+        self.id.store(0, Ordering::Relaxed);
+        lock(&self.pending).clear();
+    }
+}
+
+async fn read_message(reader: &mut OwnedReadHalf) -> io::Result<Message> {
+    let mut header = [0; 6];
+    reader.read_exact(&mut header).await?;
+
+    let size = get_u32(&header[4..6]) as usize;
+    if size < 6 {
+        return Err(Error::new(ErrorKind::InvalidData, "Wrong size"));
+    }
+
+    let mut data = header.to_vec();
+    data.resize(size, 0);
+    reader.read_exact(&mut data[6..]).await?;
+
+    Message::from_protocol(data)
+}
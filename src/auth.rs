@@ -0,0 +1,141 @@
+use std::{collections::HashMap, fs, io};
+
+use serde_json::Value;
+
+use crate::{acl::Rules, namespace};
+
+/// Failed attempts allowed before the connection is dropped.
+pub const MAX_FAILURES: usize = 3;
+
+/// Credentials loaded from a JSON config file:
+///
+/// ```json
+/// {
+///     "secret": "A shared secret for everyone",
+///     "users": {
+///         "alvivar": { "password": "A password only for alvivar" }
+///     }
+/// }
+/// ```
+///
+/// Both are optional, clients authenticate with `a <secret>` or
+/// `a <user> <password>`. The secret and each user can also have access
+/// "rules", check out `acl::Rules`, without rules everything is allowed. And a
+/// "namespace" to isolate their keys, without it they can choose one.
+pub struct Auth {
+    secret: Option<String>,
+    secret_rules: Option<Rules>,
+    secret_namespace: Option<String>,
+    users: HashMap<String, User>,
+}
+
+pub struct User {
+    password: String,
+    rules: Option<Rules>,
+    namespace: Option<String>,
+}
+
+/// Who is behind an authenticated connection. No user means the shared secret.
+pub struct Session {
+    pub user: Option<String>,
+    pub rules: Option<Rules>,
+    pub namespace: Option<String>,
+}
+
+impl Auth {
+    pub fn from_file(path: &str) -> io::Result<Auth> {
+        let content = fs::read_to_string(path)?;
+        let json: Value = serde_json::from_str(&content)?;
+
+        let secret = json["secret"].as_str().map(|x| x.to_owned());
+        let secret_rules = rules(&json["rules"]);
+        let secret_namespace = namespace(&json["namespace"])?;
+
+        let mut users = HashMap::<String, User>::new();
+        if let Some(object) = json["users"].as_object() {
+            for (name, user) in object {
+                let password = match user["password"].as_str() {
+                    Some(password) => password.to_owned(),
+                    None => return Err(invalid(&format!("user {name} without password"))),
+                };
+
+                let rules = rules(&user["rules"]);
+                let namespace = namespace(&user["namespace"])?;
+
+                users.insert(
+                    name.to_owned(),
+                    User {
+                        password,
+                        rules,
+                        namespace,
+                    },
+                );
+            }
+        }
+
+        if secret.is_none() && users.is_empty() {
+            return Err(invalid("no secret and no users"));
+        }
+
+        Ok(Auth {
+            secret,
+            secret_rules,
+            secret_namespace,
+            users,
+        })
+    }
+
+    /// `a <secret>` when the password is empty, or `a <user> <password>`.
+    pub fn login(&self, id: usize, user: &str, password: &[u8]) -> Option<Session> {
+        if password.is_empty() {
+            return match &self.secret {
+                Some(secret) if same(secret.as_bytes(), user.as_bytes()) => Some(Session {
+                    user: None,
+                    rules: self.secret_rules.as_ref().map(|x| x.resolve(id, "")),
+                    namespace: self.secret_namespace.to_owned(),
+                }),
+
+                _ => None,
+            };
+        }
+
+        match self.users.get(user) {
+            Some(found) if same(found.password.as_bytes(), password) => Some(Session {
+                user: Some(user.to_owned()),
+                rules: found.rules.as_ref().map(|x| x.resolve(id, user)),
+                namespace: found.namespace.to_owned(),
+            }),
+
+            _ => None,
+        }
+    }
+}
+
+fn rules(json: &Value) -> Option<Rules> {
+    match json {
+        Value::Null => None,
+        json => Some(Rules::from_json(json)),
+    }
+}
+
+fn namespace(json: &Value) -> io::Result<Option<String>> {
+    match json.as_str() {
+        Some(name) if namespace::is_valid_name(name) => Ok(Some(name.to_owned())),
+        Some(name) => Err(invalid(&format!("invalid namespace {name}"))),
+        None => Ok(None),
+    }
+}
+
+/// Compares without returning early, so the time doesn't leak how much of the
+/// password was right.
+fn same(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Auth config: {reason}"))
+}
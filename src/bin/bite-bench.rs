@@ -0,0 +1,477 @@
+use std::{
+    collections::HashMap,
+    env,
+    io::{self, ErrorKind, Read, Write},
+    net::TcpStream,
+    process, thread,
+    time::{Duration, Instant},
+};
+
+use bite::message::{get_u32, stamp_header, Message};
+
+const USAGE: &str = "Usage: bite-bench [options]
+
+    --address <address>    The server, 127.0.0.1:1984 by default
+    --clients <n>          Simulated clients, 100 by default
+    --duration <seconds>   Time running the workload, 10 by default
+    --workload <name>      set-get, fanout or json, set-get by default
+    --rate <hz>            Commands per second of each client, 0 is as fast as
+                           possible, 60 for fanout and 0 for the rest by default
+    --reads <ratio>        Gets on set-get, from 0 to 1, 0.5 by default
+    --size <bytes>         Size of the values, 16 by default
+    --keys <n>             Keys on set-get and json, 1000 by default
+    --publishers <n>       Clients calling ! on fanout, all by default";
+
+/// Time to wait for a reply before counting the client as failed.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, PartialEq)]
+enum Workload {
+    SetGet,
+    Fanout,
+    Json,
+}
+
+#[derive(Clone)]
+struct Config {
+    address: String,
+    clients: usize,
+    duration: Duration,
+    workload: Workload,
+    rate: u32,
+    reads: f64,
+    size: usize,
+    keys: usize,
+    publishers: usize,
+}
+
+fn main() {
+    let config = match parse_args(env::args().skip(1).collect()) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{err}\n\n{USAGE}");
+            process::exit(2);
+        }
+    };
+
+    if config.workload == Workload::Json {
+        if let Err(err) = seed(&config) {
+            eprintln!("Seeding {}: {err}", config.address);
+            process::exit(1);
+        }
+    }
+
+    let start = Instant::now();
+    let deadline = start + config.duration;
+
+    let mut handles = Vec::new();
+    for i in 0..config.clients {
+        let config = config.clone();
+        let handle = thread::Builder::new()
+            .stack_size(256 * 1024)
+            .spawn(move || simulate(i, &config, start, deadline))
+            .unwrap();
+
+        handles.push(handle);
+    }
+
+    let mut stats = Stats::default();
+    let mut failed = 0;
+
+    for handle in handles {
+        match handle.join().unwrap() {
+            Ok(client) => stats.merge(client),
+            Err(err) => {
+                failed += 1;
+
+                if failed == 1 {
+                    eprintln!("Client failed: {err}");
+                }
+            }
+        }
+    }
+
+    stats.report(&config, start.elapsed(), failed);
+}
+
+fn parse_args(args: Vec<String>) -> Result<Config, String> {
+    let mut config = Config {
+        address: "127.0.0.1:1984".into(),
+        clients: 100,
+        duration: Duration::from_secs(10),
+        workload: Workload::SetGet,
+        rate: 0,
+        reads: 0.5,
+        size: 16,
+        keys: 1000,
+        publishers: 0,
+    };
+
+    let mut rate = None;
+
+    if args.iter().any(|arg| arg == "--help") {
+        return Err("BITE load generator".into());
+    }
+
+    for pair in args.chunks(2) {
+        let (name, value) = match pair {
+            [name, value] => (name.as_str(), value.as_str()),
+            [name] => return Err(format!("Missing the value of {name}")),
+            _ => unreachable!(),
+        };
+
+        let number = || value.parse::<usize>().map_err(|_| format!("Wrong {name}"));
+
+        match name {
+            "--address" => config.address = value.into(),
+            "--clients" => config.clients = number()?.max(1),
+            "--duration" => config.duration = Duration::from_secs(number()? as u64),
+            "--rate" => rate = Some(number()? as u32),
+            "--size" => config.size = number()?,
+            "--keys" => config.keys = number()?.max(1),
+            "--publishers" => config.publishers = number()?,
+
+            "--reads" => {
+                config.reads = value.parse().map_err(|_| "Wrong --reads")?;
+            }
+
+            "--workload" => {
+                config.workload = match value {
+                    "set-get" => Workload::SetGet,
+                    "fanout" => Workload::Fanout,
+                    "json" => Workload::Json,
+                    _ => return Err(format!("Unknown workload {value}")),
+                }
+            }
+
+            _ => return Err(format!("Unknown option {name}")),
+        }
+    }
+
+    config.rate = match (rate, config.workload) {
+        (Some(rate), _) => rate,
+        (None, Workload::Fanout) => 60,
+        (None, _) => 0,
+    };
+
+    if config.publishers == 0 || config.publishers > config.clients {
+        config.publishers = config.clients;
+    }
+
+    Ok(config)
+}
+
+/// The keys for the json workload, before the clients start.
+fn seed(config: &Config) -> io::Result<()> {
+    let mut conn = Conn::connect(&config.address, Instant::now())?;
+    let mut stats = Stats::default();
+    let value = "x".repeat(config.size);
+
+    for key in 0..config.keys {
+        let command = format!("s bench.json.{key} {value}");
+        conn.request(command.into_bytes(), &mut stats)?;
+    }
+
+    Ok(())
+}
+
+/// One client running the workload until the deadline.
+fn simulate(i: usize, config: &Config, start: Instant, deadline: Instant) -> io::Result<Stats> {
+    let mut conn = Conn::connect(&config.address, start)?;
+    let mut stats = Stats::default();
+    let mut random = Random::new(i as u64 + 1);
+    let value = "x".repeat(config.size);
+
+    if config.workload == Workload::Fanout {
+        conn.request(b"#g bench.room".to_vec(), &mut stats)?;
+
+        // Everyone subscribes before the first call.
+        conn.idle(Instant::now() + Duration::from_millis(500), &mut stats)?;
+
+        if i >= config.publishers {
+            conn.idle(deadline, &mut stats)?;
+            return Ok(stats);
+        }
+    }
+
+    let interval = match config.rate {
+        0 => None,
+        rate => Some(Duration::from_secs(1) / rate),
+    };
+
+    // Clients start spread over the first interval.
+    let mut next = Instant::now();
+    if let Some(interval) = interval {
+        next += interval.mul_f64(random.next_f64());
+    }
+
+    while Instant::now() < deadline {
+        if let Some(interval) = interval {
+            conn.idle(next.min(deadline), &mut stats)?;
+            next += interval;
+
+            if Instant::now() >= deadline {
+                break;
+            }
+        }
+
+        let command = match config.workload {
+            Workload::SetGet => {
+                let key = random.next() as usize % config.keys;
+
+                match random.next_f64() < config.reads {
+                    true => format!("g bench.{key}"),
+                    false => format!("s bench.{key} {value}"),
+                }
+            }
+
+            Workload::Fanout => {
+                let sent = start.elapsed().as_nanos();
+                format!("! bench.room t{sent}")
+            }
+
+            Workload::Json => "j bench.json".into(),
+        };
+
+        conn.request(command.into_bytes(), &mut stats)?;
+    }
+
+    Ok(stats)
+}
+
+/// A connection speaking the BITE framing.
+struct Conn {
+    socket: TcpStream,
+    start: Instant,
+    id: u32,
+    msg_id: u16,
+    buffer: Vec<u8>,
+}
+
+impl Conn {
+    /// The start is the clock of the ! calls on fanout.
+    fn connect(address: &str, start: Instant) -> io::Result<Conn> {
+        let socket = TcpStream::connect(address)?;
+        socket.set_nodelay(true)?;
+
+        let mut conn = Conn {
+            socket,
+            start,
+            id: 0,
+            msg_id: 0,
+            buffer: Vec::new(),
+        };
+
+        match conn.read(Instant::now() + REPLY_TIMEOUT)? {
+            Some(message) => conn.id = message.from,
+            None => return Err(io::Error::new(ErrorKind::TimedOut, "No id")),
+        }
+
+        Ok(conn)
+    }
+
+    /// Sends the command and waits for its reply, recording the latency with
+    /// the command name.
+    fn request(&mut self, command: Vec<u8>, stats: &mut Stats) -> io::Result<Vec<u8>> {
+        let name = command.split(|byte| *byte == b' ').next().unwrap();
+        let name = String::from_utf8_lossy(name).into_owned();
+
+        // Message ids wrap, skipping 0 that belongs to the id message.
+        self.msg_id = self.msg_id.wrapping_add(1).max(1);
+        let msg_id = self.msg_id as u32;
+
+        let sent = Instant::now();
+        self.socket
+            .write_all(&stamp_header(command, self.id, msg_id))?;
+
+        let timeout = sent + REPLY_TIMEOUT;
+        loop {
+            let message = match self.read(timeout)? {
+                Some(message) => message,
+                None => return Err(io::Error::new(ErrorKind::TimedOut, "No reply")),
+            };
+
+            if message.from == self.id && message.id == msg_id {
+                stats.record(&name, sent.elapsed(), message.data.len());
+                return Ok(message.data);
+            }
+
+            stats.event(&message, self.start);
+        }
+    }
+
+    /// Reads the subscription messages until the time.
+    fn idle(&mut self, until: Instant, stats: &mut Stats) -> io::Result<()> {
+        while let Some(message) = self.read(until)? {
+            stats.event(&message, self.start);
+        }
+
+        Ok(())
+    }
+
+    /// The next message, skipping pings, or none when the time is over.
+    fn read(&mut self, until: Instant) -> io::Result<Option<Message>> {
+        let mut chunk = [0; 4096];
+
+        loop {
+            if self.buffer.len() >= 6 {
+                let size = get_u32(&self.buffer[4..6]) as usize;
+
+                if size < 6 {
+                    return Err(io::Error::new(ErrorKind::InvalidData, "Wrong size"));
+                }
+
+                if self.buffer.len() >= size {
+                    let rest = self.buffer.split_off(size);
+                    let frame = std::mem::replace(&mut self.buffer, rest);
+                    let message = Message::from_protocol(frame)?;
+
+                    if message.from == 0 && message.data.is_empty() {
+                        continue;
+                    }
+
+                    return Ok(Some(message));
+                }
+            }
+
+            let now = Instant::now();
+            if now >= until {
+                return Ok(None);
+            }
+
+            self.socket.set_read_timeout(Some(until - now))?;
+
+            match self.socket.read(&mut chunk) {
+                Ok(0) => return Err(io::Error::new(ErrorKind::UnexpectedEof, "Disconnected")),
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Ok(None)
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+/// Latencies in microseconds and bytes received, by command.
+#[derive(Default)]
+struct Stats {
+    latencies: HashMap<String, Vec<u64>>,
+    bytes: HashMap<String, usize>,
+}
+
+impl Stats {
+    fn record(&mut self, name: &str, latency: Duration, bytes: usize) {
+        let latencies = self.latencies.entry(name.to_owned()).or_default();
+        latencies.push(latency.as_micros() as u64);
+
+        *self.bytes.entry(name.to_owned()).or_default() += bytes;
+    }
+
+    /// Subscription messages from the ! calls, with the time since the call.
+    fn event(&mut self, message: &Message, start: Instant) {
+        let sent = message
+            .data
+            .strip_prefix(b"t")
+            .and_then(|nanos| String::from_utf8_lossy(nanos).parse::<u64>().ok());
+
+        let latency = match sent {
+            Some(nanos) => start.elapsed().saturating_sub(Duration::from_nanos(nanos)),
+            None => return,
+        };
+
+        self.record("delivery", latency, message.data.len());
+    }
+
+    fn merge(&mut self, other: Stats) {
+        for (name, mut latencies) in other.latencies {
+            self.latencies
+                .entry(name)
+                .or_default()
+                .append(&mut latencies);
+        }
+
+        for (name, bytes) in other.bytes {
+            *self.bytes.entry(name).or_default() += bytes;
+        }
+    }
+
+    fn report(mut self, config: &Config, elapsed: Duration, failed: usize) {
+        let workload = match config.workload {
+            Workload::SetGet => "set-get",
+            Workload::Fanout => "fanout",
+            Workload::Json => "json",
+        };
+
+        println!(
+            "{workload} on {} with {} clients for {:.1}s, {failed} failed",
+            config.address,
+            config.clients,
+            elapsed.as_secs_f64()
+        );
+
+        println!(
+            "\n{:<10} {:>10} {:>12} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10}",
+            "command", "count", "ops/s", "MB/s", "p50", "p90", "p99", "p99.9", "max"
+        );
+
+        let mut names: Vec<String> = self.latencies.keys().cloned().collect();
+        names.sort();
+
+        let seconds = elapsed.as_secs_f64();
+
+        for name in names {
+            let latencies = self.latencies.get_mut(&name).unwrap();
+            latencies.sort_unstable();
+
+            let count = latencies.len();
+            let megabytes = self.bytes[&name] as f64 / 1_000_000.0;
+
+            println!(
+                "{:<10} {:>10} {:>12.1} {:>10.2} {:>10} {:>10} {:>10} {:>10} {:>10}",
+                name,
+                count,
+                count as f64 / seconds,
+                megabytes / seconds,
+                micros(percentile(latencies, 50.0)),
+                micros(percentile(latencies, 90.0)),
+                micros(percentile(latencies, 99.0)),
+                micros(percentile(latencies, 99.9)),
+                micros(*latencies.last().unwrap()),
+            );
+        }
+    }
+}
+
+/// From the sorted latencies.
+fn percentile(latencies: &[u64], percent: f64) -> u64 {
+    let rank = (percent / 100.0 * latencies.len() as f64).ceil() as usize;
+    latencies[rank.clamp(1, latencies.len()) - 1]
+}
+
+fn micros(micros: u64) -> String {
+    match micros {
+        0..1000 => format!("{micros}us"),
+        _ => format!("{:.2}ms", micros as f64 / 1000.0),
+    }
+}
+
+/// Xorshift, enough to spread the keys without a dependency.
+struct Random(u64);
+
+impl Random {
+    fn new(seed: u64) -> Random {
+        Random(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -0,0 +1,180 @@
+use std::{
+    env,
+    io::{self, BufRead, IsTerminal},
+    path::PathBuf,
+    time::Duration,
+};
+
+use bite::{client::Client, message::get_values};
+use rustyline::{error::ReadlineError, DefaultEditor};
+
+/// Time to wait for a reply before reading the next command.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const HELP: &str = "Type the commands like s key value, or q! to quit.";
+
+fn main() -> io::Result<()> {
+    let address = match env::args().nth(1) {
+        Some(address) => address,
+        None => env::var("SERVER").unwrap_or("127.0.0.1:1984".into()),
+    };
+
+    let mut client = Client::connect(&address)?;
+    client.set_timeout(REPLY_TIMEOUT);
+
+    if io::stdin().is_terminal() {
+        println!("Connected to {address} as {}", client.id());
+        println!("{HELP}");
+    }
+
+    // Subscriptions, with the client that caused them.
+    client.on_message(|message| {
+        println!(
+            "[{}] #{} {}",
+            message.id,
+            message.from,
+            String::from_utf8_lossy(&message.data)
+        )
+    });
+
+    let mut cli = Cli { client, count: 0 };
+
+    match io::stdin().is_terminal() {
+        true => cli.repl(),
+        false => cli.script(),
+    }
+}
+
+struct Cli {
+    client: Client,
+    count: usize,
+}
+
+impl Cli {
+    /// Commands from the terminal, with history.
+    fn repl(&mut self) -> io::Result<()> {
+        let mut editor = DefaultEditor::new().map_err(io::Error::other)?;
+        let history = history_file();
+
+        if let Some(path) = &history {
+            editor.load_history(path).ok();
+        }
+
+        loop {
+            match editor.readline("> ") {
+                Ok(line) => {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+
+                    // Never save credentials.
+                    if !is_auth(&line) {
+                        editor.add_history_entry(&line).ok();
+                    }
+
+                    if !self.send(&line) {
+                        break;
+                    }
+                }
+
+                Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+                Err(err) => return Err(io::Error::other(err)),
+            }
+        }
+
+        if let Some(path) = &history {
+            editor.save_history(path).ok();
+        }
+
+        Ok(())
+    }
+
+    /// Commands from stdin, one per line, waiting for each reply.
+    fn script(&mut self) -> io::Result<()> {
+        for line in io::stdin().lock().lines() {
+            let line = line?;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            if !self.send(&line) {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends the command and prints its reply, numbered. False to quit.
+    fn send(&mut self, line: &str) -> bool {
+        let line = line.trim_start();
+
+        if line.trim_end() == "q!" {
+            return false;
+        }
+
+        self.count += 1;
+
+        // The rest of the line goes as it is, the client only adds the space.
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+
+        match self.client.request(command, rest, b"") {
+            Ok(reply) => println!(
+                "[{}] {}",
+                self.count,
+                pretty(&command.to_lowercase(), &reply)
+            ),
+            Err(err) => eprintln!("[{}] {err}", self.count),
+        }
+
+        true
+    }
+}
+
+/// The reply formatted for its command.
+fn pretty(command: &str, data: &[u8]) -> String {
+    match command {
+        "k" if !data.is_empty() => data
+            .split(|byte| *byte == b'\0')
+            .map(|pair| String::from_utf8_lossy(pair).replacen(' ', ": ", 1))
+            .collect::<Vec<String>>()
+            .join("\n"),
+
+        "gk" | "gc" => String::from_utf8_lossy(data)
+            .trim_end_matches('\0')
+            .replace('\0', "\n"),
+
+        "gl" => match get_values(data) {
+            Some(values) => values
+                .iter()
+                .map(|value| match value {
+                    Some(value) => String::from_utf8_lossy(value).into(),
+                    None => "(none)".into(),
+                })
+                .collect::<Vec<String>>()
+                .join("\n"),
+
+            None => String::from_utf8_lossy(data).into(),
+        },
+
+        "j" | "js" => match serde_json::from_slice::<serde_json::Value>(data) {
+            Ok(json) => serde_json::to_string_pretty(&json).unwrap(),
+            Err(_) => String::from_utf8_lossy(data).into(),
+        },
+
+        "+1" if data.len() == 8 => u64::from_be_bytes(data.try_into().unwrap()).to_string(),
+
+        _ => String::from_utf8_lossy(data).into(),
+    }
+}
+
+fn is_auth(line: &str) -> bool {
+    let command = line.split_whitespace().next().unwrap_or_default();
+    command.eq_ignore_ascii_case("a")
+}
+
+fn history_file() -> Option<PathBuf> {
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))?;
+    Some(PathBuf::from(home).join(".bite_history"))
+}
@@ -8,13 +8,16 @@ use std::{
 
 use crate::{
     connection::Connection,
+    parser,
     subs::{self, Action::DelAll},
+    supervisor::{lock, Error},
 };
 
 use polling::Poller;
 
 pub enum Action {
     Drop(usize),
+    Stop,
 }
 
 pub struct Cleaner {
@@ -45,22 +48,31 @@ impl Cleaner {
         }
     }
 
-    pub fn handle(&self, subs_tx: Sender<subs::Action>) {
+    pub fn handle(
+        &self,
+        subs_tx: Sender<subs::Action>,
+        parser_tx: Sender<parser::Action>,
+    ) -> Result<(), Error> {
         loop {
-            match self.rx.recv().unwrap() {
+            match self.rx.recv()? {
                 Action::Drop(id) => {
-                    let reader = self.readers.lock().unwrap().remove(&id);
+                    let reader = lock(&self.readers).remove(&id);
                     if let Some(reader) = reader {
-                        self.poller.delete(&reader.socket).unwrap();
-                        self.used_ids.lock().unwrap().push_back(id);
-                        subs_tx.send(DelAll(id)).unwrap();
+                        // Sockets closed by the other side could be gone already.
+                        let _ = self.poller.delete(&reader.socket);
+                        subs_tx.send(DelAll(id))?;
+                        parser_tx.send(parser::Action::Drop(id))?;
+                        lock(&self.used_ids).push_back(id);
                     }
 
-                    let writer = self.writers.lock().unwrap().remove(&id);
+                    let writer = lock(&self.writers).remove(&id);
                     if let Some(writer) = writer {
-                        self.poller.delete(&writer.socket).unwrap();
+                        let _ = self.poller.delete(&writer.socket);
                     }
                 }
+
+                // The server stopped.
+                Action::Stop => return Ok(()),
             }
         }
     }
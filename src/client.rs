@@ -0,0 +1,671 @@
+use std::{
+    collections::HashMap,
+    io::{self, Error, ErrorKind, Read, Write},
+    net::{Shutdown, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+pub use crate::message::Message;
+use crate::{
+    message::{get_u32, get_values, stamp_header},
+    supervisor::lock,
+};
+
+/// Time without receiving anything, not even a ping, to consider the
+/// connection dead. Every 2 heartbeats, 60 seconds by default, the server pings
+/// the connections without writes for longer than that, so an idle connection
+/// can wait up to 4 heartbeats for its ping.
+pub const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(150);
+
+/// Time to wait for a reply by default.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+pub(crate) type Handler = Box<dyn FnMut(Message) + Send>;
+
+/// The subscriptions, what they receive when the key or its children change.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Sub {
+    /// The value, like **#g**.
+    Get,
+    /// The last segment of the key and the value, like **#k**.
+    KeyValue,
+    /// The last segment of the key and the value as JSON, like **#j**.
+    Json,
+    /// The full key and the value, like **#f**.
+    FullKey,
+}
+
+impl Sub {
+    pub(crate) fn command(&self) -> &'static str {
+        match self {
+            Sub::Get => "#g",
+            Sub::KeyValue => "#k",
+            Sub::Json => "#j",
+            Sub::FullKey => "#f",
+        }
+    }
+}
+
+/// What the client replays after a reconnection.
+#[derive(Default)]
+pub(crate) struct Session {
+    pub auth: Option<Vec<u8>>,
+    pub namespace: Option<String>,
+    pub subs: Vec<(Sub, String)>,
+}
+
+impl Session {
+    /// The commands that restore the session, in order.
+    pub fn replay(&self) -> Vec<Vec<u8>> {
+        let mut commands = Vec::new();
+
+        if let Some(auth) = &self.auth {
+            commands.push(auth.to_owned());
+        }
+
+        if let Some(namespace) = &self.namespace {
+            commands.push(command("n", namespace, b""));
+        }
+
+        for (sub, key) in &self.subs {
+            commands.push(command(sub.command(), key, b""));
+        }
+
+        commands
+    }
+
+    pub fn subscribe(&mut self, sub: Sub, key: &str) {
+        if !self.subs.iter().any(|(s, k)| *s == sub && k == key) {
+            self.subs.push((sub, key.to_owned()));
+        }
+    }
+
+    pub fn unsubscribe(&mut self, key: &str) {
+        self.subs.retain(|(_, k)| k != key);
+    }
+}
+
+/// A blocking client for the BITE protocol.
+///
+/// Replies are matched to their requests by message id, so the client can be
+/// shared between threads. Subscription updates and **!** calls go to the
+/// handler from [`Client::on_message`]. When the connection is lost, the
+/// pending requests fail, and the client reconnects in the background,
+/// authenticating, moving to the namespace and subscribing again.
+pub struct Client {
+    shared: Arc<Shared>,
+    timeout: Duration,
+}
+
+struct Shared {
+    address: String,
+    id: AtomicU32,
+    msg_id: Mutex<u16>,
+    writer: Mutex<Option<TcpStream>>,
+    pending: Mutex<HashMap<u32, Sender<Vec<u8>>>>,
+    session: Mutex<Session>,
+    handler: Mutex<Option<Handler>>,
+    closed: AtomicBool,
+}
+
+impl Client {
+    /// Connects and waits for the id from the server.
+    pub fn connect(address: &str) -> io::Result<Client> {
+        let shared = Arc::new(Shared {
+            address: address.to_owned(),
+            id: AtomicU32::new(0),
+            msg_id: Mutex::new(0),
+            writer: Mutex::new(None),
+            pending: Mutex::new(HashMap::new()),
+            session: Mutex::new(Session::default()),
+            handler: Mutex::new(None),
+            closed: AtomicBool::new(false),
+        });
+
+        let reader = shared.handshake()?;
+
+        let reading = shared.clone();
+        thread::spawn(move || reading.read_loop(reader));
+
+        Ok(Client {
+            shared,
+            timeout: REQUEST_TIMEOUT,
+        })
+    }
+
+    /// Time to wait for each reply.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// The id from the server, 0 while disconnected.
+    pub fn id(&self) -> u16 {
+        self.shared.id.load(Ordering::Relaxed) as u16
+    }
+
+    /// Receives the subscription updates and **!** calls, on the thread that
+    /// reads from the server, so the handler shouldn't wait for replies.
+    pub fn on_message<F: FnMut(Message) + Send + 'static>(&self, handler: F) {
+        *lock(&self.shared.handler) = Some(Box::new(handler));
+    }
+
+    /// Sends the command with the key and the data, and waits for the reply.
+    pub fn request(&self, command: &str, key: &str, data: &[u8]) -> io::Result<Vec<u8>> {
+        self.send(self::command(command, key, data))
+    }
+
+    /// Like a request, with the value after its size so it arrives exactly as
+    /// it is.
+    fn request_value(&self, command: &str, key: &str, value: &[u8]) -> io::Result<Vec<u8>> {
+        self.send(sized_command(command, key, value))
+    }
+
+    fn send(&self, message: Vec<u8>) -> io::Result<Vec<u8>> {
+        let rx = self.shared.send(message)?;
+
+        match rx.recv_timeout(self.timeout) {
+            Ok(reply) => Ok(reply),
+            Err(RecvTimeoutError::Timeout) => Err(Error::new(ErrorKind::TimedOut, "No reply")),
+            Err(RecvTimeoutError::Disconnected) => Err(disconnected()),
+        }
+    }
+
+    /// Authenticates with the shared secret, like **a**.
+    pub fn auth(&self, secret: &str) -> io::Result<()> {
+        self.authenticate(command("a", secret, b""))
+    }
+
+    /// Authenticates with a user and a password, like **a**.
+    pub fn login(&self, user: &str, password: &str) -> io::Result<()> {
+        self.authenticate(command("a", user, password.as_bytes()))
+    }
+
+    fn authenticate(&self, message: Vec<u8>) -> io::Result<()> {
+        let rx = self.shared.send(message.to_owned())?;
+        let reply = rx.recv_timeout(self.timeout).map_err(|_| disconnected())?;
+
+        match reply.as_slice() {
+            b"OK" => {
+                lock(&self.shared.session).auth = Some(message);
+                Ok(())
+            }
+
+            _ => Err(Error::new(ErrorKind::PermissionDenied, "Wrong credentials")),
+        }
+    }
+
+    /// Moves to the namespace, or to the default one with an empty name, like
+    /// **n**.
+    pub fn namespace(&self, name: &str) -> io::Result<()> {
+        ok(&self.request("n", name, b"")?)?;
+
+        let mut session = lock(&self.shared.session);
+        session.namespace = match name.is_empty() {
+            true => None,
+            false => Some(name.to_owned()),
+        };
+
+        Ok(())
+    }
+
+    /// A token to bind a UDP socket, like **u**. It's only valid for this
+    /// connection, so it's needed again after a reconnection.
+    pub fn udp_token(&self) -> io::Result<String> {
+        let reply = data(self.request("u", "", b"")?)?;
+        Ok(String::from_utf8_lossy(&reply).into())
+    }
+
+    pub fn set(&self, key: &str, value: &[u8]) -> io::Result<()> {
+        ok(&self.request_value("s", key, value)?)
+    }
+
+    /// Sets the value only if the key doesn't exist, like **s?**.
+    pub fn set_if_none(&self, key: &str, value: &[u8]) -> io::Result<()> {
+        ok(&self.request_value("s?", key, value)?)
+    }
+
+    /// Sets all the keys and values in one operation, like **sl**.
+    pub fn set_list(&self, list: &[(&str, &[u8])]) -> io::Result<()> {
+        let (separator, data) = set_list(list)?;
+        ok(&self.request("sl", &separator, &data)?)
+    }
+
+    /// The fields of the JSON as children of the key, merged with the ones it
+    /// already has, like **sj**.
+    pub fn set_json(&self, key: &str, json: &str) -> io::Result<()> {
+        ok(&self.request("sj", key, json.as_bytes())?)
+    }
+
+    /// The fields of the JSON as the only children of the key, like **sj!**.
+    pub fn replace_json(&self, key: &str, json: &str) -> io::Result<()> {
+        ok(&self.request("sj!", key, json.as_bytes())?)
+    }
+
+    /// Changes the JSON of the key with a merge patch, like **jm**.
+    pub fn merge_patch(&self, key: &str, patch: &str) -> io::Result<()> {
+        ok(&self.request("jm", key, patch.as_bytes())?)
+    }
+
+    /// Changes the JSON of the key with a JSON Patch, like **jp**. Nothing
+    /// changes and it fails when an operation does, like a **test**.
+    pub fn json_patch(&self, key: &str, patch: &str) -> io::Result<()> {
+        ok(&self.request("jp", key, patch.as_bytes())?)
+    }
+
+    /// Increases the value by 1, like **+1**. The value becomes 0 if it isn't
+    /// a number.
+    pub fn inc(&self, key: &str) -> io::Result<u64> {
+        number(&data(self.request("+1", key, b"")?)?)
+    }
+
+    /// Appends to the value, like **+**.
+    pub fn append(&self, key: &str, value: &[u8]) -> io::Result<()> {
+        ok(&self.request_value("+", key, value)?)
+    }
+
+    pub fn delete(&self, key: &str) -> io::Result<()> {
+        ok(&self.request("d", key, b"")?)
+    }
+
+    /// The value, empty when the key doesn't exist. It comes from **gl**, so a
+    /// value like **NO** or **DENIED** is never taken for an error.
+    pub fn get(&self, key: &str) -> io::Result<Vec<u8>> {
+        let value = self.get_list(&[key])?.pop().flatten();
+        Ok(value.unwrap_or_default())
+    }
+
+    /// The values of the keys in the same order, None when the key doesn't
+    /// exist, like **gl**.
+    pub fn get_list(&self, keys: &[&str]) -> io::Result<Vec<Option<Vec<u8>>>> {
+        let (separator, list) = get_list(keys)?;
+        values(&data(self.request("gl", &separator, &list)?)?)
+    }
+
+    /// The last segment of the keys and the values of the key and its
+    /// children, like **k**. Up to a depth below the key, or all of them.
+    pub fn key_values(
+        &self,
+        key: &str,
+        depth: Option<usize>,
+    ) -> io::Result<Vec<(String, Vec<u8>)>> {
+        Ok(key_values(&data(self.request(
+            "k",
+            key,
+            &depth_arg(depth),
+        )?)?))
+    }
+
+    /// The JSON without the full path, like **j**, with the depth of
+    /// [`Client::key_values`].
+    pub fn jtrim(&self, key: &str, depth: Option<usize>) -> io::Result<String> {
+        text(data(self.request("j", key, &depth_arg(depth))?)?)
+    }
+
+    /// The JSON with the full path, like **js**, with the depth of
+    /// [`Client::key_values`].
+    pub fn json(&self, key: &str, depth: Option<usize>) -> io::Result<String> {
+        text(data(self.request("js", key, &depth_arg(depth))?)?)
+    }
+
+    /// A page of the keys with a value of the key and its children, like
+    /// **gk**. Up to the size, after a key or from the start when it's empty.
+    /// An empty page is the end.
+    pub fn keys(&self, key: &str, size: usize, after: &str) -> io::Result<Vec<String>> {
+        keys(&self.request("gk", key, &page(size, after))?)
+    }
+
+    /// A page of the direct children of the key, like **gc**, with or without
+    /// a value. It continues after a child, like [`Client::keys`].
+    pub fn children(&self, key: &str, size: usize, after: &str) -> io::Result<Vec<String>> {
+        keys(&self.request("gc", key, &page(size, after))?)
+    }
+
+    /// Subscribes to the key and its children. The updates go to the handler
+    /// from [`Client::on_message`].
+    pub fn subscribe(&self, sub: Sub, key: &str) -> io::Result<()> {
+        ok(&self.request(sub.command(), key, b"")?)?;
+        lock(&self.shared.session).subscribe(sub, key);
+
+        Ok(())
+    }
+
+    /// Removes the subscriptions to the key, like **#-**.
+    pub fn unsubscribe(&self, key: &str) -> io::Result<()> {
+        lock(&self.shared.session).unsubscribe(key);
+        ok(&self.request("#-", key, b"")?)
+    }
+
+    /// Calls the subscriptions to the key without changing the data, like
+    /// **!**.
+    pub fn call(&self, key: &str, value: &[u8]) -> io::Result<()> {
+        ok(&self.request_value("!", key, value)?)
+    }
+
+    /// Closes the connection and stops reconnecting.
+    pub fn close(&self) {
+        self.shared.closed.store(true, Ordering::Relaxed);
+
+        if let Some(writer) = lock(&self.shared.writer).take() {
+            writer.shutdown(Shutdown::Both).ok();
+        }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl Shared {
+    /// Connects, reads the id and restores the session. Returns the reader.
+    fn handshake(&self) -> io::Result<TcpStream> {
+        let mut socket = TcpStream::connect(&self.address)?;
+        socket.set_nodelay(true)?;
+        socket.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+
+        let message = read_message(&mut socket)?;
+        if message.id != 0 || message.from == 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "Expected an id"));
+        }
+
+        socket.set_read_timeout(Some(HEARTBEAT_TIMEOUT))?;
+
+        self.id.store(message.from, Ordering::Relaxed);
+        *lock(&self.writer) = Some(socket.try_clone()?);
+
+        // The replies of the replay are ignored, their receivers are dropped.
+        let replay = lock(&self.session).replay();
+        for message in replay {
+            self.send(message)?;
+        }
+
+        Ok(socket)
+    }
+
+    fn send(&self, message: Vec<u8>) -> io::Result<Receiver<Vec<u8>>> {
+        if message.len() + 6 > 65535 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Message too big"));
+        }
+
+        if self.closed.load(Ordering::Relaxed) {
+            return Err(Error::new(ErrorKind::NotConnected, "Closed"));
+        }
+
+        let mut writer = lock(&self.writer);
+        let socket = match writer.as_mut() {
+            Some(socket) => socket,
+            None => return Err(Error::new(ErrorKind::NotConnected, "Reconnecting")),
+        };
+
+        let msg_id = self.next_msg_id();
+        let (tx, rx) = channel();
+        lock(&self.pending).insert(msg_id, tx);
+
+        let id = self.id.load(Ordering::Relaxed);
+        if let Err(err) = socket.write_all(&stamp_header(message, id, msg_id)) {
+            lock(&self.pending).remove(&msg_id);
+
+            // The reader notices and reconnects.
+            socket.shutdown(Shutdown::Both).ok();
+            return Err(err);
+        }
+
+        Ok(rx)
+    }
+
+    /// Message ids wrap, skipping 0 that belongs to the id message.
+    fn next_msg_id(&self) -> u32 {
+        let mut msg_id = lock(&self.msg_id);
+        *msg_id = msg_id.wrapping_add(1).max(1);
+        *msg_id as u32
+    }
+
+    fn read_loop(self: Arc<Self>, mut reader: TcpStream) {
+        let mut backoff = MIN_BACKOFF;
+
+        loop {
+            match read_message(&mut reader) {
+                Ok(message) => self.dispatch(message),
+
+                Err(err) => {
+                    self.disconnect();
+
+                    if self.closed.load(Ordering::Relaxed) {
+                        return;
+                    }
+
+                    warn!("Disconnected from {}: {err}", self.address);
+
+                    loop {
+                        thread::sleep(backoff);
+
+                        if self.closed.load(Ordering::Relaxed) {
+                            return;
+                        }
+
+                        match self.handshake() {
+                            Ok(socket) => {
+                                info!("Reconnected to {}", self.address);
+                                reader = socket;
+                                backoff = MIN_BACKOFF;
+                                break;
+                            }
+
+                            Err(_) => backoff = (backoff * 2).min(MAX_BACKOFF),
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn dispatch(&self, message: Message) {
+        // Pings, the server checking that the connection is alive.
+        if message.from == 0 && message.data.is_empty() {
+            return;
+        }
+
+        if message.from == self.id.load(Ordering::Relaxed) {
+            if let Some(tx) = lock(&self.pending).remove(&message.id) {
+                tx.send(message.data).ok();
+                return;
+            }
+        }
+
+        if let Some(handler) = lock(&self.handler).as_mut() {
+            handler(message);
+        }
+    }
+
+    /// Fails the pending requests, dropping their senders.
+    fn disconnect(&self) {
+        if let Some(writer) = lock(&self.writer).take() {
+            writer.shutdown(Shutdown::Both).ok();
+        }
+
+        self.id.store(0, Ordering::Relaxed);
+        lock(&self.pending).clear();
+    }
+}
+
+fn read_message(socket: &mut TcpStream) -> io::Result<Message> {
+    let mut header = [0; 6];
+    socket.read_exact(&mut header)?;
+
+    let size = get_u32(&header[4..6]) as usize;
+    if size < 6 {
+        return Err(Error::new(ErrorKind::InvalidData, "Wrong size"));
+    }
+
+    let mut data = header.to_vec();
+    data.resize(size, 0);
+    socket.read_exact(&mut data[6..])?;
+
+    Message::from_protocol(data)
+}
+
+/// The command as text, the key and the data separated by spaces.
+pub(crate) fn command(command: &str, key: &str, data: &[u8]) -> Vec<u8> {
+    let mut message = command.as_bytes().to_vec();
+
+    if !key.is_empty() {
+        message.push(b' ');
+        message.extend(key.as_bytes());
+    }
+
+    if !data.is_empty() {
+        message.push(b' ');
+        message.extend(data);
+    }
+
+    message
+}
+
+/// A command with the value after its size, so the server keeps it exactly as
+/// it is: "s: key" and the value like on **gl**.
+pub(crate) fn sized_command(command: &str, key: &str, value: &[u8]) -> Vec<u8> {
+    let mut message = format!("{command}: {key} ").into_bytes();
+
+    message.extend((value.len() as u16).to_be_bytes());
+    message.extend(value);
+    message
+}
+
+/// The separator for **sl**, the first that isn't on the keys or the values.
+pub(crate) fn set_list(list: &[(&str, &[u8])]) -> io::Result<(String, Vec<u8>)> {
+    let used = |byte: &u8| {
+        list.iter()
+            .any(|(key, value)| key.as_bytes().contains(byte) || value.contains(byte))
+    };
+
+    let separator = match b"|,;:~^".iter().find(|byte| !used(byte)) {
+        Some(separator) => *separator,
+        None => return Err(Error::new(ErrorKind::InvalidInput, "No separator left")),
+    };
+
+    let mut data = Vec::new();
+    for (i, (key, value)) in list.iter().enumerate() {
+        if i > 0 {
+            data.push(separator);
+        }
+
+        data.extend(key.as_bytes());
+        data.push(b' ');
+        data.extend(*value);
+    }
+
+    Ok(((separator as char).to_string(), data))
+}
+
+/// The separator for **gl**, the first that isn't on the keys.
+pub(crate) fn get_list(keys: &[&str]) -> io::Result<(String, Vec<u8>)> {
+    let separator = match b"|,;:~^"
+        .iter()
+        .find(|byte| !keys.iter().any(|key| key.as_bytes().contains(byte)))
+    {
+        Some(separator) => *separator,
+        None => return Err(Error::new(ErrorKind::InvalidInput, "No separator left")),
+    };
+
+    let data = keys.join(&(separator as char).to_string());
+
+    Ok(((separator as char).to_string(), data.into()))
+}
+
+/// The values of **gl**.
+pub(crate) fn values(reply: &[u8]) -> io::Result<Vec<Option<Vec<u8>>>> {
+    get_values(reply).ok_or_else(|| Error::new(ErrorKind::InvalidData, "Wrong values"))
+}
+
+/// The depth of **k**, **j** and **js**, nothing is everything.
+pub(crate) fn depth_arg(depth: Option<usize>) -> Vec<u8> {
+    depth.map(|x| x.to_string()).unwrap_or_default().into()
+}
+
+/// The size and the cursor of **gk** and **gc**.
+pub(crate) fn page(size: usize, after: &str) -> Vec<u8> {
+    format!("{size} {after}").trim_end().into()
+}
+
+/// The keys of **gk** and **gc**. Each one ends with 0, anything else is an
+/// error from the server.
+pub(crate) fn keys(reply: &[u8]) -> io::Result<Vec<String>> {
+    match reply.strip_suffix(b"\0") {
+        Some(keys) => Ok(keys
+            .split(|byte| *byte == b'\0')
+            .map(|key| String::from_utf8_lossy(key).into())
+            .collect()),
+
+        None if reply.is_empty() => Ok(Vec::new()),
+
+        None => Err(error(reply)),
+    }
+}
+
+/// **OK**, or the error from the server.
+pub(crate) fn ok(reply: &[u8]) -> io::Result<()> {
+    match reply {
+        b"OK" => Ok(()),
+        reply => Err(error(reply)),
+    }
+}
+
+/// The reply as data, unless it's an error from the server. Only for replies
+/// that can't be a value, since a value could be an error too.
+pub(crate) fn data(reply: Vec<u8>) -> io::Result<Vec<u8>> {
+    match reply.as_slice() {
+        b"AUTH" | b"DENIED" | b"NO" => Err(error(&reply)),
+        _ => Ok(reply),
+    }
+}
+
+pub(crate) fn text(reply: Vec<u8>) -> io::Result<String> {
+    String::from_utf8(reply).map_err(|err| Error::new(ErrorKind::InvalidData, err))
+}
+
+pub(crate) fn number(reply: &[u8]) -> io::Result<u64> {
+    match reply.try_into() {
+        Ok(bytes) => Ok(u64::from_be_bytes(bytes)),
+        Err(_) => Err(Error::new(ErrorKind::InvalidData, "Expected 8 bytes")),
+    }
+}
+
+pub(crate) fn key_values(reply: &[u8]) -> Vec<(String, Vec<u8>)> {
+    if reply.is_empty() {
+        return Vec::new();
+    }
+
+    reply
+        .split(|byte| *byte == b'\0')
+        .map(|pair| {
+            let space = pair.iter().position(|byte| *byte == b' ');
+            let (key, value) = pair.split_at(space.unwrap_or(pair.len()));
+            let value = value.get(1..).unwrap_or_default();
+
+            (String::from_utf8_lossy(key).into(), value.to_owned())
+        })
+        .collect()
+}
+
+fn error(reply: &[u8]) -> Error {
+    match reply {
+        b"AUTH" => Error::new(ErrorKind::PermissionDenied, "Authentication required"),
+        b"DENIED" => Error::new(ErrorKind::PermissionDenied, "Denied by the rules"),
+        b"NO" => Error::new(ErrorKind::InvalidInput, "Rejected by the server"),
+        reply => Error::other(String::from_utf8_lossy(reply).into_owned()),
+    }
+}
+
+pub(crate) fn disconnected() -> Error {
+    Error::new(ErrorKind::ConnectionReset, "Disconnected")
+}
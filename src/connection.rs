@@ -1,20 +1,67 @@
 use std::{
+    collections::VecDeque,
     io::{
         self,
         ErrorKind::{BrokenPipe, Interrupted, WouldBlock},
-        Read, Write,
+        IoSlice, Read, Write,
     },
-    net::{SocketAddr, TcpStream},
     time::Instant,
 };
 
+use crate::stream::{Address, Stream};
+
 const BUFFER_SIZE: usize = 4096;
+const MAX_WRITE_SLICES: usize = 64;
+
+/// What to do when a connection send queue is full.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Overflow {
+    DropOldest,
+    DropNewest,
+    Disconnect,
+}
+
+impl Overflow {
+    pub fn from_name(name: &str) -> Option<Overflow> {
+        match name.to_lowercase().as_str() {
+            "drop-oldest" => Some(Overflow::DropOldest),
+            "drop-newest" => Some(Overflow::DropNewest),
+            "disconnect" => Some(Overflow::Disconnect),
+            _ => None,
+        }
+    }
+}
+
+/// Per connection send queue limits, in messages and bytes.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    pub max_messages: usize,
+    pub max_bytes: usize,
+    pub overflow: Overflow,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_messages: 1024,
+            max_bytes: 4 * 1024 * 1024,
+            overflow: Overflow::DropOldest,
+        }
+    }
+}
 
 pub struct Connection {
     pub id: usize,
-    pub socket: TcpStream,
-    pub addr: SocketAddr,
-    pub send_queue: Vec<Vec<u8>>,
+    pub socket: Stream,
+    pub addr: Address,
+    pub send_queue: VecDeque<Vec<u8>>,
+    pub write_cursor: usize,
+    pub send_queue_bytes: usize,
+    pub dropped_messages: usize,
+    pub dropped_bytes: usize,
+    /// The drops on the last report of the heartbeat.
+    pub reported_messages: usize,
+    pub reported_bytes: usize,
     pub pending_read: bool,
     pub last_read: Instant,
     pub last_write: Instant,
@@ -22,14 +69,20 @@ pub struct Connection {
 }
 
 impl Connection {
-    pub fn new(id: usize, socket: TcpStream, addr: SocketAddr) -> Connection {
-        let send_queue = Vec::<Vec<u8>>::new();
+    pub fn new(id: usize, socket: Stream, addr: Address) -> Connection {
+        let send_queue = VecDeque::<Vec<u8>>::new();
 
         Connection {
             id,
             socket,
             addr,
             send_queue,
+            write_cursor: 0,
+            send_queue_bytes: 0,
+            dropped_messages: 0,
+            dropped_bytes: 0,
+            reported_messages: 0,
+            reported_bytes: 0,
             pending_read: false,
             last_read: Instant::now(),
             last_write: Instant::now(),
@@ -37,6 +90,56 @@ impl Connection {
         }
     }
 
+    /// Queues the data respecting the limits. Returns false when the queue
+    /// overflowed and the policy says the connection should be dropped.
+    pub fn queue(&mut self, data: Vec<u8>, limits: &Limits) -> bool {
+        let len = data.len();
+
+        while self.send_queue.len() >= limits.max_messages
+            || self.send_queue_bytes + len > limits.max_bytes
+        {
+            match limits.overflow {
+                // The front message could be partially written already, and
+                // dropping it would corrupt the stream.
+                Overflow::DropOldest if self.send_queue.len() > self.writing() => {
+                    let oldest = self.send_queue.remove(self.writing()).unwrap();
+                    self.send_queue_bytes -= oldest.len();
+                    self.count_dropped(oldest.len());
+                }
+
+                // Nothing left to drop but the new message is still too big.
+                Overflow::DropOldest | Overflow::DropNewest => {
+                    self.count_dropped(len);
+                    return true;
+                }
+
+                Overflow::Disconnect => {
+                    self.closed = true;
+                    return false;
+                }
+            }
+        }
+
+        self.send_queue_bytes += len;
+        self.send_queue.push_back(data);
+
+        true
+    }
+
+    /// 1 when the front message is partially written, 0 otherwise.
+    fn writing(&self) -> usize {
+        (self.write_cursor > 0) as usize
+    }
+
+    fn count_dropped(&mut self, len: usize) {
+        if self.dropped_messages == 0 {
+            warn!("Connection #{} throttled, send queue is full", self.id);
+        }
+
+        self.dropped_messages += 1;
+        self.dropped_bytes += len;
+    }
+
     pub fn try_read(&mut self) -> io::Result<Vec<u8>> {
         match read(&mut self.socket) {
             Ok(data) => Ok(data),
@@ -49,9 +152,19 @@ impl Connection {
         }
     }
 
-    pub fn try_write(&mut self, data: Vec<u8>) -> io::Result<usize> {
-        match write(&mut self.socket, data) {
-            Ok(count) => Ok(count),
+    /// Writes as much of the send queue as the socket accepts, resuming
+    /// partially written messages from the write cursor.
+    pub fn try_write(&mut self) -> io::Result<usize> {
+        match write(
+            &mut self.socket,
+            &mut self.send_queue,
+            &mut self.write_cursor,
+        ) {
+            Ok(count) => {
+                self.send_queue_bytes -= count;
+
+                Ok(count)
+            }
 
             Err(err) => {
                 self.closed = true;
@@ -62,7 +175,7 @@ impl Connection {
     }
 }
 
-fn read(socket: &mut TcpStream) -> io::Result<Vec<u8>> {
+fn read(socket: &mut Stream) -> io::Result<Vec<u8>> {
     let mut buffer = Vec::with_capacity(BUFFER_SIZE);
 
     loop {
@@ -78,7 +191,9 @@ fn read(socket: &mut TcpStream) -> io::Result<Vec<u8>> {
             Ok(n) => {
                 buffer.extend_from_slice(&chunk[..n]);
 
-                if n < BUFFER_SIZE {
+                // TLS or WebSocket could have more data than what was read
+                // from the socket, so they need to read until they would block.
+                if n < BUFFER_SIZE && !socket.is_buffered() {
                     break;
                 }
             }
@@ -98,22 +213,53 @@ fn read(socket: &mut TcpStream) -> io::Result<Vec<u8>> {
     Ok(buffer)
 }
 
-fn write(socket: &mut TcpStream, data: Vec<u8>) -> io::Result<usize> {
+/// Coalesces the queued messages into vectored writes until the queue is empty
+/// or the socket would block. The cursor is the amount of bytes of the front
+/// message that were already written.
+fn write(
+    socket: &mut Stream,
+    queue: &mut VecDeque<Vec<u8>>,
+    cursor: &mut usize,
+) -> io::Result<usize> {
     let mut total_written = 0;
 
-    while total_written < data.len() {
-        match socket.write(&data[total_written..]) {
+    while !queue.is_empty() {
+        let mut slices = Vec::<IoSlice>::with_capacity(MAX_WRITE_SLICES);
+        for (i, data) in queue.iter().take(MAX_WRITE_SLICES).enumerate() {
+            let start = if i == 0 { *cursor } else { 0 };
+            slices.push(IoSlice::new(&data[start..]));
+        }
+
+        match socket.write_vectored(&slices) {
             Ok(0) => {
                 // Writing 0 bytes means the other side has closed the
                 // connection or is done writing, then so are we.
                 return Err(BrokenPipe.into());
             }
 
-            Ok(n) => total_written += n,
+            Ok(mut n) => {
+                total_written += n;
+
+                // Pops what was completely written and moves the cursor
+                // inside the message that was partially written.
+                while n > 0 {
+                    let left = queue[0].len() - *cursor;
+
+                    if n >= left {
+                        n -= left;
+                        *cursor = 0;
+                        queue.pop_front();
+                    } else {
+                        *cursor += n;
+                        n = 0;
+                    }
+                }
+            }
 
             // Would block "errors" are the OS's way of saying that the
             // connection is not actually ready to perform this I/O operation.
-            Err(ref err) if err.kind() == WouldBlock => return Err(WouldBlock.into()),
+            // What's left will be written when the socket is writable again.
+            Err(ref err) if err.kind() == WouldBlock => break,
 
             // Got interrupted, we'll try again.
             Err(ref err) if err.kind() == Interrupted => continue,
@@ -123,5 +269,8 @@ fn write(socket: &mut TcpStream, data: Vec<u8>) -> io::Result<usize> {
         }
     }
 
+    // TLS could still have encrypted bytes waiting.
+    socket.flush()?;
+
     Ok(total_written)
 }
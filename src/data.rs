@@ -1,6 +1,8 @@
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, HashSet},
     io::Cursor,
+    ops::{Bound, RangeInclusive},
+    str,
     sync::{
         atomic::{AtomicBool, Ordering},
         mpsc::{channel, Receiver, Sender},
@@ -9,12 +11,29 @@ use std::{
 };
 
 use crate::{
+    message::{stamp_values, MAX_DATA},
+    namespace,
     parser::{next_word, remaining},
-    subs::{self, Action::Call},
+    patch,
+    subs::{
+        self,
+        Action::{Call, Flush},
+    },
+    supervisor::{lock, Error},
     writer::{self, Action::Queue, Order},
 };
 
-use serde_json::{self, json, Value};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::{self, json, Map, Number, Value};
+
+const OK: &str = "OK";
+const NO: &str = "NO";
+
+/// The field for the value of a key that also has children.
+pub const VALUE: &str = "@value";
+
+/// The field for the base64 of a binary value.
+pub const BASE64: &str = "@base64";
 
 pub enum Action {
     Set(String, Vec<u8>),
@@ -24,9 +43,17 @@ pub enum Action {
     Append(String, Vec<u8>, usize, usize),
     Delete(String),
     Get(String, usize, usize),
-    KeyValue(String, usize, usize),
-    Jtrim(String, usize, usize),
-    Json(String, usize, usize),
+    GetList(String, Vec<u8>, usize, usize),
+    KeyValue(String, usize, usize, usize),
+    Jtrim(String, usize, usize, usize),
+    Json(String, usize, usize, usize),
+    SetJson(String, Vec<(String, Vec<u8>)>, bool, usize, usize),
+    MergePatch(String, Value, usize, usize),
+    JsonPatch(String, Value, usize, usize),
+    Keys(String, usize, Option<String>, usize, usize),
+    Children(String, usize, Option<String>, usize, usize),
+    Flush(Sender<()>),
+    Stop,
 }
 
 pub struct Data {
@@ -51,17 +78,17 @@ impl Data {
         }
     }
 
-    pub fn handle(&self, db_modified: Arc<AtomicBool>) {
+    pub fn handle(&self, db_modified: Arc<AtomicBool>) -> Result<(), Error> {
         loop {
-            match self.rx.recv().unwrap() {
+            match self.rx.recv()? {
                 Action::Set(key, val) => {
-                    self.map.lock().unwrap().insert(key, val);
+                    lock(&self.map).insert(key, val);
 
                     db_modified.swap(true, Ordering::Relaxed);
                 }
 
                 Action::SetIfNone(key, val, from_id, msg_id) => {
-                    let mut map = self.map.lock().unwrap();
+                    let mut map = lock(&self.map);
 
                     match map.contains_key(&key) {
                         true => continue,
@@ -70,7 +97,7 @@ impl Data {
                             map.insert(key.to_owned(), val.to_owned());
                             drop(map);
 
-                            self.subs_tx.send(Call(key, val, from_id, msg_id)).unwrap();
+                            self.subs_tx.send(Call(key, val, from_id, msg_id))?;
 
                             db_modified.swap(true, Ordering::Relaxed);
                         }
@@ -82,20 +109,17 @@ impl Data {
                 // a separator for the rest of the message.
                 //     sl , somekey value 1, other.key value 2, key value 3, 1.2 value 4
                 Action::SetList(key, val, from_id, msg_id) => {
-                    let separator = key.chars().next().unwrap() as u8;
-                    let set_list = val.split(|x| *x == separator);
-
-                    let mut map = self.map.lock().unwrap();
-                    for key_val in set_list {
-                        let mut cursor = Cursor::new(key_val);
-                        let key = String::from_utf8_lossy(next_word(&mut cursor));
-                        let val = remaining(&mut cursor);
+                    // The same byte namespace::scope_list uses.
+                    let separator = match key.as_bytes().first() {
+                        Some(separator) => *separator,
+                        None => continue,
+                    };
 
-                        map.insert(key.to_string(), val.to_owned());
+                    let mut map = lock(&self.map);
+                    for (key, val) in split_list(separator, &val) {
+                        map.insert(key.to_owned(), val.to_owned());
 
-                        self.subs_tx
-                            .send(Call(key.into(), val.into(), from_id, msg_id))
-                            .unwrap();
+                        self.subs_tx.send(Call(key, val, from_id, msg_id))?;
                     }
                     drop(map);
 
@@ -104,10 +128,10 @@ impl Data {
 
                 Action::Inc(key, from_id, msg_id) => {
                     let inc_vec = {
-                        let mut map = self.map.lock().unwrap();
+                        let mut map = lock(&self.map);
 
                         let inc = match map.get(&key) {
-                            Some(val) => vec_to_u64(val) + 1,
+                            Some(val) => vec_to_u64(val).saturating_add(1),
                             None => 1,
                         };
 
@@ -118,70 +142,77 @@ impl Data {
                         inc_vec
                     };
 
-                    self.writer_tx
-                        .send(Queue(Order {
-                            from_id,
-                            to_id: from_id,
-                            msg_id,
-                            data: inc_vec.to_owned(),
-                        }))
-                        .unwrap();
+                    self.writer_tx.send(Queue(Order {
+                        from_id,
+                        to_id: from_id,
+                        msg_id,
+                        data: inc_vec.to_owned(),
+                    }))?;
 
-                    self.subs_tx
-                        .send(Call(key, inc_vec, from_id, msg_id))
-                        .unwrap();
+                    self.subs_tx.send(Call(key, inc_vec, from_id, msg_id))?;
 
                     db_modified.swap(true, Ordering::Relaxed);
                 }
 
                 Action::Append(key, data, from_id, msg_id) => {
-                    let mut map = self.map.lock().unwrap();
+                    let mut map = lock(&self.map);
                     let value = map.entry(key.to_owned()).or_default();
                     value.extend_from_slice(&data);
                     drop(map);
 
-                    self.subs_tx.send(Call(key, data, from_id, msg_id)).unwrap();
+                    self.subs_tx.send(Call(key, data, from_id, msg_id))?;
 
                     db_modified.swap(true, Ordering::Relaxed);
                 }
 
                 Action::Delete(key) => {
-                    if self.map.lock().unwrap().remove(&key).is_some() {
+                    if lock(&self.map).remove(&key).is_some() {
                         db_modified.swap(true, Ordering::Relaxed);
                     }
                 }
 
                 Action::Get(key, from_id, msg_id) => {
-                    let message = match self.map.lock().unwrap().get(&key) {
+                    let message = match lock(&self.map).get(&key) {
                         Some(value) => value.to_vec(),
                         None => [].into(),
                     };
 
-                    self.writer_tx
-                        .send(Queue(Order {
-                            from_id,
-                            to_id: from_id,
-                            msg_id,
-                            data: message,
-                        }))
-                        .unwrap();
+                    self.writer_tx.send(Queue(Order {
+                        from_id,
+                        to_id: from_id,
+                        msg_id,
+                        data: message,
+                    }))?;
                 }
 
-                Action::KeyValue(key, from_id, msg_id) => {
-                    let map = self.map.lock().unwrap();
-                    let range = map.range(key.to_owned()..);
+                // The values of the keys in the same order, NO when they don't
+                // fit in a message.
+                Action::GetList(separator, list, from_id, msg_id) => {
+                    let separator = separator.as_bytes()[0];
+                    let keys = split_list(separator, &list);
+
+                    let map = lock(&self.map);
+                    let values: Vec<Option<&Vec<u8>>> =
+                        keys.iter().map(|(key, _)| map.get(key)).collect();
+                    let message = stamp_values(&values);
+                    drop(map);
 
-                    let key_value: Vec<_> = range
-                        .take_while(|(k, _)| k.starts_with(&key))
-                        .map(|(k, v)| (k.as_str(), v.to_vec()))
-                        .collect();
+                    match message.len() > MAX_DATA {
+                        true => self.reply(NO.into(), from_id, msg_id)?,
+                        false => self.reply(message, from_id, msg_id)?,
+                    }
+                }
+
+                Action::KeyValue(key, depth, from_id, msg_id) => {
+                    let map = lock(&self.map);
+                    let key_value = key_range(&map, &key, depth);
 
                     let mut message = Vec::<u8>::new();
-                    for (key, mut value) in key_value {
-                        let key = key.split('.').last().unwrap();
+                    for (key, value) in key_value {
+                        let key = key.split('.').next_back().unwrap();
                         message.extend(key.as_bytes());
                         message.extend(b" ");
-                        message.append(&mut value);
+                        message.extend(value);
                         message.extend(b"\0");
                     }
 
@@ -192,110 +223,537 @@ impl Data {
                         }
                     }
 
-                    self.writer_tx
-                        .send(Queue(Order {
-                            from_id,
-                            to_id: from_id,
-                            msg_id,
-                            data: message,
-                        }))
-                        .unwrap();
+                    self.writer_tx.send(Queue(Order {
+                        from_id,
+                        to_id: from_id,
+                        msg_id,
+                        data: message,
+                    }))?;
                 }
 
-                Action::Jtrim(key, from_id, msg_id) => {
-                    let map = self.map.lock().unwrap();
-                    let range = map.range(key.to_owned()..);
-
-                    let key_value: Vec<_> = range
-                        .take_while(|(k, _)| k.starts_with(&key))
-                        .map(|(k, v)| (k.as_str(), v))
-                        .collect();
-
-                    let json = kv_to_json(&key_value);
-
-                    // Returns the pointer.
-                    // Always returns everything when the key is empty.
-                    let pointr = format!("/{}", key.replace('.', "/"));
-                    let message = match json.pointer(pointr.as_str()) {
-                        Some(value) => value.to_string(),
-                        None => {
-                            let message = if pointr.len() <= 1 { json } else { json!({}) };
-                            message.to_string()
-                        }
-                    };
+                Action::Jtrim(key, depth, from_id, msg_id) => {
+                    let message = jtrim(&lock(&self.map), &key, depth);
 
-                    self.writer_tx
-                        .send(Queue(Order {
-                            from_id,
-                            to_id: from_id,
-                            msg_id,
-                            data: message.into(),
-                        }))
-                        .unwrap();
+                    self.writer_tx.send(Queue(Order {
+                        from_id,
+                        to_id: from_id,
+                        msg_id,
+                        data: message.into(),
+                    }))?;
                 }
 
-                Action::Json(key, from_id, msg_id) => {
-                    let map = self.map.lock().unwrap();
-                    let range = map.range(key.to_owned()..);
-
-                    let key_value: Vec<_> = range
-                        .take_while(|(k, _)| k.starts_with(&key))
-                        .map(|(k, v)| (k.as_str(), v))
-                        .collect();
-
-                    let json = kv_to_json(&key_value);
-
-                    // Returns the json, but only if the pointer is real.
-                    // Always returns everything when the key is empty.
-                    let pointr = format!("/{}", key.replace('.', "/"));
-                    let message = match json.pointer(pointr.as_str()) {
-                        Some(_) => json.to_string(),
-                        None => {
-                            let message = if pointr.len() <= 1 { json } else { json!({}) };
-                            message.to_string()
+                Action::Json(key, depth, from_id, msg_id) => {
+                    let message = json(&lock(&self.map), &key, depth);
+
+                    self.writer_tx.send(Queue(Order {
+                        from_id,
+                        to_id: from_id,
+                        msg_id,
+                        data: message.into(),
+                    }))?;
+                }
+
+                // The keys of a JSON at once. Replacing deletes the key and
+                // its children first.
+                Action::SetJson(key, kv, replace, from_id, msg_id) => {
+                    let mut map = lock(&self.map);
+
+                    if replace {
+                        for k in subtree_keys(&map, &key) {
+                            map.remove(&k);
                         }
-                    };
+                    }
 
-                    self.writer_tx
-                        .send(Queue(Order {
-                            from_id,
-                            to_id: from_id,
-                            msg_id,
-                            data: message.into(),
-                        }))
-                        .unwrap();
+                    for (key, val) in kv.iter() {
+                        map.insert(key.to_owned(), val.to_owned());
+                    }
+                    drop(map);
+
+                    for (key, val) in kv {
+                        self.subs_tx.send(Call(key, val, from_id, msg_id))?;
+                    }
+
+                    db_modified.swap(true, Ordering::Relaxed);
+                }
+
+                // Patches on the JSON of the key, like **j** returns it. Only
+                // the values that changed are written and called.
+                Action::MergePatch(key, patch, from_id, msg_id) => {
+                    self.patch(&key, from_id, msg_id, &db_modified, |json| {
+                        patch::merge(json, &patch);
+                        Ok(())
+                    })?;
+                }
+
+                Action::JsonPatch(key, operations, from_id, msg_id) => {
+                    self.patch(&key, from_id, msg_id, &db_modified, |json| {
+                        patch::apply(json, &operations)
+                    })?;
                 }
+
+                // A page of keys, the last one is the cursor for the next
+                // page, and an empty page is the end.
+                Action::Keys(key, size, after, from_id, msg_id) => {
+                    let map = lock(&self.map);
+                    let keys = subtree(&map, &key, 0..=usize::MAX, after.as_deref());
+                    let message = page(keys.map(|(k, _)| k.as_str()).take(size));
+                    drop(map);
+
+                    self.reply(message, from_id, msg_id)?;
+                }
+
+                // The same pages, with each child once, the cursor is the child.
+                Action::Children(key, size, after, from_id, msg_id) => {
+                    let children = children(&lock(&self.map), &key, after.as_deref(), size);
+                    let message = page(children.iter().map(|x| x.as_str()));
+
+                    self.reply(message, from_id, msg_id)?;
+                }
+
+                // Everything before this is already on subs.
+                Action::Flush(done) => self.subs_tx.send(Flush(done))?,
+
+                // The server stopped.
+                Action::Stop => return Ok(()),
             }
         }
     }
 }
 
-// @todo I don't really understand this, I took this code from a Discord chat
-// when I asked for help. I wanted to merge json values with the same parent.
-pub fn kv_to_json(kv: &[(&str, &Vec<u8>)]) -> Value {
-    let mut merged_json = json!({});
+impl Data {
+    /// Patches the JSON of the key, then writes the difference as keys, all
+    /// under the same lock. NO when the patch fails, and nothing changes.
+    fn patch<F>(
+        &self,
+        key: &str,
+        from_id: usize,
+        msg_id: usize,
+        db_modified: &AtomicBool,
+        patch: F,
+    ) -> Result<(), Error>
+    where
+        F: FnOnce(&mut Value) -> Result<(), String>,
+    {
+        let mut map = lock(&self.map);
+
+        let tree = Node::from_kv(&key_range(&map, key, usize::MAX));
+        let mut json = match tree.find(namespace::strip(key)) {
+            Some(node) => node.to_json(),
+            None => json!({}),
+        };
+
+        let patched = patch(&mut json)
+            .and_then(|_| json_to_kv(key, &json).ok_or_else(|| "a key isn't valid".into()));
+
+        let kv = match patched {
+            Ok(kv) => kv,
+            Err(err) => {
+                drop(map);
+                info!("Patch on {key} failed: {err}");
+
+                return self.reply(NO.into(), from_id, msg_id);
+            }
+        };
+
+        let keys: HashSet<&str> = kv.iter().map(|(k, _)| k.as_str()).collect();
+        for k in subtree_keys(&map, key) {
+            if !keys.contains(k.as_str()) {
+                map.remove(&k);
+            }
+        }
+
+        // Values that look the same on the JSON stay as they are, like the 8
+        // bytes of +1 that come back as text.
+        let changed: Vec<(String, Vec<u8>)> = kv
+            .into_iter()
+            .filter(|(k, v)| match map.get(k) {
+                Some(old) => json_value(old) != json_value(v),
+                None => true,
+            })
+            .collect();
+
+        for (k, v) in changed.iter() {
+            map.insert(k.to_owned(), v.to_owned());
+        }
+        drop(map);
 
-    // NOTE(Wojciech): Unfinished alternative.
-    // kv.iter().map(|(k, v)| k.split(".").map(|name| {}));
+        self.reply(OK.into(), from_id, msg_id)?;
+
+        for (k, v) in changed {
+            self.subs_tx.send(Call(k, v, from_id, msg_id))?;
+        }
 
-    for (k, v) in kv.iter().rev() {
-        json_insert(&mut merged_json, k, json!(v));
+        db_modified.swap(true, Ordering::Relaxed);
+
+        Ok(())
     }
 
-    merged_json
+    fn reply(&self, data: Vec<u8>, from_id: usize, msg_id: usize) -> Result<(), Error> {
+        self.writer_tx.send(Queue(Order {
+            from_id,
+            to_id: from_id,
+            msg_id,
+            data,
+        }))?;
+
+        Ok(())
+    }
 }
 
-fn json_insert(mut json: &mut Value, key: &str, val: Value) {
-    for k in key.split('.') {
-        json = json
-            .as_object_mut()
-            .unwrap()
-            .entry(k)
-            .or_insert_with(|| json!({}));
+/// The JSON of the key without the full path, like **j**, up to a depth below
+/// the key.
+pub fn jtrim(map: &BTreeMap<String, Vec<u8>>, key: &str, depth: usize) -> String {
+    let tree = Node::from_kv(&key_range(map, key, depth));
+
+    // Always returns everything when the key is empty.
+    let key = namespace::strip(key);
+    if key.is_empty() {
+        return tree.to_json().to_string();
     }
 
-    if json == &json!({}) {
-        *json = val;
+    match tree.find(key) {
+        Some(node) => node.to_json().to_string(),
+        None => json!({}).to_string(),
+    }
+}
+
+/// The JSON of the key with the full path, like **js**, up to a depth below
+/// the key.
+pub fn json(map: &BTreeMap<String, Vec<u8>>, key: &str, depth: usize) -> String {
+    let tree = Node::from_kv(&key_range(map, key, depth));
+
+    // Returns the json, but only if the key is real.
+    // Always returns everything when the key is empty.
+    let key = namespace::strip(key);
+    if key.is_empty() || tree.find(key).is_some() {
+        return tree.to_json().to_string();
+    }
+
+    json!({}).to_string()
+}
+
+/// The JSON of a value, the same for **j**, **js** and **#j**. Text is a
+/// string, or a number when it's written like one. The 8 bytes of **+1** are a
+/// number too, and anything else is binary, an object with its base64.
+pub fn json_value(value: &[u8]) -> Value {
+    if let Some(text) = text(value) {
+        // Only numbers that come back the same, "007" or "1e3" stay text.
+        return match serde_json::from_str::<Number>(text) {
+            Ok(number) if number.to_string() == text => Value::Number(number),
+            _ => Value::String(text.into()),
+        };
+    }
+
+    if let Some(number) = counter(value) {
+        return json!(number);
+    }
+
+    json!({ BASE64: STANDARD.encode(value) })
+}
+
+/// The number of **+1**, 8 bytes that aren't text.
+pub fn counter(value: &[u8]) -> Option<u64> {
+    (value.len() == 8 && text(value).is_none()).then(|| vec_to_u64(value))
+}
+
+/// The value as text, when it has no control characters but whitespace.
+fn text(value: &[u8]) -> Option<&str> {
+    let text = str::from_utf8(value).ok()?;
+    let control = text
+        .chars()
+        .any(|x| x.is_control() && !matches!(x, '\t' | '\n' | '\r'));
+
+    (!control).then_some(text)
+}
+
+/// The keys and values of a JSON under the key, the opposite of **j**. Objects
+/// and arrays are children, **@value** is the value of the key itself, and
+/// **@base64** objects are binary. Nulls have no value. None when a field has
+/// the namespace separator, or the base64 is wrong.
+pub fn json_to_kv(key: &str, json: &Value) -> Option<Vec<(String, Vec<u8>)>> {
+    let mut kv = Vec::new();
+    flatten(key, json, &mut kv)?;
+
+    Some(kv)
+}
+
+fn flatten(key: &str, json: &Value, kv: &mut Vec<(String, Vec<u8>)>) -> Option<()> {
+    let child = |segment: &str| match key.is_empty() {
+        true => segment.to_owned(),
+        false => format!("{key}.{segment}"),
+    };
+
+    match json {
+        Value::Null => {}
+
+        Value::String(text) => kv.push((key.into(), text.as_bytes().into())),
+
+        Value::Number(_) | Value::Bool(_) => kv.push((key.into(), json.to_string().into())),
+
+        Value::Array(array) => {
+            for (i, value) in array.iter().enumerate() {
+                flatten(&child(&i.to_string()), value, kv)?;
+            }
+        }
+
+        Value::Object(object) => match object.get(BASE64) {
+            Some(Value::String(base64)) if object.len() == 1 => {
+                kv.push((key.into(), STANDARD.decode(base64).ok()?));
+            }
+
+            _ => {
+                for (field, value) in object {
+                    if !namespace::is_valid(field) {
+                        return None;
+                    }
+
+                    match field.as_str() {
+                        VALUE => flatten(key, value, kv)?,
+                        _ => flatten(&child(field), value, kv)?,
+                    }
+                }
+            }
+        },
+    }
+
+    Some(())
+}
+
+/// The keys and values of a list like the one used by **sl**.
+pub fn split_list(separator: u8, list: &[u8]) -> Vec<(String, Vec<u8>)> {
+    list.split(|x| *x == separator)
+        .map(|key_val| {
+            let mut cursor = Cursor::new(key_val);
+            let key = String::from_utf8_lossy(next_word(&mut cursor));
+            let val = remaining(&mut cursor);
+
+            (key.into(), val.into())
+        })
+        .collect()
+}
+
+/// The key and all its children.
+fn subtree_keys(map: &BTreeMap<String, Vec<u8>>, key: &str) -> Vec<String> {
+    subtree(map, key, 0..=usize::MAX, None)
+        .map(|(k, _)| k.to_owned())
+        .collect()
+}
+
+/// The key and its children on segment boundaries, in a range of depths below
+/// the key, and after a key when there is one. An empty key is the whole
+/// namespace.
+fn subtree<'a: 'b, 'b>(
+    map: &'a BTreeMap<String, Vec<u8>>,
+    key: &'b str,
+    depth: RangeInclusive<usize>,
+    after: Option<&str>,
+) -> impl Iterator<Item = (&'a String, &'a Vec<u8>)> + 'b {
+    let start = match after {
+        Some(after) if after > key => Bound::Excluded(after),
+        _ => Bound::Included(key),
+    };
+
+    let (namespace, parent) = namespace::split(key);
+
+    map.range::<str, _>((start, Bound::Unbounded))
+        .take_while(move |(k, _)| k.starts_with(key))
+        .filter(move |(k, _)| {
+            let (k_namespace, k) = namespace::split(k);
+
+            match depth_below(parent, k) {
+                Some(below) => k_namespace == namespace && depth.contains(&below),
+                None => false,
+            }
+        })
+}
+
+/// Up to size direct children of the key on segment boundaries, sorted and
+/// after a child when there is one, including the parents without a value of
+/// their own. It starts from the cursor, and the keys below a child are
+/// skipped, so it takes a few lookups per child.
+fn children(
+    map: &BTreeMap<String, Vec<u8>>,
+    key: &str,
+    cursor: Option<&str>,
+    size: usize,
+) -> Vec<String> {
+    let (namespace, parent) = namespace::split(key);
+    let child_of = |segment: &str| match parent {
+        "" => namespace::scope(namespace, segment),
+        parent => namespace::scope(namespace, &format!("{parent}.{segment}")),
+    };
+
+    let mut children = Vec::<String>::new();
+    let mut last = cursor.filter(|x| *x > key).map(|x| x.to_owned());
+    let mut start = match &last {
+        Some(last) => Bound::Excluded(last.to_owned()),
+        None => Bound::Included(key.to_owned()),
+    };
+
+    while children.len() < size {
+        let k = match map
+            .range::<String, _>((start.clone(), Bound::Unbounded))
+            .next()
+        {
+            Some((k, _)) if k.starts_with(key) => k,
+            _ => break,
+        };
+
+        start = Bound::Excluded(k.clone());
+
+        // Other namespaces under the whole default one, all at once.
+        let (k_namespace, k_key) = namespace::split(k);
+        if k_namespace != namespace {
+            start = Bound::Included(format!("{k_namespace}{}", after(namespace::SEPARATOR)));
+            continue;
+        }
+
+        let rest = match parent {
+            "" => k_key,
+            parent => match k_key.strip_prefix(parent).and_then(|x| x.strip_prefix('.')) {
+                Some(rest) => rest,
+                None => continue,
+            },
+        };
+
+        let segment = rest.split('.').next().unwrap_or_default();
+        let child = child_of(segment);
+
+        // Listed already, so everything below it at once. Everything that
+        // starts with "child." comes before "child/".
+        if last.as_ref().is_some_and(|last| child <= *last) {
+            start = Bound::Included(format!("{child}{}", after('.')));
+            continue;
+        }
+
+        // A shorter child sorts first but its keys come later, when the
+        // segment goes on with a char before the dot, "b" of "b.x" after "b-x".
+        let shorter = segment
+            .char_indices()
+            .filter(|(_, x)| *x < '.')
+            .map(|(i, _)| child_of(&segment[..i]))
+            .find(|x| last.as_ref().is_none_or(|last| x > last) && has_below(map, x));
+
+        let child = shorter.unwrap_or(child);
+
+        start = Bound::Excluded(child.to_owned());
+        children.push(child.to_owned());
+        last = Some(child);
+    }
+
+    children
+}
+
+/// True when some key starts with "key.".
+fn has_below(map: &BTreeMap<String, Vec<u8>>, key: &str) -> bool {
+    let below = format!("{key}.");
+
+    map.range::<str, _>((Bound::Included(below.as_str()), Bound::Unbounded))
+        .next()
+        .is_some_and(|(k, _)| k.starts_with(&below))
+}
+
+/// The char that sorts right after this one.
+fn after(char: char) -> char {
+    char::from_u32(char as u32 + 1).unwrap_or(char)
+}
+
+/// Keys without the namespace, as many as fit in a message. Each one ends
+/// with 0, so a page is never taken for **NO**, **AUTH** or **DENIED**.
+fn page<'a>(keys: impl Iterator<Item = &'a str>) -> Vec<u8> {
+    let mut message = Vec::<u8>::new();
+
+    for k in keys.map(namespace::strip) {
+        if message.len() + k.len() + 1 > MAX_DATA {
+            break;
+        }
+
+        message.extend(k.as_bytes());
+        message.push(0);
+    }
+
+    message
+}
+
+/// How many segments the key is below the parent, or None when it isn't the
+/// parent or one of its children. "a.b.c" is 2 below "a", and 3 below "".
+fn depth_below(parent: &str, key: &str) -> Option<usize> {
+    if parent.is_empty() {
+        return Some(key.split('.').count());
+    }
+
+    match key.strip_prefix(parent)? {
+        "" => Some(0),
+        rest => Some(rest.strip_prefix('.')?.split('.').count()),
+    }
+}
+
+/// The keys and values of the key and its children up to a depth, with the
+/// namespace stripped from the keys.
+fn key_range<'a>(
+    map: &'a BTreeMap<String, Vec<u8>>,
+    key: &str,
+    depth: usize,
+) -> Vec<(&'a str, &'a Vec<u8>)> {
+    subtree(map, key, 0..=depth, None)
+        .map(|(k, v)| (namespace::strip(k), v))
+        .collect()
+}
+
+/// The keys and values as one JSON, merging the children of the same parent.
+pub fn kv_to_json(kv: &[(&str, &Vec<u8>)]) -> Value {
+    Node::from_kv(kv).to_json()
+}
+
+/// A segment of the keys, with its own value and its children.
+#[derive(Default)]
+struct Node<'a> {
+    value: Option<&'a [u8]>,
+    children: BTreeMap<&'a str, Node<'a>>,
+}
+
+impl<'a> Node<'a> {
+    fn from_kv(kv: &[(&'a str, &'a Vec<u8>)]) -> Node<'a> {
+        let mut root = Node::default();
+
+        for (key, value) in kv {
+            let mut node = &mut root;
+            for segment in key.split('.') {
+                node = node.children.entry(segment).or_default();
+            }
+
+            node.value = Some(value);
+        }
+
+        root
+    }
+
+    fn find(&self, key: &str) -> Option<&Node<'a>> {
+        key.split('.')
+            .try_fold(self, |node, segment| node.children.get(segment))
+    }
+
+    /// Values without children are just the value. With children, the value
+    /// goes on its own field next to them, so nothing is lost.
+    fn to_json(&self) -> Value {
+        if self.children.is_empty() {
+            return match self.value {
+                Some(value) => json_value(value),
+                None => json!({}),
+            };
+        }
+
+        let mut object = Map::new();
+
+        if let Some(value) = self.value {
+            object.insert(VALUE.into(), json_value(value));
+        }
+
+        for (segment, child) in &self.children {
+            object.insert((*segment).into(), child.to_json());
+        }
+
+        Value::Object(object)
     }
 }
 
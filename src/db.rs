@@ -1,74 +1,110 @@
 use std::{
     collections::BTreeMap,
     fs::{self, OpenOptions},
-    io::{Read, Write},
+    io::{self, Read, Write},
+    path::Path,
     sync::{
         atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, RecvTimeoutError},
         Arc, Mutex,
     },
-    thread::sleep,
     time::Duration,
 };
 
-const DB_PATH: &str = "./data";
-const DB_FILE: &str = "./data/db.bin";
-const DB_NAME: &str = "db.bin";
+use crate::supervisor::lock;
 
+pub const DB_FILE: &str = "./data/db.bin";
+
+/// Snapshots of the data on a file, or nothing without one.
+#[derive(Clone)]
 pub struct DB {
     data: Arc<Mutex<BTreeMap<String, Vec<u8>>>>,
+    file: Option<Arc<str>>,
     pub modified: Arc<AtomicBool>,
 }
 
 impl DB {
-    pub fn new(data: Arc<Mutex<BTreeMap<String, Vec<u8>>>>) -> DB {
+    pub fn new(data: Arc<Mutex<BTreeMap<String, Vec<u8>>>>, file: Option<&str>) -> DB {
         let modified = Arc::new(AtomicBool::new(false));
 
-        DB { data, modified }
+        DB {
+            data,
+            file: file.map(|x| x.into()),
+            modified,
+        }
     }
 
-    pub fn handle(&mut self, throttle: u64) {
+    /// Saves the modified data every few seconds, until something arrives on
+    /// stop.
+    pub fn handle(&mut self, throttle: u64, stop: &Receiver<()>) {
         loop {
-            sleep(Duration::new(throttle, 0));
+            match stop.recv_timeout(Duration::new(throttle, 0)) {
+                Err(RecvTimeoutError::Timeout) => {}
+                _ => return,
+            }
 
+            // Tries again on the next round when it fails, like a full disk.
             if self.modified.swap(false, Ordering::Relaxed) {
-                self.save_to_file();
+                if let Err(err) = self.save_to_file() {
+                    error!("Snapshot failed: {err}");
+                    self.modified.store(true, Ordering::Relaxed);
+                }
             }
         }
     }
 
-    pub fn load_from_file(&self) {
-        fs::create_dir_all(DB_PATH).unwrap();
+    pub fn load_from_file(&self) -> io::Result<()> {
+        let path = match &self.file {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        if let Some(dir) = Path::new(&**path)
+            .parent()
+            .filter(|x| !x.as_os_str().is_empty())
+        {
+            fs::create_dir_all(dir)?;
+        }
 
-        let file = OpenOptions::new()
+        let mut file = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
             .truncate(false)
-            .open(DB_FILE);
+            .open(&**path)?;
 
         let mut content = Vec::<u8>::new();
-        file.unwrap().read_to_end(&mut content).unwrap();
+        file.read_to_end(&mut content)?;
 
         if content.is_empty() {
-            return;
+            return Ok(());
         }
 
         if let Ok(data) = bincode::deserialize::<BTreeMap<String, Vec<u8>>>(&content[..]) {
-            *self.data.lock().unwrap() = data;
+            *lock(&self.data) = data;
         }
+
+        Ok(())
     }
 
-    pub fn save_to_file(&self) {
-        let file = OpenOptions::new()
+    pub fn save_to_file(&self) -> io::Result<()> {
+        let path = match &self.file {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let mut file = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
             .truncate(true)
-            .open(DB_FILE);
+            .open(&**path)?;
+
+        let data: Vec<u8> = bincode::serialize(&*lock(&self.data)).map_err(io::Error::other)?;
+        file.write_all(&data[..])?;
 
-        let data: Vec<u8> = bincode::serialize(&*self.data.lock().unwrap()).unwrap();
-        file.unwrap().write_all(&data[..]).unwrap();
+        info!("{path} saved");
 
-        info!("{DB_NAME} saved");
+        Ok(())
     }
 }
@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    data,
+    message::{Message, Messages, Received},
+    namespace,
+    parser::{self, Request},
+    patch,
+};
+
+/// Feeds the bytes to the framing in chunks, the way the reader does. The
+/// first byte chooses the size of the chunks.
+pub fn framing(bytes: &[u8]) {
+    let (chunk, stream) = match bytes.split_first() {
+        Some((chunk, stream)) => (*chunk as usize + 1, stream),
+        None => return,
+    };
+
+    let mut messages = Messages::default();
+
+    for data in stream.chunks(chunk) {
+        let mut data = data.to_vec();
+
+        // Several messages can arrive in one read, they are fed until there
+        // is nothing complete left.
+        loop {
+            let (received, pending) = match messages.feed(data) {
+                Received::None => break,
+                Received::Error(_) => return,
+                Received::Complete(received) => (received, false),
+                Received::Pending(received) => (received, true),
+            };
+
+            if Message::from_protocol(received).is_err() {
+                return;
+            }
+
+            if !pending {
+                break;
+            }
+
+            data = Vec::new();
+        }
+    }
+}
+
+/// Parses the bytes as a command into the request the parser dispatches,
+/// scoped to a namespace, including the keys of **sl** and **gl**.
+pub fn parse(bytes: &[u8]) {
+    match parser::request(parser::parse(bytes), "fuzz") {
+        Request::SetList(separator, list) | Request::GetList(separator, list) => {
+            for (key, _) in data::split_list(separator.as_bytes()[0], &list) {
+                namespace::split(&key);
+            }
+        }
+
+        Request::Keys(key, _, after) | Request::Children(key, _, after) => {
+            namespace::split(&key);
+            if let Some(after) = &after {
+                namespace::split(after);
+            }
+        }
+
+        _ => {}
+    }
+}
+
+/// Builds the JSON of keys and values, one "key value" per line, in any
+/// order, and queries it with the key of the first line. The JSON, and the
+/// bytes when they are JSON, go back into keys.
+pub fn json(bytes: &[u8]) {
+    let lines: Vec<(String, Vec<u8>)> = bytes
+        .split(|x| *x == b'\n')
+        .map(|line| {
+            let (key, value) = match line.iter().position(|x| *x == b' ') {
+                Some(space) => (&line[..space], &line[space + 1..]),
+                None => (line, &[][..]),
+            };
+
+            (String::from_utf8_lossy(key).into(), value.to_vec())
+        })
+        .collect();
+
+    let kv: Vec<(&str, &Vec<u8>)> = lines.iter().map(|(k, v)| (k.as_str(), v)).collect();
+    let json = data::kv_to_json(&kv);
+
+    // Back into keys, like **sj**.
+    data::json_to_kv("fuzz", &json);
+    if let Ok(json) = serde_json::from_slice(bytes) {
+        data::json_to_kv("fuzz", &json);
+
+        // And as patches on themselves, like **jm** and **jp**.
+        patch::merge(&mut json.clone(), &json);
+        let _ = patch::apply(&mut json.clone(), &json);
+    }
+
+    let map: BTreeMap<String, Vec<u8>> = lines.iter().cloned().collect();
+    let key = lines.first().map(|(k, _)| k.as_str()).unwrap_or_default();
+
+    data::jtrim(&map, key, usize::MAX);
+    data::json(&map, key, 1);
+}
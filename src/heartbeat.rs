@@ -1,60 +1,92 @@
 use std::{
     collections::HashMap,
     net::Shutdown,
-    sync::{mpsc::Sender, Arc, Mutex},
-    thread::sleep,
+    sync::{
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
     time::Duration,
 };
 
 use crate::connection::Connection;
+use crate::supervisor::{lock, Error};
 use crate::writer::{self, Action::QueueAll, Order};
 
-const TIMEOUT_30: u64 = 30;
-const TIMEOUT_60: u64 = 60;
+pub enum Action {
+    Stop,
+}
 
 pub struct Heartbeat {
     readers: Arc<Mutex<HashMap<usize, Connection>>>,
     writers: Arc<Mutex<HashMap<usize, Connection>>>,
+    interval: Duration,
+    pub tx: Sender<Action>,
+    rx: Receiver<Action>,
 }
 
 impl Heartbeat {
     pub fn new(
         readers: Arc<Mutex<HashMap<usize, Connection>>>,
         writers: Arc<Mutex<HashMap<usize, Connection>>>,
+        interval: Duration,
     ) -> Heartbeat {
-        Heartbeat { readers, writers }
+        let (tx, rx) = channel::<Action>();
+
+        Heartbeat {
+            readers,
+            writers,
+            interval,
+            tx,
+            rx,
+        }
     }
 
-    pub fn handle(&self, writer_tx: Sender<writer::Action>) {
+    pub fn handle(&self, writer_tx: Sender<writer::Action>) -> Result<(), Error> {
         loop {
-            sleep(Duration::from_secs(TIMEOUT_30));
+            if self.stopped() {
+                return Ok(());
+            }
+
             self.drop_idle_readers();
 
-            sleep(Duration::from_secs(TIMEOUT_30));
-            self.ping_idle_writers(&writer_tx);
+            if self.stopped() {
+                return Ok(());
+            }
+
+            self.ping_idle_writers(&writer_tx)?;
+            self.report_throttled_writers();
+        }
+    }
+
+    /// Waits an interval, true when the server stopped meanwhile.
+    fn stopped(&self) -> bool {
+        match self.rx.recv_timeout(self.interval) {
+            Ok(Action::Stop) | Err(RecvTimeoutError::Disconnected) => true,
+            Err(RecvTimeoutError::Timeout) => false,
         }
     }
 
     fn drop_idle_readers(&self) {
-        let mut readers = self.readers.lock().unwrap();
+        let mut readers = lock(&self.readers);
 
         for (id, connection) in readers.iter_mut() {
-            let elapsed = connection.last_read.elapsed().as_secs();
-            if connection.pending_read && elapsed > TIMEOUT_30 {
+            let elapsed = connection.last_read.elapsed();
+            if connection.pending_read && elapsed > self.interval {
                 connection.closed = true;
-                connection.socket.shutdown(Shutdown::Both).unwrap();
+                // The socket could be closed already.
+                let _ = connection.socket.shutdown(Shutdown::Both);
 
                 info!("Shutting down Reader #{id}, timed out");
             }
         }
     }
 
-    fn ping_idle_writers(&self, writer_tx: &Sender<writer::Action>) {
+    fn ping_idle_writers(&self, writer_tx: &Sender<writer::Action>) -> Result<(), Error> {
         let mut messages = Vec::<Order>::new();
-        let writers = self.writers.lock().unwrap();
+        let writers = lock(&self.writers);
 
         for (id, connection) in writers.iter() {
-            if connection.last_write.elapsed().as_secs() > TIMEOUT_60 {
+            if connection.last_write.elapsed() > self.interval * 2 {
                 messages.push(Order {
                     from_id: 0,
                     to_id: *id,
@@ -69,7 +101,30 @@ impl Heartbeat {
         drop(writers);
 
         if !messages.is_empty() {
-            writer_tx.send(QueueAll(messages)).unwrap();
+            writer_tx.send(QueueAll(messages))?;
+        }
+
+        Ok(())
+    }
+
+    /// The drops since the last report, for the connections that had any.
+    fn report_throttled_writers(&self) {
+        let mut writers = lock(&self.writers);
+
+        for (id, connection) in writers.iter_mut() {
+            let messages = connection.dropped_messages - connection.reported_messages;
+            if messages == 0 {
+                continue;
+            }
+
+            warn!(
+                "Connection #{id} throttled, {messages} messages ({} bytes) dropped, {} queued",
+                connection.dropped_bytes - connection.reported_bytes,
+                connection.send_queue.len()
+            );
+
+            connection.reported_messages = connection.dropped_messages;
+            connection.reported_bytes = connection.dropped_bytes;
         }
     }
 }
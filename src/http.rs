@@ -0,0 +1,511 @@
+use std::{
+    collections::VecDeque,
+    io::{
+        self,
+        ErrorKind::{BrokenPipe, WouldBlock},
+        Read, Write,
+    },
+    net::{Shutdown, TcpStream},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::{
+    client::{get_list, sized_command},
+    message::get_values,
+};
+
+const MAX_HEAD: usize = 8192;
+const MAX_FRAME: usize = 65535;
+const BUFFER_SIZE: usize = 4096;
+
+/// What a request expects from its reply.
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Value,
+    Status,
+    Json,
+    Events,
+    Rejected(u16),
+}
+
+struct Request {
+    msg_id: u16,
+    kind: Kind,
+    close: bool,
+    response: Option<Vec<u8>>,
+}
+
+/// HTTP state for a connection. Requests become BITE frames for the reader,
+/// and the frames from the writer become responses, in the same order. An
+/// events request turns the connection into a stream of Server-Sent Events.
+pub struct Http {
+    id: usize,
+    msg_id: u16,
+    auth_id: Option<u16>,
+    authenticated: bool,
+    input: Vec<u8>,
+    frames: Vec<u8>,
+    replies: Vec<u8>,
+    output: Vec<u8>,
+    pending: VecDeque<Request>,
+    streaming: bool,
+    closing: bool,
+    closed: bool,
+}
+
+impl Http {
+    pub fn new(id: usize) -> Http {
+        Http {
+            id,
+            msg_id: 0,
+            auth_id: None,
+            authenticated: false,
+            input: Vec::new(),
+            frames: Vec::new(),
+            replies: Vec::new(),
+            output: Vec::new(),
+            pending: VecDeque::new(),
+            streaming: false,
+            closing: false,
+            closed: false,
+        }
+    }
+
+    /// True when there are responses waiting for the socket.
+    pub fn wants_write(&self) -> bool {
+        !self.output.is_empty()
+    }
+
+    pub fn read(&mut self, socket: &mut TcpStream, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if !self.frames.is_empty() {
+                let n = buf.len().min(self.frames.len());
+                buf[..n].copy_from_slice(&self.frames[..n]);
+                self.frames.drain(..n);
+
+                return Ok(n);
+            }
+
+            if self.closed {
+                return Ok(0);
+            }
+
+            let mut chunk = [0; BUFFER_SIZE];
+            let n = socket.read(&mut chunk)?;
+            if n == 0 {
+                return Ok(0);
+            }
+
+            if !self.accepts_requests() {
+                continue;
+            }
+
+            self.input.extend_from_slice(&chunk[..n]);
+            self.requests();
+
+            // Errors answered right away.
+            self.respond();
+            self.flush(socket)?;
+        }
+    }
+
+    pub fn write(&mut self, socket: &mut TcpStream, buf: &[u8]) -> io::Result<usize> {
+        // Responses still waiting means the socket is full.
+        self.flush(socket)?;
+        if self.wants_write() {
+            return Err(WouldBlock.into());
+        }
+
+        self.replies.extend_from_slice(buf);
+        self.decode();
+        self.respond();
+        self.flush(socket)?;
+
+        Ok(buf.len())
+    }
+
+    /// Writes the responses that are waiting until the socket would block.
+    pub fn flush(&mut self, socket: &mut TcpStream) -> io::Result<()> {
+        while !self.output.is_empty() {
+            match socket.write(&self.output) {
+                Ok(0) => return Err(BrokenPipe.into()),
+                Ok(n) => drop(self.output.drain(..n)),
+                Err(err) if err.kind() == WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        // Everything was said, the client will close its side.
+        if self.closing && !self.closed && self.output.is_empty() && self.pending.is_empty() {
+            self.closed = true;
+            let _ = socket.shutdown(Shutdown::Write);
+        }
+
+        Ok(())
+    }
+
+    /// Streams and closing connections don't take more requests.
+    fn accepts_requests(&self) -> bool {
+        let stream = self.pending.back().is_some_and(|x| x.kind == Kind::Events);
+        !(self.streaming || self.closing || stream)
+    }
+
+    /// Moves the complete requests from the input to frames.
+    fn requests(&mut self) {
+        while self.accepts_requests() {
+            let end = match self.input.windows(4).position(|x| x == b"\r\n\r\n") {
+                Some(end) => end + 4,
+
+                None if self.input.len() > MAX_HEAD => {
+                    self.input.clear();
+                    self.reject(431, true);
+
+                    return;
+                }
+
+                None => return,
+            };
+
+            let head = String::from_utf8_lossy(&self.input[..end]).to_string();
+            let mut lines = head.lines();
+
+            let line = lines.next().unwrap_or_default().to_owned();
+            let mut parts = line.split(' ');
+            let method = parts.next().unwrap_or_default();
+            let target = parts.next().unwrap_or_default();
+            let version = parts.next().unwrap_or_default();
+
+            let mut length = 0;
+            let mut authorization = None;
+            let mut close = version == "HTTP/1.0";
+
+            for (name, value) in lines.filter_map(|x| x.split_once(':')) {
+                let value = value.trim();
+
+                match name.trim().to_lowercase().as_str() {
+                    "content-length" => length = value.parse().unwrap_or(MAX_FRAME + 1),
+                    "authorization" => authorization = Some(value.to_owned()),
+                    "connection" => close = value.eq_ignore_ascii_case("close"),
+                    "transfer-encoding" => length = MAX_FRAME + 1,
+                    _ => {}
+                }
+            }
+
+            if !version.starts_with("HTTP/1.") {
+                self.input.clear();
+                self.reject(400, true);
+
+                return;
+            }
+
+            if length > MAX_FRAME {
+                self.input.clear();
+                self.reject(413, true);
+
+                return;
+            }
+
+            if self.input.len() < end + length {
+                return;
+            }
+
+            let body = self.input[end..end + length].to_vec();
+            self.input.drain(..end + length);
+
+            if !self.authenticated {
+                if let Some(credentials) = authorization.and_then(|x| credentials(&x)) {
+                    let msg_id = self.next_msg_id();
+                    self.push_frame(msg_id, format!("a {credentials}").as_bytes());
+                    self.auth_id = Some(msg_id);
+                    self.authenticated = true;
+                }
+            }
+
+            let (path, query) = target.split_once('?').unwrap_or((target, ""));
+            let (command, kind, key) = match route(method, path, query) {
+                Ok(route) => route,
+                Err(status) => {
+                    self.reject(status, close);
+                    continue;
+                }
+            };
+
+            // Values come from **gl**, where they can't be taken for errors.
+            let command = match kind {
+                Kind::Value => match get_list(&[&key]) {
+                    Ok((separator, list)) => {
+                        let mut command = format!("{command} {separator} ").into_bytes();
+                        command.extend(list);
+                        command
+                    }
+
+                    Err(_) => {
+                        self.reject(400, close);
+                        continue;
+                    }
+                },
+
+                // The body is the value, byte for byte.
+                Kind::Status if method == "PUT" => sized_command(command, &key, &body),
+
+                _ => format!("{command} {key}").into_bytes(),
+            };
+
+            if command.len() + 6 > MAX_FRAME {
+                self.reject(413, close);
+                continue;
+            }
+
+            let msg_id = self.next_msg_id();
+            self.push_frame(msg_id, &command);
+            self.pending.push_back(Request {
+                msg_id,
+                kind,
+                close,
+                response: None,
+            });
+
+            // The connection belongs to the stream from now on.
+            if kind == Kind::Events {
+                self.input.clear();
+            }
+        }
+    }
+
+    /// Moves the replies of complete frames to their requests.
+    fn decode(&mut self) {
+        while self.replies.len() >= 6 {
+            let from = u16::from_be_bytes([self.replies[0], self.replies[1]]);
+            let msg_id = u16::from_be_bytes([self.replies[2], self.replies[3]]);
+            let size = u16::from_be_bytes([self.replies[4], self.replies[5]]) as usize;
+
+            if size < 6 || self.replies.len() < size {
+                return;
+            }
+
+            let data = self.replies[6..size].to_vec();
+            self.replies.drain(..size);
+
+            if self.streaming {
+                self.output.extend(event(from, &data));
+                continue;
+            }
+
+            // The id message, pings and anything that isn't a reply.
+            if msg_id == 0 || from as usize != self.id {
+                continue;
+            }
+
+            if self.auth_id == Some(msg_id) {
+                self.auth_id = None;
+                self.authenticated = data == b"OK";
+                continue;
+            }
+
+            if let Some(request) = self.pending.iter_mut().find(|x| x.msg_id == msg_id) {
+                if request.response.is_none() {
+                    request.response = Some(data);
+                }
+            }
+        }
+    }
+
+    /// Writes the responses that are ready, in the order of the requests.
+    fn respond(&mut self) {
+        while let Some(request) = self.pending.front() {
+            let reply = match &request.response {
+                Some(reply) => reply.as_slice(),
+                None => return,
+            };
+
+            let kind = request.kind;
+            let close = request.close;
+
+            let value = match kind {
+                Kind::Value => get_values(reply),
+                _ => None,
+            };
+
+            let response = match (kind, reply, value.as_deref()) {
+                (Kind::Rejected(status), _, _) => {
+                    response(status, "text/plain", reason(status).as_bytes(), close)
+                }
+                (Kind::Value, _, Some([Some(value)])) => {
+                    response(200, "text/plain; charset=utf-8", value, close)
+                }
+                (Kind::Value, _, Some([None])) => response(404, "text/plain", b"", close),
+                (_, b"AUTH", _) => response(401, "text/plain", b"AUTH", close),
+                (_, b"DENIED", _) => response(403, "text/plain", b"DENIED", close),
+                (Kind::Json, b"", _) => response(404, "text/plain", b"", close),
+                (Kind::Json, _, _) => response(200, "application/json", reply, close),
+                (Kind::Events, b"OK", _) => {
+                    self.streaming = true;
+
+                    "HTTP/1.1 200 OK\r\n\
+                     Content-Type: text/event-stream\r\n\
+                     Cache-Control: no-cache\r\n\r\n"
+                        .into()
+                }
+                (Kind::Status, b"OK", _) => response(200, "text/plain", b"OK", close),
+                (_, reply, _) => response(400, "text/plain", reply, close),
+            };
+
+            self.output.extend(response);
+            self.pending.pop_front();
+
+            if close {
+                self.closing = true;
+                self.pending.clear();
+            }
+        }
+    }
+
+    /// A response for a request that never reaches the server.
+    fn reject(&mut self, status: u16, close: bool) {
+        self.pending.push_back(Request {
+            msg_id: 0,
+            kind: Kind::Rejected(status),
+            close,
+            response: Some(Vec::new()),
+        });
+    }
+
+    fn next_msg_id(&mut self) -> u16 {
+        self.msg_id = self.msg_id.checked_add(1).unwrap_or(1);
+        self.msg_id
+    }
+
+    fn push_frame(&mut self, msg_id: u16, data: &[u8]) {
+        let size = (data.len() + 6) as u16;
+
+        self.frames.extend((self.id as u16).to_be_bytes());
+        self.frames.extend(msg_id.to_be_bytes());
+        self.frames.extend(size.to_be_bytes());
+        self.frames.extend(data);
+    }
+}
+
+/// The command, what to expect and the key for a request.
+fn route(method: &str, path: &str, query: &str) -> Result<(&'static str, Kind, String), u16> {
+    let path = path.trim_start_matches('/');
+    let (resource, key) = path.split_once('/').unwrap_or((path, ""));
+
+    let key = match percent_decode(key) {
+        Some(key) if !key.contains(char::is_whitespace) => key,
+        _ => return Err(400),
+    };
+
+    match (method, resource) {
+        (_, "keys" | "events") if key.is_empty() => Err(404),
+
+        ("GET", "keys") => Ok(("gl", Kind::Value, key)),
+        ("PUT", "keys") => Ok(("s", Kind::Status, key)),
+        ("DELETE", "keys") => Ok(("d", Kind::Status, key)),
+
+        ("GET", "json") if query == "full" => Ok(("js", Kind::Json, key)),
+        ("GET", "json") => Ok(("j", Kind::Json, key)),
+
+        ("GET", "events") => Ok(("#f", Kind::Events, key)),
+
+        (_, "keys" | "json" | "events") => Err(405),
+
+        _ => Err(404),
+    }
+}
+
+/// The arguments for **a**, from Basic or Bearer credentials.
+fn credentials(authorization: &str) -> Option<String> {
+    let (scheme, value) = authorization.split_once(' ')?;
+
+    if scheme.eq_ignore_ascii_case("bearer") {
+        return Some(value.trim().to_owned());
+    }
+
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+
+    let decoded = STANDARD.decode(value.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+
+    if user.is_empty() {
+        Some(password.to_owned())
+    } else {
+        Some(format!("{user} {password}"))
+    }
+}
+
+fn percent_decode(text: &str) -> Option<String> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::<u8>::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+fn response(status: u16, content_type: &str, body: &[u8], close: bool) -> Vec<u8> {
+    let mut response = format!(
+        "HTTP/1.1 {status} {}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n",
+        reason(status),
+        body.len()
+    );
+
+    if status == 401 {
+        response.push_str("WWW-Authenticate: Basic realm=\"bite\"\r\n");
+    }
+
+    if close {
+        response.push_str("Connection: close\r\n");
+    }
+
+    response.push_str("\r\n");
+
+    let mut response = response.into_bytes();
+    response.extend(body);
+    response
+}
+
+/// Subscription calls as events, and pings as comments to keep it alive.
+fn event(from: u16, data: &[u8]) -> Vec<u8> {
+    if from == 0 && data.is_empty() {
+        return b":\n\n".to_vec();
+    }
+
+    let mut event = Vec::<u8>::new();
+
+    for line in data.split(|x| *x == b'\n') {
+        event.extend(b"data: ");
+        event.extend(line);
+        event.push(b'\n');
+    }
+
+    event.push(b'\n');
+    event
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        _ => "Error",
+    }
+}
@@ -0,0 +1,39 @@
+mod acl;
+#[cfg(feature = "async")]
+pub mod async_client;
+mod auth;
+mod cleaner;
+pub mod client;
+mod connection;
+mod data;
+mod db;
+/// Entry points for the fuzz targets on **fuzz/**, the code that handles bytes
+/// from the network, without threads.
+#[doc(hidden)]
+pub mod fuzz;
+mod heartbeat;
+mod http;
+mod listener;
+mod local;
+pub mod message;
+mod namespace;
+mod parser;
+mod patch;
+mod reader;
+mod resp;
+mod server;
+mod stream;
+mod subs;
+mod supervisor;
+mod udp;
+mod websocket;
+mod writer;
+
+pub use crate::{
+    connection::{Limits, Overflow},
+    local::Local,
+    server::{Addresses, Builder, Server, Stop},
+};
+
+#[macro_use]
+extern crate log;
@@ -0,0 +1,190 @@
+use std::{
+    io,
+    net::{SocketAddr, TcpListener},
+    sync::Arc,
+};
+
+#[cfg(unix)]
+use std::{
+    fs::{self, DirBuilder, Permissions},
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, RawFd},
+        unix::{
+            fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+            net::UnixListener,
+        },
+    },
+    process,
+};
+
+#[cfg(windows)]
+use std::os::windows::io::{AsRawSocket, AsSocket, BorrowedSocket, RawSocket};
+
+use crate::stream::{Address, Stream};
+
+use rustls::ServerConfig;
+
+/// How the connections accepted by a listener talk.
+pub enum Transport {
+    Tcp,
+    Tls(Arc<ServerConfig>),
+    WebSocket,
+    Http,
+    Resp,
+    #[cfg(unix)]
+    Unix,
+}
+
+enum Socket {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, Arc<str>),
+}
+
+/// A socket accepting connections, registered on the poller with its key.
+pub struct Listener {
+    pub key: usize,
+    socket: Socket,
+    transport: Transport,
+}
+
+impl Listener {
+    pub fn bind(address: &str, key: usize, transport: Transport) -> io::Result<Listener> {
+        let socket = TcpListener::bind(address)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(Listener {
+            key,
+            socket: Socket::Tcp(socket),
+            transport,
+        })
+    }
+
+    /// Listens on a Unix domain socket file, replacing an old socket but never
+    /// other files. The mode, like 0o660, limits who can connect.
+    #[cfg(unix)]
+    pub fn bind_unix(path: &str, key: usize, mode: Option<u32>) -> io::Result<Listener> {
+        match fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+
+            Ok(_) => {
+                let err = format!("{path} exists and isn't a socket");
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, err));
+            }
+
+            Err(_) => {}
+        }
+
+        let socket = match mode {
+            Some(mode) => bind_private(path, mode)?,
+            None => UnixListener::bind(path)?,
+        };
+        socket.set_nonblocking(true)?;
+
+        Ok(Listener {
+            key,
+            socket: Socket::Unix(socket, path.into()),
+            transport: Transport::Unix,
+        })
+    }
+
+    /// The address it's bound to, useful when the port was 0.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match &self.socket {
+            Socket::Tcp(listener) => listener.local_addr(),
+
+            #[cfg(unix)]
+            Socket::Unix(..) => Err(io::ErrorKind::Unsupported.into()),
+        }
+    }
+
+    /// The stream for the next connection with the id, ready for non-blocking
+    /// use.
+    pub fn accept(&self, id: usize) -> io::Result<(Stream, Address)> {
+        match &self.socket {
+            Socket::Tcp(listener) => {
+                let (socket, addr) = listener.accept()?;
+                socket.set_nonblocking(true)?;
+
+                let stream = match &self.transport {
+                    Transport::Tls(config) => Stream::tls(socket, config.clone())?,
+                    Transport::WebSocket => Stream::websocket(socket),
+                    Transport::Http => Stream::http(socket, id),
+                    Transport::Resp => Stream::resp(socket, id),
+                    _ => Stream::Tcp(socket),
+                };
+
+                Ok((stream, Address::Net(addr)))
+            }
+
+            #[cfg(unix)]
+            Socket::Unix(listener, path) => {
+                let (socket, _) = listener.accept()?;
+                socket.set_nonblocking(true)?;
+
+                Ok((Stream::Unix(socket), Address::Unix(path.clone())))
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Socket::Unix(_, path) = &self.socket {
+            let _ = fs::remove_file(&**path);
+        }
+    }
+}
+
+#[cfg(unix)]
+impl AsFd for Listener {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        match &self.socket {
+            Socket::Tcp(socket) => socket.as_fd(),
+            Socket::Unix(socket, _) => socket.as_fd(),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl AsRawFd for Listener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.as_fd().as_raw_fd()
+    }
+}
+
+#[cfg(windows)]
+impl AsRawSocket for Listener {
+    fn as_raw_socket(&self) -> RawSocket {
+        self.as_socket().as_raw_socket()
+    }
+}
+
+#[cfg(windows)]
+impl AsSocket for Listener {
+    fn as_socket(&self) -> BorrowedSocket<'_> {
+        match &self.socket {
+            Socket::Tcp(socket) => socket.as_socket(),
+        }
+    }
+}
+
+/// Binds the socket inside a private directory next to the path, and moves it
+/// to the path once it has the mode, so nobody can connect before.
+#[cfg(unix)]
+fn bind_private(path: &str, mode: u32) -> io::Result<UnixListener> {
+    let dir = format!("{path}.{}", process::id());
+    DirBuilder::new().mode(0o700).create(&dir)?;
+
+    let temporary = format!("{dir}/socket");
+    let bound = UnixListener::bind(&temporary).and_then(|socket| {
+        fs::set_permissions(&temporary, Permissions::from_mode(mode))?;
+        fs::rename(&temporary, path)?;
+        Ok(socket)
+    });
+
+    fs::remove_dir_all(&dir).ok();
+
+    bound
+}
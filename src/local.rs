@@ -0,0 +1,102 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    sync::{
+        atomic::Ordering,
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+};
+
+use crate::{
+    data,
+    db::DB,
+    namespace,
+    subs::{self, Action::Call, Change},
+    supervisor::lock,
+};
+
+/// The data and the subscriptions of a server from the same process, without a
+/// connection. Keys are on the default namespace, and changes call the
+/// subscriptions like **s** and **!** do.
+#[derive(Clone)]
+pub struct Local {
+    map: Arc<Mutex<BTreeMap<String, Vec<u8>>>>,
+    db: DB,
+    subs_tx: Sender<subs::Action>,
+}
+
+impl Local {
+    pub(crate) fn new(
+        map: Arc<Mutex<BTreeMap<String, Vec<u8>>>>,
+        db: DB,
+        subs_tx: Sender<subs::Action>,
+    ) -> Local {
+        Local { map, db, subs_tx }
+    }
+
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        if !namespace::is_valid(key) {
+            return None;
+        }
+
+        lock(&self.map).get(key).cloned()
+    }
+
+    pub fn set(&self, key: &str, value: &[u8]) {
+        if !namespace::is_valid(key) {
+            return;
+        }
+
+        lock(&self.map).insert(key.to_owned(), value.to_owned());
+        self.db.modified.swap(true, Ordering::Relaxed);
+
+        self.call(key, value);
+    }
+
+    /// The value that was deleted.
+    pub fn delete(&self, key: &str) -> Option<Vec<u8>> {
+        if !namespace::is_valid(key) {
+            return None;
+        }
+
+        let value = lock(&self.map).remove(key);
+        if value.is_some() {
+            self.db.modified.swap(true, Ordering::Relaxed);
+        }
+
+        value
+    }
+
+    /// The JSON of the key without the full path, like **j**.
+    pub fn json(&self, key: &str) -> String {
+        data::jtrim(&lock(&self.map), key, usize::MAX)
+    }
+
+    /// Calls the subscriptions of the key and its parents without changing the
+    /// data, like **!**.
+    pub fn call(&self, key: &str, value: &[u8]) {
+        if !namespace::is_valid(key) {
+            return;
+        }
+
+        // Nobody is called after the server stopped.
+        let _ = self
+            .subs_tx
+            .send(Call(key.to_owned(), value.to_owned(), 0, 0));
+    }
+
+    /// The keys and values of the changes and calls to the key and its
+    /// children, until the receiver is dropped or the server stops.
+    pub fn subscribe(&self, key: &str) -> Receiver<Change> {
+        let (tx, rx) = channel();
+        let _ = self.subs_tx.send(subs::Action::Local(key.to_owned(), tx));
+
+        rx
+    }
+
+    /// Saves a snapshot right away, when there is persistence.
+    pub fn save(&self) -> io::Result<()> {
+        self.db.save_to_file()
+    }
+}
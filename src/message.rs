@@ -3,6 +3,12 @@ use std::{
     io::{self, Error, ErrorKind},
 };
 
+/// The most data a message can have after the header.
+pub const MAX_DATA: usize = 65535 - 6;
+
+/// The size of a missing value on **gl**, no value can be this big.
+const MISSING: u16 = u16::MAX;
+
 pub enum Received {
     None,
     Complete(Vec<u8>),
@@ -41,6 +47,7 @@ impl Message {
     }
 }
 
+#[derive(Default)]
 pub struct Messages {
     buffer: Vec<u8>,
 }
@@ -50,6 +57,10 @@ impl Messages {
         Messages { buffer: Vec::new() }
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
     /// Appends the data acting like a buffer to return complete messages
     /// assumming is part of the protocol. You need to call this function in a
     /// loop and retry when Received::Pending is returned.
@@ -102,6 +113,47 @@ impl Messages {
     }
 }
 
+/// The values of **gl**, each one with 2 bytes of size before it, or only the
+/// size 0xFFFF when the key doesn't exist.
+pub fn stamp_values(values: &[Option<&Vec<u8>>]) -> Vec<u8> {
+    let mut data = Vec::<u8>::new();
+
+    for value in values {
+        match value {
+            Some(value) => {
+                data.extend((value.len() as u16).to_be_bytes());
+                data.extend(*value);
+            }
+
+            None => data.extend(MISSING.to_be_bytes()),
+        }
+    }
+
+    data
+}
+
+/// The values of **gl**, None for the missing keys. None when the data isn't
+/// made of values.
+pub fn get_values(mut data: &[u8]) -> Option<Vec<Option<Vec<u8>>>> {
+    let mut values = Vec::new();
+
+    while !data.is_empty() {
+        let size = u16::from_be_bytes(data.get(..2)?.try_into().ok()?);
+        data = &data[2..];
+
+        if size == MISSING {
+            values.push(None);
+            continue;
+        }
+
+        let size = size as usize;
+        values.push(Some(data.get(..size)?.to_vec()));
+        data = &data[size..];
+    }
+
+    Some(values)
+}
+
 pub fn get_u32(bytes: &[u8]) -> u32 {
     (bytes[0] as u32) << 8 | bytes[1] as u32
 }
@@ -0,0 +1,62 @@
+/// Keys inside a namespace are stored as "namespace\x1Fkey", the default
+/// namespace doesn't have a prefix. Clients can't use the separator on keys.
+pub const SEPARATOR: char = '\u{1F}';
+
+/// The key as stored for the namespace.
+pub fn scope(namespace: &str, key: &str) -> String {
+    if namespace.is_empty() {
+        return key.into();
+    }
+
+    format!("{namespace}{SEPARATOR}{key}")
+}
+
+/// Scopes all the keys from a list of "key value" separated by a byte, like
+/// the one used by `sl`.
+pub fn scope_list(namespace: &str, separator: u8, list: &[u8]) -> Vec<u8> {
+    if namespace.is_empty() {
+        return list.into();
+    }
+
+    let mut scoped = Vec::<u8>::with_capacity(list.len());
+
+    for (i, key_val) in list.split(|x| *x == separator).enumerate() {
+        if i > 0 {
+            scoped.push(separator);
+        }
+
+        // Spaces before the key are ignored by the parser.
+        let start = key_val.iter().position(|x| *x != b' ');
+        let start = start.unwrap_or(key_val.len());
+
+        scoped.extend(namespace.as_bytes());
+        scoped.extend(SEPARATOR.to_string().as_bytes());
+        scoped.extend(&key_val[start..]);
+    }
+
+    scoped
+}
+
+/// "namespace\x1Fkey" -> ("namespace", "key"), and "key" -> ("", "key").
+pub fn split(key: &str) -> (&str, &str) {
+    match key.split_once(SEPARATOR) {
+        Some((namespace, key)) => (namespace, key),
+        None => ("", key),
+    }
+}
+
+/// The key without the namespace.
+pub fn strip(key: &str) -> &str {
+    split(key).1
+}
+
+/// True if the name is valid as a namespace, or as a key inside one.
+pub fn is_valid(name: &str) -> bool {
+    !name.contains(SEPARATOR)
+}
+
+/// True if the name is valid as a namespace. Dots aren't allowed, so the
+/// parents of a scoped key never leave its namespace.
+pub fn is_valid_name(name: &str) -> bool {
+    is_valid(name) && !name.contains('.')
+}
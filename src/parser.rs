@@ -1,28 +1,48 @@
-use core::fmt::{Debug, Display, Formatter, Result};
+use core::fmt::{self, Debug, Display, Formatter};
 use std::{
+    collections::HashMap,
     io::Cursor,
-    net::SocketAddr,
     sync::mpsc::{channel, Receiver, Sender},
 };
 
 use crate::{
+    auth::{self, Auth, Session},
+    cleaner,
     data::{
         self,
-        Action::{Append, Delete, Get, Inc, Json, Jtrim, KeyValue, Set, SetIfNone, SetList},
+        Action::{
+            Append, Children, Delete, Flush, Get, GetList, Inc, Json, JsonPatch, Jtrim, KeyValue,
+            Keys, MergePatch, Set, SetIfNone, SetJson, SetList,
+        },
     },
     message::Message,
+    namespace,
+    stream::Address,
     subs::{
         self,
-        Action::{Add, Call, Del},
+        Action::{Add, Call, CallDatagram, Del},
     },
+    supervisor::Error,
+    udp,
     writer::{self, Action::Queue, Order},
 };
 
+use serde_json::Value;
+
 const OK: &str = "OK";
 const NO: &str = "NO";
+const AUTH: &str = "AUTH";
+const DENIED: &str = "DENIED";
+
+/// Keys on a page of **gk** and **gc** without a size.
+const PAGE_SIZE: usize = 100;
 
 pub enum Action {
-    Parse(Message, SocketAddr),
+    Parse(Message, Address),
+    ParseDatagram(Message, Address),
+    Flush(Sender<()>),
+    Drop(usize),
+    Stop,
 }
 
 pub struct Parsed {
@@ -34,6 +54,9 @@ pub struct Parsed {
 #[derive(PartialEq, Debug)]
 pub enum Command {
     No,
+    Auth,
+    Namespace,
+    Udp,
     Set,
     SetIfNone,
     SetList,
@@ -41,44 +64,78 @@ pub enum Command {
     Append,
     Delete,
     Get,
+    GetList,
     KeyValue,
     Jtrim,
     Json,
+    SetJson,
+    ReplaceJson,
+    MergePatch,
+    JsonPatch,
+    Keys,
+    Children,
     SubGet,
     SubKeyValue,
+    SubFullKey,
     SubJson,
     Unsub,
     SubCall,
 }
 
 impl Display for Command {
-    fn fmt(&self, f: &mut Formatter) -> Result {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         Debug::fmt(self, f)
     }
 }
 
 pub struct Parser {
+    auth: Option<Auth>,
+    sessions: HashMap<usize, Session>,
+    failures: HashMap<usize, usize>,
+    namespaces: HashMap<usize, String>,
     pub tx: Sender<Action>,
     rx: Receiver<Action>,
 }
 
 impl Parser {
-    pub fn new() -> Parser {
+    pub fn new(auth: Option<Auth>) -> Parser {
+        let sessions = HashMap::<usize, Session>::new();
+        let failures = HashMap::<usize, usize>::new();
+        let namespaces = HashMap::<usize, String>::new();
         let (tx, rx) = channel::<Action>();
 
-        Parser { tx, rx }
+        Parser {
+            auth,
+            sessions,
+            failures,
+            namespaces,
+            tx,
+            rx,
+        }
     }
 
     pub fn handle(
-        &self,
+        &mut self,
         data_tx: Sender<data::Action>,
         writer_tx: Sender<writer::Action>,
         subs_tx: Sender<subs::Action>,
-    ) {
+        cleaner_tx: Sender<cleaner::Action>,
+        udp_tx: Option<Sender<udp::Action>>,
+    ) -> Result<(), Error> {
         loop {
-            match self.rx.recv().unwrap() {
+            match self.rx.recv()? {
                 Action::Parse(message, addr) => {
-                    let utf8 = String::from_utf8_lossy(&message.data);
+                    let parsed = parse(&message.data);
+                    let invalid = is_invalid(&parsed);
+                    let command = &parsed.command;
+                    let key = &parsed.key;
+                    let data = &parsed.data;
+
+                    // Never log credentials.
+                    let utf8 = match parsed.command {
+                        Command::Auth => "a ***".into(),
+                        _ => String::from_utf8_lossy(&message.data),
+                    };
                     let mut text = utf8.to_string();
 
                     let limit = 128;
@@ -94,188 +151,371 @@ impl Parser {
 
                     info!("{addr} ({} bytes): {text}", size);
 
-                    let parsed = parse(&message.data);
-                    let command = parsed.command;
-                    let key = parsed.key;
-                    let data = parsed.data;
-
-                    match command {
-                        // Commands that doesn't make sense without key.
-                        _ if key.is_empty() && needs_key(&command) => {
-                            writer_tx
-                                .send(Queue(Order {
-                                    from_id,
-                                    to_id: from_id,
-                                    msg_id,
-                                    data: NO.into(),
-                                }))
-                                .unwrap();
+                    // Only the auth command is allowed until authenticated.
+                    if let Some(auth) = &self.auth {
+                        if parsed.command == Command::Auth {
+                            let reply = match auth.login(from_id, key, data) {
+                                Some(session) => {
+                                    let user = session.user.as_deref().unwrap_or("secret");
+                                    info!("Connection #{from_id} authenticated as {user}");
+
+                                    self.failures.remove(&from_id);
+
+                                    if let Some(namespace) = &session.namespace {
+                                        self.namespaces.insert(from_id, namespace.to_owned());
+                                    }
+
+                                    self.sessions.insert(from_id, session);
+
+                                    OK
+                                }
+
+                                None => {
+                                    let failures = self.failures.entry(from_id).or_default();
+                                    *failures += 1;
+
+                                    if *failures >= auth::MAX_FAILURES {
+                                        info!(
+                                            "Connection #{from_id} closed, too many auth failures"
+                                        );
+                                        cleaner_tx.send(cleaner::Action::Drop(from_id))?;
+                                    }
+
+                                    NO
+                                }
+                            };
+
+                            writer_tx.send(Queue(Order {
+                                from_id,
+                                to_id: from_id,
+                                msg_id,
+                                data: reply.into(),
+                            }))?;
+
+                            continue;
+                        }
+
+                        if !self.sessions.contains_key(&from_id) {
+                            writer_tx.send(Queue(Order {
+                                from_id,
+                                to_id: from_id,
+                                msg_id,
+                                data: AUTH.into(),
+                            }))?;
+
+                            continue;
+                        }
+                    }
+
+                    // Access control, only when the session has rules.
+                    let rules = self.sessions.get(&from_id).and_then(|x| x.rules.as_ref());
+                    if let Some(rules) = rules {
+                        if !invalid && !rules.allows_command(command, key, data) {
+                            info!("Connection #{from_id} denied: {command} {key}");
+
+                            writer_tx.send(Queue(Order {
+                                from_id,
+                                to_id: from_id,
+                                msg_id,
+                                data: DENIED.into(),
+                            }))?;
+
+                            continue;
+                        }
+                    }
+
+                    // Keys are scoped to the connection namespace.
+                    let current = self.namespaces.get(&from_id).map(|x| x.as_str());
+                    let current = current.unwrap_or_default();
+
+                    match request(parsed, current) {
+                        // Commands that doesn't make sense without key, with
+                        // the reserved separator on them, or wrong arguments.
+                        Request::No => {
+                            writer_tx.send(Queue(Order {
+                                from_id,
+                                to_id: from_id,
+                                msg_id,
+                                data: NO.into(),
+                            }))?;
+                        }
+
+                        // Without auth configured, everyone is welcome.
+                        Request::Auth => {
+                            writer_tx.send(Queue(Order {
+                                from_id,
+                                to_id: from_id,
+                                msg_id,
+                                data: OK.into(),
+                            }))?;
                         }
 
-                        // No
-                        Command::No => {
-                            writer_tx
-                                .send(Queue(Order {
-                                    from_id,
-                                    to_id: from_id,
-                                    msg_id,
-                                    data: NO.into(),
-                                }))
-                                .unwrap();
+                        // Chooses the namespace for the next commands, empty for
+                        // the default one. Fixed when the user has one.
+                        Request::Namespace(key) => {
+                            let fixed = self.sessions.get(&from_id).map(|x| x.namespace.is_some());
+
+                            let reply = if fixed.unwrap_or(false) {
+                                DENIED
+                            } else if !namespace::is_valid_name(&key) {
+                                NO
+                            } else if key.is_empty() {
+                                self.namespaces.remove(&from_id);
+                                OK
+                            } else {
+                                self.namespaces.insert(from_id, key);
+                                OK
+                            };
+
+                            writer_tx.send(Queue(Order {
+                                from_id,
+                                to_id: from_id,
+                                msg_id,
+                                data: reply.into(),
+                            }))?;
+                        }
+
+                        // A token to bind a UDP endpoint to this connection.
+                        Request::Udp => {
+                            let reply = match &udp_tx {
+                                Some(udp_tx) => {
+                                    let token = udp::token(from_id);
+                                    udp_tx.send(udp::Action::Token(from_id, token.to_owned()))?;
+
+                                    token
+                                }
+
+                                None => NO.into(),
+                            };
+
+                            writer_tx.send(Queue(Order {
+                                from_id,
+                                to_id: from_id,
+                                msg_id,
+                                data: reply.into(),
+                            }))?;
                         }
 
                         // Set
-                        Command::Set => {
-                            writer_tx
-                                .send(Queue(Order {
-                                    from_id,
-                                    to_id: from_id,
-                                    msg_id,
-                                    data: OK.into(),
-                                }))
-                                .unwrap();
-
-                            subs_tx
-                                .send(Call(key.to_owned(), data.to_owned(), from_id, msg_id))
-                                .unwrap();
-
-                            data_tx.send(Set(key, data)).unwrap();
+                        Request::Set(key, data) => {
+                            writer_tx.send(Queue(Order {
+                                from_id,
+                                to_id: from_id,
+                                msg_id,
+                                data: OK.into(),
+                            }))?;
+
+                            subs_tx.send(Call(key.to_owned(), data.to_owned(), from_id, msg_id))?;
+
+                            data_tx.send(Set(key, data))?;
                         }
 
                         // Set only if the key doesn't exists.
-                        Command::SetIfNone => {
-                            writer_tx
-                                .send(Queue(Order {
-                                    from_id,
-                                    to_id: from_id,
-                                    msg_id,
-                                    data: OK.into(),
-                                }))
-                                .unwrap();
-
-                            data_tx.send(SetIfNone(key, data, from_id, msg_id)).unwrap();
+                        Request::SetIfNone(key, data) => {
+                            writer_tx.send(Queue(Order {
+                                from_id,
+                                to_id: from_id,
+                                msg_id,
+                                data: OK.into(),
+                            }))?;
+
+                            data_tx.send(SetIfNone(key, data, from_id, msg_id))?;
                         }
 
-                        Command::SetList => {
-                            writer_tx
-                                .send(Queue(Order {
-                                    from_id,
-                                    to_id: from_id,
-                                    msg_id,
-                                    data: OK.into(),
-                                }))
-                                .unwrap();
-
-                            data_tx.send(SetList(key, data, from_id, msg_id)).unwrap();
+                        // The key is the separator, and the keys are in the data.
+                        Request::SetList(separator, list) => {
+                            writer_tx.send(Queue(Order {
+                                from_id,
+                                to_id: from_id,
+                                msg_id,
+                                data: OK.into(),
+                            }))?;
+
+                            data_tx.send(SetList(separator, list, from_id, msg_id))?;
                         }
 
                         // Makes the value an integer and increase it in 1.
-                        Command::Inc => {
-                            data_tx.send(Inc(key, from_id, msg_id)).unwrap();
+                        Request::Inc(key) => {
+                            data_tx.send(Inc(key, from_id, msg_id))?;
                         }
 
                         // Appends the value.
-                        Command::Append => {
-                            writer_tx
-                                .send(Queue(Order {
-                                    from_id,
-                                    to_id: from_id,
-                                    msg_id,
-                                    data: OK.into(),
-                                }))
-                                .unwrap();
-
-                            data_tx.send(Append(key, data, from_id, msg_id)).unwrap();
+                        Request::Append(key, data) => {
+                            writer_tx.send(Queue(Order {
+                                from_id,
+                                to_id: from_id,
+                                msg_id,
+                                data: OK.into(),
+                            }))?;
+
+                            data_tx.send(Append(key, data, from_id, msg_id))?;
                         }
 
                         // Delete!
-                        Command::Delete => {
-                            writer_tx
-                                .send(Queue(Order {
-                                    from_id,
-                                    to_id: from_id,
-                                    msg_id,
-                                    data: OK.into(),
-                                }))
-                                .unwrap();
-
-                            data_tx.send(Delete(key)).unwrap();
+                        Request::Delete(key) => {
+                            writer_tx.send(Queue(Order {
+                                from_id,
+                                to_id: from_id,
+                                msg_id,
+                                data: OK.into(),
+                            }))?;
+
+                            data_tx.send(Delete(key))?;
                         }
 
                         // Get
-                        Command::Get => {
-                            data_tx.send(Get(key, from_id, msg_id)).unwrap();
+                        Request::Get(key) => {
+                            data_tx.send(Get(key, from_id, msg_id))?;
+                        }
+
+                        // The values of a list of keys: "gl | key1|key2"
+                        Request::GetList(separator, list) => {
+                            data_tx.send(GetList(separator, list, from_id, msg_id))?;
                         }
 
                         // 0x0 separated key value enumeration: key value\0x0key2 value2
-                        Command::KeyValue => {
-                            data_tx.send(KeyValue(key, from_id, msg_id)).unwrap();
+                        Request::KeyValue(key, depth) => {
+                            data_tx.send(KeyValue(key, depth, from_id, msg_id))?;
                         }
 
                         // Trimmed Json (just the data).
-                        Command::Jtrim => {
-                            data_tx.send(Jtrim(key, from_id, msg_id)).unwrap();
+                        Request::Jtrim(key, depth) => {
+                            data_tx.send(Jtrim(key, depth, from_id, msg_id))?;
                         }
 
                         // Json (full path).
-                        Command::Json => {
-                            data_tx.send(Json(key, from_id, msg_id)).unwrap();
+                        Request::Json(key, depth) => {
+                            data_tx.send(Json(key, depth, from_id, msg_id))?;
+                        }
+
+                        // Json into keys, merged with the key children, or
+                        // replacing them.
+                        Request::SetJson(key, kv, replace) => {
+                            writer_tx.send(Queue(Order {
+                                from_id,
+                                to_id: from_id,
+                                msg_id,
+                                data: OK.into(),
+                            }))?;
+
+                            data_tx.send(SetJson(key, kv, replace, from_id, msg_id))?;
+                        }
+
+                        // Pages of the keys of the key and its children.
+                        Request::Keys(key, size, after) => {
+                            data_tx.send(Keys(key, size, after, from_id, msg_id))?;
+                        }
+
+                        // Pages of its direct children.
+                        Request::Children(key, size, after) => {
+                            data_tx.send(Children(key, size, after, from_id, msg_id))?;
+                        }
+
+                        // Merge patch or JSON Patch on the JSON of the key, data
+                        // replies when it applies.
+                        Request::MergePatch(key, patch) => {
+                            data_tx.send(MergePatch(key, patch, from_id, msg_id))?;
+                        }
+
+                        Request::JsonPatch(key, patch) => {
+                            data_tx.send(JsonPatch(key, patch, from_id, msg_id))?;
                         }
 
                         // A generic "bite" subscription. Subscribers also receive their key: "key value"
                         // Also a first message if value is available.
-                        Command::SubGet | Command::SubKeyValue | Command::SubJson => {
-                            writer_tx
-                                .send(Queue(Order {
-                                    from_id,
-                                    to_id: from_id,
-                                    msg_id,
-                                    data: OK.into(),
-                                }))
-                                .unwrap();
-
-                            subs_tx.send(Add(key.to_owned(), from_id, command)).unwrap();
+                        Request::Sub(command, key, data) => {
+                            writer_tx.send(Queue(Order {
+                                from_id,
+                                to_id: from_id,
+                                msg_id,
+                                data: OK.into(),
+                            }))?;
+
+                            subs_tx.send(Add(key.to_owned(), from_id, command))?;
 
                             if !data.is_empty() {
-                                subs_tx.send(Call(key, data, from_id, msg_id)).unwrap()
+                                subs_tx.send(Call(key, data, from_id, msg_id))?
                             }
                         }
 
                         // A unsubscription and a last message if value is available.
-                        Command::Unsub => {
-                            writer_tx
-                                .send(Queue(Order {
-                                    from_id,
-                                    to_id: from_id,
-                                    msg_id,
-                                    data: OK.into(),
-                                }))
-                                .unwrap();
+                        Request::Unsub(key, data) => {
+                            writer_tx.send(Queue(Order {
+                                from_id,
+                                to_id: from_id,
+                                msg_id,
+                                data: OK.into(),
+                            }))?;
 
                             if !data.is_empty() {
-                                subs_tx
-                                    .send(Call(key.to_owned(), data, from_id, msg_id))
-                                    .unwrap();
+                                subs_tx.send(Call(key.to_owned(), data, from_id, msg_id))?;
                             }
 
-                            subs_tx.send(Del(key, from_id)).unwrap();
+                            subs_tx.send(Del(key, from_id))?;
                         }
 
                         // Calls key subscribers with the new value without data modifications.
-                        Command::SubCall => {
-                            writer_tx
-                                .send(Queue(Order {
-                                    from_id,
-                                    to_id: from_id,
-                                    msg_id,
-                                    data: OK.into(),
-                                }))
-                                .unwrap();
-
-                            subs_tx.send(Call(key, data, from_id, msg_id)).unwrap();
+                        Request::Call(key, data) => {
+                            writer_tx.send(Queue(Order {
+                                from_id,
+                                to_id: from_id,
+                                msg_id,
+                                data: OK.into(),
+                            }))?;
+
+                            subs_tx.send(CallDatagram(key, data, from_id, msg_id))?;
                         }
                     }
                 }
+
+                // Only fire and forget calls arrive as datagrams, and they
+                // don't have replies.
+                Action::ParseDatagram(message, addr) => {
+                    let from_id = message.from as usize;
+                    let msg_id = message.id as usize;
+
+                    let parsed = parse(&message.data);
+
+                    debug!("{addr} ({} bytes): ! {}", message.size, parsed.key);
+
+                    if parsed.command != Command::SubCall || is_invalid(&parsed) {
+                        continue;
+                    }
+
+                    if self.auth.is_some() && !self.sessions.contains_key(&from_id) {
+                        continue;
+                    }
+
+                    let rules = self.sessions.get(&from_id).and_then(|x| x.rules.as_ref());
+                    if let Some(rules) = rules {
+                        if !rules.allows_command(&Command::SubCall, &parsed.key, &parsed.data) {
+                            continue;
+                        }
+                    }
+
+                    let current = self.namespaces.get(&from_id).map(|x| x.as_str());
+
+                    if let Request::Call(key, data) = request(parsed, current.unwrap_or_default()) {
+                        subs_tx.send(CallDatagram(key, data, from_id, msg_id))?;
+                    }
+                }
+
+                // Everything parsed before this is already on data and subs.
+                Action::Flush(done) => data_tx.send(Flush(done))?,
+
+                // The server stopped.
+                Action::Stop => return Ok(()),
+
+                // The connection is gone, and the id could be reused.
+                Action::Drop(id) => {
+                    self.sessions.remove(&id);
+                    self.failures.remove(&id);
+                    self.namespaces.remove(&id);
+
+                    if let Some(udp_tx) = &udp_tx {
+                        udp_tx.send(udp::Action::Drop(id))?;
+                    }
+                }
             }
         }
     }
@@ -289,7 +529,7 @@ impl Parser {
 /// - `data`: The remaining data after command and key
 ///
 /// # Example
-/// ```
+/// ```ignore
 /// let msg = b"+ hello world is a pretty old meme";
 /// let parsed = parse(msg);
 /// assert_eq!(parsed.command, Command::Append);
@@ -300,9 +540,20 @@ pub fn parse(message: &[u8]) -> Parsed {
     let mut cursor = Cursor::new(message);
     let instruction = String::from_utf8_lossy(next_word(&mut cursor));
     let key = String::from_utf8_lossy(next_word(&mut cursor));
-    let data = remaining(&mut cursor);
 
-    let command = match instruction.to_lowercase().trim_end() {
+    // With a ":" after the command, the value comes after its size, so it's
+    // kept exactly as it is, even with spaces at the start.
+    let instruction = instruction.to_lowercase();
+    let (instruction, data) = match instruction.trim_end().strip_suffix(':') {
+        Some(instruction) => (instruction, sized(&mut cursor)),
+        None => (instruction.trim_end(), Some(remaining(&mut cursor))),
+    };
+
+    let command = match instruction {
+        _ if data.is_none() => Command::No,
+        "a" => Command::Auth,
+        "n" => Command::Namespace,
+        "u" => Command::Udp,
         "s" => Command::Set,
         "s?" => Command::SetIfNone,
         "sl" => Command::SetList,
@@ -310,11 +561,19 @@ pub fn parse(message: &[u8]) -> Parsed {
         "+" => Command::Append,
         "d" => Command::Delete,
         "g" => Command::Get,
+        "gl" => Command::GetList,
         "k" => Command::KeyValue,
         "j" => Command::Jtrim,
         "js" => Command::Json,
+        "sj" => Command::SetJson,
+        "sj!" => Command::ReplaceJson,
+        "jm" => Command::MergePatch,
+        "jp" => Command::JsonPatch,
+        "gk" => Command::Keys,
+        "gc" => Command::Children,
         "#g" => Command::SubGet,
         "#k" => Command::SubKeyValue,
+        "#f" => Command::SubFullKey,
         "#j" => Command::SubJson,
         "#-" => Command::Unsub,
         "!" => Command::SubCall,
@@ -326,23 +585,195 @@ pub fn parse(message: &[u8]) -> Parsed {
     Parsed {
         command,
         key,
-        data: data.into(),
+        data: data.unwrap_or_default().into(),
     }
 }
 
-pub fn needs_key(command: &Command) -> bool {
+/// A command with its arguments parsed, and its keys scoped to a namespace.
+/// What the parser sends to data and subs, without the ids.
+#[derive(Debug)]
+pub enum Request {
+    /// Unknown commands, keys missing or with the namespace separator, and
+    /// arguments that aren't valid.
+    No,
+    Auth,
+    Namespace(String),
+    Udp,
+    Set(String, Vec<u8>),
+    SetIfNone(String, Vec<u8>),
+    SetList(String, Vec<u8>),
+    Inc(String),
+    Append(String, Vec<u8>),
+    Delete(String),
+    Get(String),
+    GetList(String, Vec<u8>),
+    KeyValue(String, usize),
+    Jtrim(String, usize),
+    Json(String, usize),
+    SetJson(String, Vec<(String, Vec<u8>)>, bool),
+    MergePatch(String, Value),
+    JsonPatch(String, Value),
+    Keys(String, usize, Option<String>),
+    Children(String, usize, Option<String>),
+    Sub(Command, String, Vec<u8>),
+    Unsub(String, Vec<u8>),
+    Call(String, Vec<u8>),
+}
+
+/// True when the key is missing, or the reserved separator is on it or on
+/// the keys of a list.
+pub fn is_invalid(parsed: &Parsed) -> bool {
+    let Parsed { command, key, data } = parsed;
+
+    if key.is_empty() && needs_key(command) {
+        return true;
+    }
+
+    !namespace::is_valid(key)
+        || match (command, key.as_bytes().first()) {
+            (Command::SetList | Command::GetList, Some(separator)) => {
+                data::split_list(*separator, data)
+                    .iter()
+                    .any(|(key, _)| !namespace::is_valid(key))
+            }
+
+            _ => false,
+        }
+}
+
+/// The request for the command, with the keys scoped to the namespace. It
+/// doesn't depend on anything else, so it can be fuzzed.
+pub fn request(parsed: Parsed, current: &str) -> Request {
+    if is_invalid(&parsed) {
+        return Request::No;
+    }
+
+    let Parsed { command, key, data } = parsed;
+
+    // A dot at the end is ignored, so "." is everything.
+    let tree = || namespace::scope(current, key.trim_end_matches('.'));
+    let scoped = || namespace::scope(current, &key);
+
     match command {
-        Command::No | Command::KeyValue | Command::Jtrim | Command::Json => false,
+        Command::No => Request::No,
+        Command::Auth => Request::Auth,
+        Command::Namespace => Request::Namespace(key),
+        Command::Udp => Request::Udp,
+        Command::Set => Request::Set(scoped(), data),
+        Command::SetIfNone => Request::SetIfNone(scoped(), data),
+        Command::Inc => Request::Inc(scoped()),
+        Command::Append => Request::Append(scoped(), data),
+        Command::Delete => Request::Delete(scoped()),
+        Command::Get => Request::Get(scoped()),
+
+        // The key is the separator, and the keys are in the data.
+        Command::SetList | Command::GetList => {
+            let list = namespace::scope_list(current, key.as_bytes()[0], &data);
+
+            match command {
+                Command::SetList => Request::SetList(key, list),
+                _ => Request::GetList(key, list),
+            }
+        }
+
+        // Queries on the key and its children, up to a depth below the key
+        // when there is one: "k key depth"
+        Command::KeyValue | Command::Jtrim | Command::Json => {
+            let depth = String::from_utf8_lossy(&data);
+            let depth = match depth.trim() {
+                "" => Some(usize::MAX),
+                depth => depth.parse::<usize>().ok(),
+            };
+
+            match (depth, command) {
+                (Some(depth), Command::KeyValue) => Request::KeyValue(tree(), depth),
+                (Some(depth), Command::Jtrim) => Request::Jtrim(tree(), depth),
+                (Some(depth), _) => Request::Json(tree(), depth),
+                (None, _) => Request::No,
+            }
+        }
 
-        Command::Set
+        // Json into keys, merged with the key children, or replacing them.
+        Command::SetJson | Command::ReplaceJson => {
+            let key = scoped();
+            let kv = serde_json::from_slice::<Value>(&data)
+                .ok()
+                .and_then(|json| data::json_to_kv(&key, &json));
+
+            match kv {
+                Some(kv) => Request::SetJson(key, kv, command == Command::ReplaceJson),
+                None => Request::No,
+            }
+        }
+
+        Command::MergePatch | Command::JsonPatch => match serde_json::from_slice::<Value>(&data) {
+            Ok(patch) if command == Command::MergePatch => Request::MergePatch(scoped(), patch),
+            Ok(patch) => Request::JsonPatch(scoped(), patch),
+            Err(_) => Request::No,
+        },
+
+        // Pages of the keys of the key and its children, or only its direct
+        // children: "gk key size after"
+        Command::Keys | Command::Children => {
+            let mut cursor = Cursor::new(&data[..]);
+            let size = String::from_utf8_lossy(next_word(&mut cursor));
+            let after = String::from_utf8_lossy(next_word(&mut cursor));
+
+            let size = match size.as_ref() {
+                "" => Some(PAGE_SIZE),
+                size => size.parse::<usize>().ok().filter(|x| *x > 0),
+            };
+
+            let after = match after.as_ref() {
+                "" => None,
+                after => Some(namespace::scope(current, after)),
+            };
+
+            match size {
+                Some(size) if command == Command::Children => {
+                    Request::Children(tree(), size, after)
+                }
+                Some(size) => Request::Keys(tree(), size, after),
+                None => Request::No,
+            }
+        }
+
+        Command::SubGet | Command::SubKeyValue | Command::SubFullKey | Command::SubJson => {
+            Request::Sub(command, scoped(), data)
+        }
+
+        Command::Unsub => Request::Unsub(scoped(), data),
+        Command::SubCall => Request::Call(scoped(), data),
+    }
+}
+
+pub fn needs_key(command: &Command) -> bool {
+    match command {
+        Command::No
+        | Command::Namespace
+        | Command::Udp
+        | Command::KeyValue
+        | Command::Jtrim
+        | Command::Json
+        | Command::Keys
+        | Command::Children => false,
+
+        Command::Auth
+        | Command::Set
         | Command::SetIfNone
         | Command::SetList
         | Command::Inc
         | Command::Append
         | Command::Delete
         | Command::Get
+        | Command::GetList
+        | Command::SetJson
+        | Command::ReplaceJson
+        | Command::MergePatch
+        | Command::JsonPatch
         | Command::SubGet
         | Command::SubKeyValue
+        | Command::SubFullKey
         | Command::SubJson
         | Command::Unsub
         | Command::SubCall => true,
@@ -425,6 +856,16 @@ pub fn remaining<'a>(src: &mut Cursor<&'a [u8]>) -> &'a [u8] {
     &src.get_ref()[start..end]
 }
 
+/// The rest of the message after a space, when it's a value after its size in
+/// 2 bytes, big endian, like on **gl**.
+fn sized<'a>(src: &mut Cursor<&'a [u8]>) -> Option<&'a [u8]> {
+    let rest = &src.get_ref()[src.position() as usize..];
+    let rest = rest.strip_prefix(b" ")?;
+    let size = u16::from_be_bytes(rest.get(..2)?.try_into().ok()?) as usize;
+
+    (rest.len() == size + 2).then(|| &rest[2..])
+}
+
 fn is_newline(c: u8) -> bool {
     c == b'\r' || c == b'\n'
 }
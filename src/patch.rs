@@ -0,0 +1,145 @@
+use serde_json::{Map, Value};
+
+/// Applies a JSON merge patch, RFC 7386. Nulls remove, objects merge, and
+/// anything else replaces.
+pub fn merge(target: &mut Value, patch: &Value) {
+    let patch = match patch.as_object() {
+        Some(patch) => patch,
+        None => {
+            *target = patch.to_owned();
+            return;
+        }
+    };
+
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+
+    let object = target.as_object_mut().unwrap();
+    for (field, value) in patch {
+        if value.is_null() {
+            object.remove(field);
+        } else {
+            merge(object.entry(field).or_insert(Value::Null), value);
+        }
+    }
+}
+
+/// Applies the operations of a JSON Patch, RFC 6902, all of them or none.
+pub fn apply(target: &mut Value, operations: &Value) -> Result<(), String> {
+    let operations = operations.as_array().ok_or("the patch isn't an array")?;
+
+    let mut patched = target.to_owned();
+    for operation in operations {
+        apply_one(&mut patched, operation)?;
+    }
+
+    *target = patched;
+
+    Ok(())
+}
+
+fn apply_one(target: &mut Value, operation: &Value) -> Result<(), String> {
+    let field = |name: &str| operation.get(name).ok_or(format!("missing {name}"));
+    let pointer = |name: &str| match field(name)?.as_str() {
+        Some(pointer) => Ok(pointer),
+        None => Err(format!("{name} isn't a string")),
+    };
+
+    let path = pointer("path")?;
+
+    match field("op")?.as_str() {
+        Some("add") => add(target, path, field("value")?.to_owned()),
+
+        Some("remove") => remove(target, path).map(|_| ()),
+
+        Some("replace") => {
+            remove(target, path)?;
+            add(target, path, field("value")?.to_owned())
+        }
+
+        Some("move") => {
+            let from = pointer("from")?;
+            if path.starts_with(from) && path[from.len()..].starts_with('/') {
+                return Err(format!("can't move {from} inside itself"));
+            }
+
+            let value = remove(target, from)?;
+            add(target, path, value)
+        }
+
+        Some("copy") => {
+            let from = pointer("from")?;
+            let value = target.pointer(from).ok_or(format!("{from} not found"))?;
+            add(target, path, value.to_owned())
+        }
+
+        Some("test") => match target.pointer(path) {
+            Some(value) if value == field("value")? => Ok(()),
+            _ => Err(format!("test on {path} failed")),
+        },
+
+        _ => Err("unknown op".into()),
+    }
+}
+
+/// The parent pointer and the last token, unescaped.
+fn split(path: &str) -> Result<(&str, String), String> {
+    match path.rfind('/') {
+        Some(slash) => {
+            let token = path[slash + 1..].replace("~1", "/").replace("~0", "~");
+            Ok((&path[..slash], token))
+        }
+
+        None => Err(format!("{path} isn't a pointer")),
+    }
+}
+
+fn add(target: &mut Value, path: &str, value: Value) -> Result<(), String> {
+    if path.is_empty() {
+        *target = value;
+        return Ok(());
+    }
+
+    let (parent, token) = split(path)?;
+    let parent = target
+        .pointer_mut(parent)
+        .ok_or(format!("{path} has no parent"))?;
+
+    match parent {
+        Value::Object(object) => {
+            object.insert(token, value);
+        }
+
+        Value::Array(array) if token == "-" => array.push(value),
+
+        Value::Array(array) => match token.parse::<usize>() {
+            Ok(i) if i <= array.len() => array.insert(i, value),
+            _ => return Err(format!("{path} is out of the array")),
+        },
+
+        _ => return Err(format!("{path} has no parent")),
+    }
+
+    Ok(())
+}
+
+fn remove(target: &mut Value, path: &str) -> Result<Value, String> {
+    if path.is_empty() {
+        return Ok(target.take());
+    }
+
+    let (parent, token) = split(path)?;
+    let removed = match target.pointer_mut(parent) {
+        Some(Value::Object(object)) => object.remove(&token),
+
+        Some(Value::Array(array)) => match token.parse::<usize>() {
+            Ok(i) if i < array.len() => Some(array.remove(i)),
+            _ => None,
+        },
+
+        _ => None,
+    };
+
+    removed.ok_or(format!("{path} not found"))
+}
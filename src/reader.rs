@@ -11,13 +11,19 @@ use crate::{
     cleaner,
     connection::Connection,
     message::{Message, Messages, Received},
-    parser::{self, Action::Parse},
+    parser::{
+        self,
+        Action::{Flush, Parse},
+    },
+    supervisor::{lock, Error},
 };
 
 use polling::{Event, Poller};
 
 pub enum Action {
     Read(usize),
+    Flush(Sender<()>),
+    Stop,
 }
 
 pub struct Reader {
@@ -46,13 +52,13 @@ impl Reader {
         &mut self,
         parser_tx: Sender<parser::Action>,
         cleaner_tx: Sender<cleaner::Action>,
-    ) {
+    ) -> Result<(), Error> {
         loop {
-            match self.rx.recv().unwrap() {
+            match self.rx.recv()? {
                 Action::Read(id) => {
                     let mut closed = false;
 
-                    if let Some(connection) = self.readers.lock().unwrap().get_mut(&id) {
+                    if let Some(connection) = lock(&self.readers).get_mut(&id) {
                         loop {
                             // Loop because "received" could have more than one
                             // message in the same read.
@@ -71,7 +77,12 @@ impl Reader {
                             };
 
                             let mut pending = false;
-                            let messages = self.messages.entry(id).or_insert_with(Messages::new);
+                            let messages = self.messages.entry(id).or_default();
+
+                            // Nothing new, like TLS handshakes without data.
+                            if data.is_empty() && messages.is_empty() {
+                                break;
+                            }
 
                             let received = match messages.feed(data) {
                                 Received::None => break,
@@ -120,27 +131,36 @@ impl Reader {
                                 }
                             };
 
-                            parser_tx.send(Parse(message, connection.addr)).unwrap();
+                            parser_tx.send(Parse(message, connection.addr.clone()))?;
 
                             if !pending {
                                 break;
                             }
                         }
 
-                        if connection.closed {
-                            closed = true;
-                        } else {
-                            self.poller
-                                .modify(&connection.socket, Event::readable(id))
-                                .unwrap();
+                        if !connection.closed {
+                            let event = Event::readable(id);
+                            if let Err(err) = self.poller.modify(&connection.socket, event) {
+                                connection.closed = true;
+
+                                info!("Connection #{id} closed, poll failed: {err}");
+                            }
                         }
+
+                        closed = connection.closed;
                     }
 
                     if closed {
                         self.messages.remove(&id);
-                        cleaner_tx.send(cleaner::Action::Drop(id)).unwrap();
+                        cleaner_tx.send(cleaner::Action::Drop(id))?;
                     }
                 }
+
+                // Everything read before this is already on the parser.
+                Action::Flush(done) => parser_tx.send(Flush(done))?,
+
+                // The server stopped.
+                Action::Stop => return Ok(()),
             }
         }
     }
@@ -0,0 +1,629 @@
+use std::{
+    cmp::Reverse,
+    collections::VecDeque,
+    io::{
+        self,
+        ErrorKind::{BrokenPipe, WouldBlock},
+        Read, Write,
+    },
+    net::{Shutdown, TcpStream},
+};
+
+use crate::{
+    client::{get_list, sized_command},
+    data::counter,
+    message::{get_values, stamp_header},
+    namespace::is_valid,
+};
+
+const MAX_ARGS: usize = 1024;
+const MAX_LINE: usize = 65535;
+const BUFFER_SIZE: usize = 4096;
+
+/// The arguments of a command and its size on the input.
+type Parsed = Option<(Vec<Vec<u8>>, usize)>;
+
+/// What a command expects from the replies of its messages.
+enum Kind {
+    Get,
+    Ok,
+    SetNx,
+    Incr,
+    Append,
+    Del,
+    Publish,
+    Select,
+    Auth,
+    Subscribe(Vec<String>),
+    Unsubscribe(Vec<String>),
+    Reply(Vec<u8>),
+}
+
+struct Request {
+    kind: Kind,
+    msg_ids: Vec<u16>,
+    replies: Vec<Option<Vec<u8>>>,
+    close: bool,
+}
+
+/// Redis RESP state for a connection. A subset of the Redis commands become
+/// BITE messages for the reader, and their replies from the writer become RESP
+/// replies, in the same order.
+pub struct Resp {
+    id: usize,
+    msg_id: u16,
+    input: Vec<u8>,
+    frames: Vec<u8>,
+    replies: Vec<u8>,
+    output: Vec<u8>,
+    pending: VecDeque<Request>,
+    channels: Vec<String>,
+    call: Option<(String, Vec<String>)>,
+    closing: bool,
+    closed: bool,
+}
+
+impl Resp {
+    pub fn new(id: usize) -> Resp {
+        Resp {
+            id,
+            msg_id: 0,
+            input: Vec::new(),
+            frames: Vec::new(),
+            replies: Vec::new(),
+            output: Vec::new(),
+            pending: VecDeque::new(),
+            channels: Vec::new(),
+            call: None,
+            closing: false,
+            closed: false,
+        }
+    }
+
+    /// True when there are replies waiting for the socket.
+    pub fn wants_write(&self) -> bool {
+        !self.output.is_empty()
+    }
+
+    pub fn read(&mut self, socket: &mut TcpStream, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if !self.frames.is_empty() {
+                let n = buf.len().min(self.frames.len());
+                buf[..n].copy_from_slice(&self.frames[..n]);
+                self.frames.drain(..n);
+
+                return Ok(n);
+            }
+
+            if self.closed {
+                return Ok(0);
+            }
+
+            let mut chunk = [0; BUFFER_SIZE];
+            let n = socket.read(&mut chunk)?;
+            if n == 0 {
+                return Ok(0);
+            }
+
+            if self.closing {
+                continue;
+            }
+
+            self.input.extend_from_slice(&chunk[..n]);
+            self.commands();
+
+            // Replies that don't need the server.
+            self.respond();
+            self.flush(socket)?;
+        }
+    }
+
+    pub fn write(&mut self, socket: &mut TcpStream, buf: &[u8]) -> io::Result<usize> {
+        // Replies still waiting means the socket is full.
+        self.flush(socket)?;
+        if self.wants_write() {
+            return Err(WouldBlock.into());
+        }
+
+        self.replies.extend_from_slice(buf);
+        self.decode();
+        self.respond();
+        self.flush(socket)?;
+
+        Ok(buf.len())
+    }
+
+    /// Writes the replies that are waiting until the socket would block.
+    pub fn flush(&mut self, socket: &mut TcpStream) -> io::Result<()> {
+        while !self.output.is_empty() {
+            match socket.write(&self.output) {
+                Ok(0) => return Err(BrokenPipe.into()),
+                Ok(n) => drop(self.output.drain(..n)),
+                Err(err) if err.kind() == WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        // After QUIT, the client will close its side.
+        if self.closing && !self.closed && self.output.is_empty() && self.pending.is_empty() {
+            self.closed = true;
+            let _ = socket.shutdown(Shutdown::Write);
+        }
+
+        Ok(())
+    }
+
+    /// Moves the complete commands from the input to frames.
+    fn commands(&mut self) {
+        while !self.closing {
+            let args = match parse_command(&self.input) {
+                Ok(Some((args, size))) => {
+                    self.input.drain(..size);
+                    args
+                }
+
+                Ok(None) => return,
+
+                Err(err) => {
+                    self.input.clear();
+                    self.reply(error(&format!("ERR Protocol error: {err}")), true);
+
+                    return;
+                }
+            };
+
+            if args.is_empty() {
+                continue;
+            }
+
+            let name = String::from_utf8_lossy(&args[0]).to_uppercase();
+            let (kind, frames) = match self.command(&name, &args[1..]) {
+                Ok(command) => command,
+                Err(reply) => {
+                    self.reply(reply, name == "QUIT");
+                    continue;
+                }
+            };
+
+            if frames.iter().any(|x| x.len() + 6 > MAX_LINE) {
+                self.reply(error("ERR value is too big"), false);
+                continue;
+            }
+
+            let mut msg_ids = Vec::<u16>::new();
+            for frame in frames {
+                self.msg_id = self.msg_id.checked_add(1).unwrap_or(1);
+                msg_ids.push(self.msg_id);
+
+                self.frames
+                    .extend(stamp_header(frame, self.id as u32, self.msg_id as u32));
+            }
+
+            self.pending.push_back(Request {
+                kind,
+                replies: vec![None; msg_ids.len()],
+                msg_ids,
+                close: false,
+            });
+        }
+    }
+
+    /// The messages for a command, or its reply when it doesn't need them.
+    fn command(&self, name: &str, args: &[Vec<u8>]) -> Result<(Kind, Vec<Vec<u8>>), Vec<u8>> {
+        let subscribed = !self.channels.is_empty();
+
+        match (name, args.len()) {
+            ("PING", 0) => Err(b"+PONG\r\n".to_vec()),
+            ("PING", 1) | ("ECHO", 1) => Err(bulk(&args[0])),
+            ("QUIT", 0) => Err(b"+OK\r\n".to_vec()),
+
+            ("SUBSCRIBE", 1..) => {
+                let channels = keys(args)?;
+                let frames = channels.iter().map(|x| message("#f", x, &[])).collect();
+
+                Ok((Kind::Subscribe(channels), frames))
+            }
+
+            ("UNSUBSCRIBE", _) => {
+                let channels = match args.len() {
+                    0 => self.channels.to_owned(),
+                    _ => keys(args)?,
+                };
+                let frames = channels.iter().map(|x| message("#-", x, &[])).collect();
+
+                Ok((Kind::Unsubscribe(channels), frames))
+            }
+
+            _ if subscribed => Err(error(&format!(
+                "ERR Can't execute '{}': only (UN)SUBSCRIBE / PING / QUIT are allowed in this context",
+                name.to_lowercase()
+            ))),
+
+            ("AUTH", 1) => Ok((Kind::Auth, vec![message("a", "", &args[0])])),
+            ("AUTH", 2) => {
+                let user = key(&args[0])?;
+                Ok((Kind::Auth, vec![message("a", &user, &args[1])]))
+            }
+
+            ("SELECT", 1) => {
+                let namespace = key(&args[0])?;
+                let namespace = if namespace == "0" { "" } else { &namespace };
+
+                Ok((Kind::Select, vec![message("n", namespace, &[])]))
+            }
+
+            ("GET", 1) => Ok((Kind::Get, vec![get(&key(&args[0])?)?])),
+
+            ("SET", 2) => Ok((Kind::Ok, vec![sized_command("s", &key(&args[0])?, &args[1])])),
+
+            ("SETNX", 2) => {
+                let key = key(&args[0])?;
+                let frames = vec![get(&key)?, sized_command("s?", &key, &args[1])];
+
+                Ok((Kind::SetNx, frames))
+            }
+
+            ("INCR", 1) => Ok((Kind::Incr, vec![message("+1", &key(&args[0])?, &[])])),
+
+            ("APPEND", 2) => {
+                let key = key(&args[0])?;
+                let frames = vec![sized_command("+", &key, &args[1]), get(&key)?];
+
+                Ok((Kind::Append, frames))
+            }
+
+            ("DEL", 1..) => {
+                let mut frames = Vec::<Vec<u8>>::new();
+
+                for key in keys(args)? {
+                    frames.push(get(&key)?);
+                    frames.push(message("d", &key, &[]));
+                }
+
+                Ok((Kind::Del, frames))
+            }
+
+            ("PUBLISH", 2) => {
+                let frames = vec![sized_command("!", &key(&args[0])?, &args[1])];
+                Ok((Kind::Publish, frames))
+            }
+
+            ("PING" | "ECHO" | "QUIT" | "AUTH" | "SELECT" | "GET" | "SET" | "SETNX", _)
+            | ("INCR" | "APPEND" | "DEL" | "PUBLISH" | "SUBSCRIBE", _) => Err(error(&format!(
+                "ERR wrong number of arguments for '{}' command",
+                name.to_lowercase()
+            ))),
+
+            _ => Err(error(&format!(
+                "ERR unknown command '{}'",
+                name.to_lowercase()
+            ))),
+        }
+    }
+
+    /// Moves the replies of complete frames to their commands, and the calls of
+    /// the subscriptions to messages.
+    fn decode(&mut self) {
+        while self.replies.len() >= 6 {
+            let from = u16::from_be_bytes([self.replies[0], self.replies[1]]) as usize;
+            let msg_id = u16::from_be_bytes([self.replies[2], self.replies[3]]);
+            let size = u16::from_be_bytes([self.replies[4], self.replies[5]]) as usize;
+
+            if size < 6 || self.replies.len() < size {
+                return;
+            }
+
+            let data = self.replies[6..size].to_vec();
+            self.replies.drain(..size);
+
+            // The id message and pings.
+            if (msg_id == 0 && from == self.id) || (from == 0 && data.is_empty()) {
+                continue;
+            }
+
+            // Replies come from the connection, calls from anyone.
+            let own = from == self.id;
+            let request = self
+                .pending
+                .iter_mut()
+                .filter(|_| own)
+                .find_map(|x| x.msg_ids.iter().position(|y| *y == msg_id).map(|i| (x, i)));
+
+            match request {
+                Some((request, i)) => request.replies[i] = Some(data),
+                None => {
+                    if let Some(message) = self.published(&data) {
+                        self.output.extend(message);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Writes the replies that are ready, in the order of the commands.
+    fn respond(&mut self) {
+        while let Some(request) = self.pending.front() {
+            if request.replies.iter().any(|x| x.is_none()) {
+                return;
+            }
+
+            let request = self.pending.pop_front().unwrap();
+            let replies: Vec<Vec<u8>> = request.replies.into_iter().flatten().collect();
+
+            let reply = self.render(request.kind, &replies);
+            self.output.extend(reply);
+
+            if request.close {
+                self.closing = true;
+                self.pending.clear();
+            }
+        }
+    }
+
+    /// Values come from **gl**, so they are never one of the errors checked
+    /// here.
+    fn render(&mut self, kind: Kind, replies: &[Vec<u8>]) -> Vec<u8> {
+        if replies.iter().any(|x| x == b"AUTH") {
+            return error("NOAUTH Authentication required.");
+        }
+
+        if replies.iter().any(|x| x == b"DENIED") {
+            return error("NOPERM this user has no permissions to access this key");
+        }
+
+        match kind {
+            Kind::Reply(reply) => reply,
+
+            Kind::Auth if replies[0] == b"OK" => b"+OK\r\n".to_vec(),
+            Kind::Auth => error("WRONGPASS invalid username-password pair"),
+
+            _ if replies.iter().any(|x| x == b"NO") => error("ERR invalid key"),
+
+            Kind::Ok | Kind::Select => b"+OK\r\n".to_vec(),
+
+            // The 8 bytes of **+1** as the text of the number, like **INCR**
+            // counters are on Redis.
+            Kind::Get => match value(&replies[0]) {
+                Some(value) => match counter(&value) {
+                    Some(number) => bulk(number.to_string().as_bytes()),
+                    None => bulk(&value),
+                },
+                None => b"$-1\r\n".to_vec(),
+            },
+
+            Kind::SetNx => integer(value(&replies[0]).is_none() as u64),
+
+            Kind::Incr => {
+                let mut bytes = [0; 8];
+                let len = replies[0].len().min(8);
+                bytes[8 - len..].copy_from_slice(&replies[0][..len]);
+
+                integer(u64::from_be_bytes(bytes))
+            }
+
+            Kind::Append => integer(value(&replies[1]).unwrap_or_default().len() as u64),
+
+            Kind::Del => {
+                let deleted = replies.iter().step_by(2).filter_map(|x| value(x));
+                integer(deleted.count() as u64)
+            }
+
+            // Subscribers aren't counted.
+            Kind::Publish => integer(0),
+
+            Kind::Subscribe(channels) => {
+                let mut reply = Vec::<u8>::new();
+
+                for channel in channels {
+                    if !self.channels.contains(&channel) {
+                        self.channels.push(channel.to_owned());
+                    }
+
+                    reply.extend(b"*3\r\n");
+                    reply.extend(bulk(b"subscribe"));
+                    reply.extend(bulk(channel.as_bytes()));
+                    reply.extend(integer(self.channels.len() as u64));
+                }
+
+                reply
+            }
+
+            Kind::Unsubscribe(channels) => {
+                let mut reply = Vec::<u8>::new();
+
+                for channel in channels.iter() {
+                    self.channels.retain(|x| x != channel);
+
+                    reply.extend(b"*3\r\n");
+                    reply.extend(bulk(b"unsubscribe"));
+                    reply.extend(bulk(channel.as_bytes()));
+                    reply.extend(integer(self.channels.len() as u64));
+                }
+
+                if channels.is_empty() {
+                    reply.extend(b"*3\r\n");
+                    reply.extend(bulk(b"unsubscribe"));
+                    reply.extend(b"$-1\r\n:0\r\n");
+                }
+
+                reply
+            }
+        }
+    }
+
+    /// A message for a subscription call, "key value" from **#f**, on the
+    /// channel of the key or its parent. A call arrives once for each channel
+    /// that includes the key, the longest first, so they take turns.
+    fn published(&mut self, data: &[u8]) -> Option<Vec<u8>> {
+        let (key, value) = match data.iter().position(|x| *x == b' ') {
+            Some(i) => (&data[..i], &data[i + 1..]),
+            None => (data, &[][..]),
+        };
+        let key = String::from_utf8_lossy(key);
+
+        let channel = match &mut self.call {
+            Some((call, rest)) if *call == key && !rest.is_empty() => rest.remove(0),
+
+            _ => {
+                let mut channels: Vec<String> = self
+                    .channels
+                    .iter()
+                    .filter(|x| *x == &key || key.starts_with(&format!("{x}.")))
+                    .cloned()
+                    .collect();
+                channels.sort_by_key(|x| Reverse(x.len()));
+
+                let channel = channels.first()?.to_owned();
+                self.call = Some((key.into(), channels.split_off(1)));
+
+                channel
+            }
+        };
+
+        let mut message = b"*3\r\n".to_vec();
+        message.extend(bulk(b"message"));
+        message.extend(bulk(channel.as_bytes()));
+        message.extend(bulk(value));
+
+        Some(message)
+    }
+
+    /// A reply for a command that never reaches the server.
+    fn reply(&mut self, reply: Vec<u8>, close: bool) {
+        self.pending.push_back(Request {
+            kind: Kind::Reply(reply),
+            msg_ids: Vec::new(),
+            replies: Vec::new(),
+            close,
+        });
+    }
+}
+
+/// The arguments and the size of the first command, or None when it isn't
+/// complete yet. Inline commands, like from telnet, are also fine.
+fn parse_command(input: &[u8]) -> Result<Parsed, &'static str> {
+    let (line, mut cursor) = match read_line(input, 0)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+
+    if line.first() != Some(&b'*') {
+        let args = line
+            .split(|x| x.is_ascii_whitespace())
+            .filter(|x| !x.is_empty())
+            .map(|x| x.to_vec())
+            .collect();
+
+        return Ok(Some((args, cursor)));
+    }
+
+    let count = number(&line[1..])?;
+    if count > MAX_ARGS {
+        return Err("too many arguments");
+    }
+
+    let mut args = Vec::<Vec<u8>>::with_capacity(count);
+
+    for _ in 0..count {
+        let (line, next) = match read_line(input, cursor)? {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+
+        if line.first() != Some(&b'$') {
+            return Err("expected '$'");
+        }
+
+        let len = number(&line[1..])?;
+        if len > MAX_LINE {
+            return Err("invalid bulk length");
+        }
+
+        if input.len() < next + len + 2 {
+            return Ok(None);
+        }
+
+        if &input[next + len..next + len + 2] != b"\r\n" {
+            return Err("expected CRLF");
+        }
+
+        args.push(input[next..next + len].to_vec());
+        cursor = next + len + 2;
+    }
+
+    Ok(Some((args, cursor)))
+}
+
+/// The line starting at the cursor without the CRLF, and where the next starts.
+fn read_line(input: &[u8], cursor: usize) -> Result<Option<(&[u8], usize)>, &'static str> {
+    match input[cursor..].windows(2).position(|x| x == b"\r\n") {
+        Some(end) => Ok(Some((&input[cursor..cursor + end], cursor + end + 2))),
+        None if input.len() - cursor > MAX_LINE => Err("line too long"),
+        None => Ok(None),
+    }
+}
+
+fn number(bytes: &[u8]) -> Result<usize, &'static str> {
+    std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .ok_or("invalid length")
+}
+
+/// A key without spaces, BITE uses them to separate the value.
+fn key(arg: &[u8]) -> Result<String, Vec<u8>> {
+    match std::str::from_utf8(arg) {
+        Ok(key) if !key.is_empty() && !key.contains(char::is_whitespace) && is_valid(key) => {
+            Ok(key.to_owned())
+        }
+        _ => Err(error("ERR invalid key")),
+    }
+}
+
+fn keys(args: &[Vec<u8>]) -> Result<Vec<String>, Vec<u8>> {
+    args.iter().map(|x| key(x)).collect()
+}
+
+/// The message to get the value of the key with **gl**.
+fn get(key: &str) -> Result<Vec<u8>, Vec<u8>> {
+    match get_list(&[key]) {
+        Ok((separator, list)) => Ok(message("gl", &separator, &list)),
+        Err(_) => Err(error("ERR invalid key")),
+    }
+}
+
+/// The value from the reply of **gl**, None when the key doesn't exist.
+fn value(reply: &[u8]) -> Option<Vec<u8>> {
+    get_values(reply)?.into_iter().next().flatten()
+}
+
+fn message(command: &str, key: &str, value: &[u8]) -> Vec<u8> {
+    let mut message = command.as_bytes().to_vec();
+
+    if !key.is_empty() {
+        message.push(b' ');
+        message.extend(key.as_bytes());
+    }
+
+    if !value.is_empty() {
+        message.push(b' ');
+        message.extend(value);
+    }
+
+    message
+}
+
+fn bulk(data: &[u8]) -> Vec<u8> {
+    let mut bulk = format!("${}\r\n", data.len()).into_bytes();
+    bulk.extend(data);
+    bulk.extend(b"\r\n");
+    bulk
+}
+
+fn integer(number: u64) -> Vec<u8> {
+    format!(":{number}\r\n").into_bytes()
+}
+
+fn error(message: &str) -> Vec<u8> {
+    format!("-{message}\r\n").into_bytes()
+}
@@ -0,0 +1,725 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    net::{Shutdown, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    auth::Auth,
+    cleaner::{self, Cleaner},
+    connection::{Connection, Limits},
+    data::{self, Data},
+    db::{DB, DB_FILE},
+    heartbeat::{self, Heartbeat},
+    listener::{Listener, Transport},
+    local::Local,
+    parser::{self, Parser},
+    reader::{
+        self,
+        Action::{Flush, Read},
+        Reader,
+    },
+    stream::{tls_config, Address, Stream},
+    subs::{self, Subs},
+    supervisor::{self, lock, Supervisor},
+    udp::{self, Udp},
+    writer::{
+        self,
+        Action::{Queue, Write},
+        Order, Writer,
+    },
+};
+
+use polling::{Event, Events, Poller};
+
+/// Poller keys for the listeners, the rest are client ids. The poller reserves
+/// usize::MAX.
+const SERVER: usize = 0;
+const TLS_SERVER: usize = usize::MAX - 1;
+const WS_SERVER: usize = usize::MAX - 2;
+const UNIX_SERVER: usize = usize::MAX - 3;
+const UDP_SERVER: usize = usize::MAX - 4;
+const HTTP_SERVER: usize = usize::MAX - 5;
+const RESP_SERVER: usize = usize::MAX - 6;
+
+/// While shutting down, waits for everything read to be answered and written.
+struct Drain {
+    started: Instant,
+    done: Receiver<()>,
+    flushed: bool,
+}
+
+/// The channels to stop the workers, when the main thread ends for any reason.
+struct Workers {
+    supervisor: Supervisor,
+    reader: Sender<reader::Action>,
+    parser: Sender<parser::Action>,
+    data: Sender<data::Action>,
+    subs: Sender<subs::Action>,
+    udp: Option<Sender<udp::Action>>,
+    writer: Sender<writer::Action>,
+    cleaner: Sender<cleaner::Action>,
+    heartbeat: Sender<heartbeat::Action>,
+    db: Sender<()>,
+}
+
+impl Drop for Workers {
+    /// From the first to the last of the pipeline. The ones that are gone
+    /// already don't matter.
+    fn drop(&mut self) {
+        self.supervisor.stopping();
+
+        let _ = self.reader.send(reader::Action::Stop);
+        let _ = self.parser.send(parser::Action::Stop);
+        let _ = self.data.send(data::Action::Stop);
+        let _ = self.subs.send(subs::Action::Stop);
+        if let Some(udp) = &self.udp {
+            let _ = udp.send(udp::Action::Stop);
+        }
+        let _ = self.writer.send(writer::Action::Stop);
+        let _ = self.cleaner.send(cleaner::Action::Stop);
+        let _ = self.heartbeat.send(heartbeat::Action::Stop);
+        let _ = self.db.send(());
+    }
+}
+
+/// The config of a server, everything but the address is optional.
+pub struct Builder {
+    address: String,
+    tls: Option<(String, String, String)>,
+    websocket: Option<String>,
+    http: Option<String>,
+    resp: Option<String>,
+    unix: Option<(String, Option<u32>)>,
+    udp: Option<String>,
+    limits: Limits,
+    drain_timeout: Duration,
+    heartbeat: Duration,
+    auth_file: Option<String>,
+    db_file: Option<String>,
+}
+
+impl Default for Builder {
+    fn default() -> Builder {
+        Builder {
+            address: "0.0.0.0:1984".into(),
+            tls: None,
+            websocket: None,
+            http: None,
+            resp: None,
+            unix: None,
+            udp: None,
+            limits: Limits::default(),
+            drain_timeout: Duration::from_secs(5),
+            heartbeat: Duration::from_secs(30),
+            auth_file: None,
+            db_file: Some(DB_FILE.into()),
+        }
+    }
+}
+
+impl Builder {
+    /// The address for the BITE protocol, 0.0.0.0:1984 by default.
+    pub fn address(mut self, address: &str) -> Builder {
+        self.address = address.into();
+        self
+    }
+
+    /// Also listens for TLS, with PEM files for the certificate and the key.
+    pub fn tls(mut self, address: &str, cert: &str, key: &str) -> Builder {
+        self.tls = Some((address.into(), cert.into(), key.into()));
+        self
+    }
+
+    pub fn websocket(mut self, address: &str) -> Builder {
+        self.websocket = Some(address.into());
+        self
+    }
+
+    pub fn http(mut self, address: &str) -> Builder {
+        self.http = Some(address.into());
+        self
+    }
+
+    pub fn resp(mut self, address: &str) -> Builder {
+        self.resp = Some(address.into());
+        self
+    }
+
+    /// Also listens on a Unix domain socket file, with its permissions.
+    pub fn unix(mut self, path: &str, mode: Option<u32>) -> Builder {
+        self.unix = Some((path.into(), mode));
+        self
+    }
+
+    pub fn udp(mut self, address: &str) -> Builder {
+        self.udp = Some(address.into());
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Builder {
+        self.limits = limits;
+        self
+    }
+
+    /// How long to wait for pending messages when stopping.
+    pub fn drain_timeout(mut self, timeout: Duration) -> Builder {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// Readers waiting for the rest of a message longer than this are dropped,
+    /// and idle writers are pinged after twice this, 30 seconds by default.
+    pub fn heartbeat(mut self, interval: Duration) -> Builder {
+        self.heartbeat = interval;
+        self
+    }
+
+    /// Clients need to authenticate with the users and rules of the file.
+    pub fn auth_file(mut self, path: &str) -> Builder {
+        self.auth_file = Some(path.into());
+        self
+    }
+
+    /// The file for the snapshots, ./data/db.bin by default. Without one,
+    /// everything stays in memory.
+    pub fn persistence(mut self, file: Option<&str>) -> Builder {
+        self.db_file = file.map(|x| x.into());
+        self
+    }
+
+    /// Binds everything and starts the threads, the server runs until stopped.
+    pub fn start(self) -> io::Result<Server> {
+        // Clients need to authenticate when there is an auth config.
+        let auth = match &self.auth_file {
+            Some(path) => {
+                info!("Authentication required, using {path}");
+                Some(Auth::from_file(path)?)
+            }
+
+            None => None,
+        };
+        let auth_required = auth.is_some();
+
+        let limits = self.limits;
+        info!(
+            "Send queue limits: {} messages, {} bytes, {:?} on overflow",
+            limits.max_messages, limits.max_bytes, limits.overflow
+        );
+
+        // The servers and the smol Poller.
+        let mut listeners = vec![Listener::bind(&self.address, SERVER, Transport::Tcp)?];
+        let local_addr = listeners[0].local_addr()?;
+
+        info!("Running at {local_addr}");
+
+        // The optional TLS server, it needs a certificate and a key.
+        let mut addresses = Addresses {
+            bite: local_addr,
+            tls: None,
+            websocket: None,
+            http: None,
+            resp: None,
+            udp: None,
+        };
+
+        if let Some((address, cert, key)) = &self.tls {
+            let config = tls_config(cert, key)?;
+
+            let listener = Listener::bind(address, TLS_SERVER, Transport::Tls(config))?;
+            let address = listener.local_addr()?;
+            listeners.push(listener);
+            addresses.tls = Some(address);
+
+            info!("Running TLS at {address} with {cert} and {key}");
+        }
+
+        // The optional WebSocket server, BITE frames inside binary messages.
+        if let Some(address) = &self.websocket {
+            let listener = Listener::bind(address, WS_SERVER, Transport::WebSocket)?;
+            let address = listener.local_addr()?;
+            listeners.push(listener);
+            addresses.websocket = Some(address);
+
+            info!("Running WebSocket at {address}");
+        }
+
+        // The optional HTTP gateway, for clients without the binary protocol.
+        if let Some(address) = &self.http {
+            let listener = Listener::bind(address, HTTP_SERVER, Transport::Http)?;
+            let address = listener.local_addr()?;
+            listeners.push(listener);
+            addresses.http = Some(address);
+
+            info!("Running HTTP at {address}");
+        }
+
+        // The optional Redis compatible server, for tools that speak RESP.
+        if let Some(address) = &self.resp {
+            let listener = Listener::bind(address, RESP_SERVER, Transport::Resp)?;
+            let address = listener.local_addr()?;
+            listeners.push(listener);
+            addresses.resp = Some(address);
+
+            info!("Running RESP at {address}");
+        }
+
+        // The optional Unix domain socket, for clients on the same host.
+        #[cfg(unix)]
+        if let Some((path, mode)) = &self.unix {
+            listeners.push(Listener::bind_unix(path, UNIX_SERVER, *mode)?);
+
+            info!("Running Unix domain socket at {path}");
+        }
+
+        let poller = Poller::new()?;
+        for listener in listeners.iter() {
+            unsafe {
+                poller.add(listener, Event::readable(listener.key))?;
+            }
+        }
+        let poller = Arc::new(poller);
+
+        // The optional UDP endpoint, for fire and forget calls.
+        let udp = match &self.udp {
+            Some(address) => {
+                let udp = Udp::bind(poller.clone(), address, UDP_SERVER)?;
+                unsafe {
+                    poller.add(&*udp.socket, Event::readable(UDP_SERVER))?;
+                }
+
+                let address = udp.socket.local_addr()?;
+                addresses.udp = Some(address);
+
+                info!("Running UDP at {address}");
+
+                Some(udp)
+            }
+
+            None => None,
+        };
+        let udp_socket = udp.as_ref().map(|x| x.socket.clone());
+        let udp_tx = udp.as_ref().map(|x| x.tx.clone());
+
+        // The connections
+        let readers = HashMap::<usize, Connection>::new();
+        let readers = Arc::new(Mutex::new(readers));
+        let writers = HashMap::<usize, Connection>::new();
+        let writers = Arc::new(Mutex::new(writers));
+        let used_ids = Arc::new(Mutex::new(VecDeque::<usize>::new()));
+
+        // The reader
+        let mut reader = Reader::new(poller.clone(), readers.clone());
+        let reader_tx = reader.tx.clone();
+
+        // The writer
+        let writer = Writer::new(poller.clone(), writers.clone(), limits);
+        let writer_tx = writer.tx.clone();
+        let subs_writer_tx = writer.tx.clone();
+        let data_writer_tx = writer.tx.clone();
+        let parser_writer_tx = writer.tx.clone();
+        let heartbeat_writer_tx = writer.tx.clone();
+        let udp_writer_tx = writer.tx.clone();
+
+        // The parser
+        let mut parser = Parser::new(auth);
+        let reader_parser_tx = parser.tx.clone();
+        let cleaner_parser_tx = parser.tx.clone();
+        let udp_parser_tx = parser.tx.clone();
+        let stop_parser_tx = parser.tx.clone();
+
+        // Subs
+        let mut subs = Subs::new();
+        let data_subs_tx = subs.tx.clone();
+        let parser_subs_tx = subs.tx.clone();
+        let cleaner_subs_tx = subs.tx.clone();
+        let local_subs_tx = subs.tx.clone();
+        let stop_subs_tx = subs.tx.clone();
+
+        // Data & DB
+        let data = Data::new(data_writer_tx, data_subs_tx);
+        let data_map = data.map.clone();
+        let parser_data_tx = data.tx.clone();
+        let stop_data_tx = data.tx.clone();
+
+        let mut db = DB::new(data_map.clone(), self.db_file.as_deref());
+        let db_modified = db.modified.clone();
+        db.load_from_file()?;
+        let final_db = db.clone();
+        let local_db = db.clone();
+
+        // Cleaner
+        let cleaner = Cleaner::new(
+            poller.clone(),
+            readers.clone(),
+            writers.clone(),
+            used_ids.clone(),
+        );
+        let reader_cleaner_tx = cleaner.tx.clone();
+        let stop_cleaner_tx = cleaner.tx.clone();
+        let writer_cleaner_tx = cleaner.tx.clone();
+        let parser_cleaner_tx = cleaner.tx.clone();
+
+        // Heartbeat
+        let heartbeat = Heartbeat::new(readers.clone(), writers.clone(), self.heartbeat);
+        let stop_heartbeat_tx = heartbeat.tx.clone();
+        let (db_stop_tx, db_stop_rx) = channel::<()>();
+
+        // Stop
+        let stop = Stop {
+            poller: poller.clone(),
+            requested: Arc::new(AtomicBool::new(false)),
+        };
+        let stop_requested = stop.requested.clone();
+        let supervisor = Supervisor::new(stop.clone());
+        let main_supervisor = supervisor.clone();
+
+        let parser_udp_tx = udp_tx.clone();
+        let subs_udp_tx = udp_tx.clone();
+
+        // Threads, restarted when they panic.
+        supervisor.spawn("reader", move || {
+            reader.handle(reader_parser_tx.clone(), reader_cleaner_tx.clone())
+        })?;
+        supervisor.spawn("writer", move || writer.handle(writer_cleaner_tx.clone()))?;
+        supervisor.spawn("parser", move || {
+            parser.handle(
+                parser_data_tx.clone(),
+                parser_writer_tx.clone(),
+                parser_subs_tx.clone(),
+                parser_cleaner_tx.clone(),
+                parser_udp_tx.clone(),
+            )
+        })?;
+        supervisor.spawn("subs", move || {
+            subs.handle(subs_writer_tx.clone(), subs_udp_tx.clone())
+        })?;
+        supervisor.spawn("data", move || data.handle(db_modified.clone()))?;
+        supervisor.spawn("db", move || {
+            db.handle(4, &db_stop_rx);
+            Ok(())
+        })?;
+        supervisor.spawn("cleaner", move || {
+            cleaner.handle(cleaner_subs_tx.clone(), cleaner_parser_tx.clone())
+        })?;
+        supervisor.spawn("heartbeat", move || {
+            heartbeat.handle(heartbeat_writer_tx.clone())
+        })?;
+        if let Some(mut udp) = udp {
+            supervisor.spawn("udp", move || {
+                udp.handle(udp_parser_tx.clone(), udp_writer_tx.clone())
+            })?;
+        }
+
+        let drain_timeout = self.drain_timeout;
+
+        let workers = Workers {
+            supervisor: supervisor.clone(),
+            reader: reader_tx.clone(),
+            parser: stop_parser_tx,
+            data: stop_data_tx,
+            subs: stop_subs_tx,
+            udp: udp_tx.clone(),
+            writer: writer_tx.clone(),
+            cleaner: stop_cleaner_tx,
+            heartbeat: stop_heartbeat_tx,
+            db: db_stop_tx,
+        };
+
+        // Connections and events via smol Poller.
+        let main = thread::spawn(move || -> io::Result<()> {
+            let _workers = workers;
+            let mut id_count: usize = 1; // 0 belongs to the main TcpListener.
+            let mut events = Events::new();
+            let mut drain: Option<Drain> = None;
+
+            // An error here stops the server like a failed worker, after the
+            // last snapshot.
+            let mut run = || -> io::Result<()> {
+                loop {
+                    events.clear();
+
+                    // While draining, wakes up often to check if everything was written.
+                    let timeout = drain.as_ref().map(|_| Duration::from_millis(100));
+                    poller.wait(&mut events, timeout)?;
+
+                    if drain.is_none() && stop_requested.load(Ordering::Relaxed) {
+                        info!("No longer accepting connections, draining");
+
+                        for listener in listeners.iter() {
+                            poller.delete(listener)?;
+                        }
+
+                        if let Some(socket) = &udp_socket {
+                            poller.delete(&**socket)?;
+                        }
+
+                        // A worker that is gone already asked to stop, and
+                        // the drain times out without it.
+                        let (done_tx, done_rx) = channel::<()>();
+                        let _ = reader_tx.send(Flush(done_tx));
+
+                        drain = Some(Drain {
+                            started: Instant::now(),
+                            done: done_rx,
+                            flushed: false,
+                        });
+                    }
+
+                    for ev in events.iter() {
+                        match ev.key {
+                            // Nothing new is accepted or read while draining.
+                            _ if drain.is_some() && !ev.writable => {}
+
+                            key if listeners.iter().any(|x| x.key == key) => {
+                                let listener = listeners.iter().find(|x| x.key == key).unwrap();
+
+                                // Reusing ids.
+                                let used_id = lock(&used_ids).pop_front();
+                                let client_id = if let Some(id) = used_id {
+                                    id
+                                } else {
+                                    let id = id_count;
+                                    id_count += 1;
+                                    id
+                                };
+
+                                // The server continues listening for more clients.
+                                poller.modify(listener, Event::readable(key))?;
+
+                                // A client that can't be set up only loses its connection.
+                                let (reader, writer, addr) =
+                                    match accept(&poller, listener, client_id) {
+                                        Ok(accepted) => accepted,
+                                        Err(err) => {
+                                            warn!("Connection #{client_id} dropped: {err}");
+                                            lock(&used_ids).push_back(client_id);
+                                            continue;
+                                        }
+                                    };
+
+                                info!("Connection #{client_id} from {addr}");
+
+                                lock(&readers).insert(
+                                    client_id,
+                                    Connection::new(client_id, reader, addr.clone()),
+                                );
+
+                                // Save the writer socket for later use.
+                                lock(&writers)
+                                    .insert(client_id, Connection::new(client_id, writer, addr));
+
+                                // The first message to the client is his id, so it can add
+                                // it on all his messages or it would get disconnected.
+                                // When auth is required, the message also says so.
+                                let data = if auth_required {
+                                    "AUTH".into()
+                                } else {
+                                    [].into()
+                                };
+
+                                let _ = writer_tx.send(Queue(Order {
+                                    from_id: client_id,
+                                    to_id: client_id,
+                                    msg_id: 0,
+                                    data,
+                                }));
+                            }
+
+                            UDP_SERVER if udp_tx.is_some() => {
+                                let _ = udp_tx.as_ref().unwrap().send(udp::Action::Read);
+                            }
+
+                            id if ev.readable => {
+                                let _ = reader_tx.send(Read(id));
+                            }
+
+                            id if ev.writable => {
+                                let _ = writer_tx.send(Write(id));
+                            }
+
+                            _ => unreachable!(),
+                        }
+                    }
+
+                    if let Some(drain) = &mut drain {
+                        if !drain.flushed {
+                            drain.flushed = drain.done.try_recv().is_ok();
+                        }
+
+                        let written = lock(&writers).values().all(|x| {
+                            x.closed || (x.send_queue.is_empty() && !x.socket.wants_write())
+                        });
+
+                        if drain.flushed && written {
+                            info!("Drained");
+                            break;
+                        }
+
+                        if drain.started.elapsed() > drain_timeout {
+                            warn!("Drain timed out after {drain_timeout:?}");
+                            break;
+                        }
+                    }
+                }
+
+                Ok(())
+            };
+
+            if let Err(err) = run() {
+                main_supervisor.fail(format!("main failed: {err}"));
+            }
+
+            if let Err(err) = final_db.save_to_file() {
+                error!("Last snapshot failed: {err}");
+            }
+
+            // Everything that could keep the sockets open goes away.
+            for connection in lock(&readers).drain().map(|(_, x)| x) {
+                let _ = connection.socket.shutdown(Shutdown::Both);
+            }
+            lock(&writers).clear();
+
+            info!("Bye");
+
+            match main_supervisor.failure() {
+                Some(reason) => Err(io::Error::other(reason)),
+                None => Ok(()),
+            }
+        });
+
+        Ok(Server {
+            addresses,
+            local: Local::new(data_map, local_db, local_subs_tx),
+            stop,
+            supervisor,
+            main,
+        })
+    }
+}
+
+/// Accepts a client and registers both of its sockets, the reader for reading
+/// events and the writer for later use. Nothing stays registered on errors.
+fn accept(
+    poller: &Poller,
+    listener: &Listener,
+    id: usize,
+) -> io::Result<(Stream, Stream, Address)> {
+    let (reader, addr) = listener.accept(id)?;
+    let writer = reader.try_clone()?;
+
+    unsafe {
+        poller.add(&reader, Event::readable(id))?;
+    }
+
+    if let Err(err) = unsafe { poller.add(&writer, Event::none(id)) } {
+        let _ = poller.delete(&reader);
+        return Err(err);
+    }
+
+    Ok((reader, writer, addr))
+}
+
+/// A running server. Stopping it drains the pending messages, saves a last
+/// snapshot and closes every socket.
+pub struct Server {
+    addresses: Addresses,
+    local: Local,
+    stop: Stop,
+    supervisor: Supervisor,
+    main: JoinHandle<io::Result<()>>,
+}
+
+impl Server {
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+
+    /// The address of the BITE protocol listener.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addresses.bite
+    }
+
+    /// The addresses of all the listeners, with the ports they got when they
+    /// were 0.
+    pub fn addresses(&self) -> Addresses {
+        self.addresses
+    }
+
+    /// The data and the subscriptions, without a connection.
+    pub fn local(&self) -> Local {
+        self.local.clone()
+    }
+
+    /// To stop the server from somewhere else, like a signal handler.
+    pub fn stopper(&self) -> Stop {
+        self.stop.clone()
+    }
+
+    /// Runs the work on a thread restarted like the workers when it panics,
+    /// for the tests of the supervisor.
+    #[doc(hidden)]
+    pub fn supervise<F>(&self, name: &'static str, mut work: F) -> io::Result<()>
+    where
+        F: FnMut() -> io::Result<()> + Send + 'static,
+    {
+        self.supervisor
+            .spawn(name, move || work().map_err(supervisor::Error::Io))
+    }
+
+    pub fn stop(self) -> io::Result<()> {
+        self.stop.stop();
+        self.wait()
+    }
+
+    /// Blocks until the server and all its threads stop.
+    pub fn wait(self) -> io::Result<()> {
+        let result = match self.main.join() {
+            Ok(result) => result,
+            Err(_) => Err(io::Error::other("the server thread panicked")),
+        };
+
+        self.supervisor.join();
+
+        result
+    }
+}
+
+/// Where the listeners of a server are bound, None for the ones it doesn't
+/// have. The Unix domain socket is on its path.
+#[derive(Clone, Copy, Debug)]
+pub struct Addresses {
+    pub bite: SocketAddr,
+    pub tls: Option<SocketAddr>,
+    pub websocket: Option<SocketAddr>,
+    pub http: Option<SocketAddr>,
+    pub resp: Option<SocketAddr>,
+    pub udp: Option<SocketAddr>,
+}
+
+/// Asks a server to stop.
+#[derive(Clone)]
+pub struct Stop {
+    poller: Arc<Poller>,
+    requested: Arc<AtomicBool>,
+}
+
+impl Stop {
+    /// Wakes up the server so it stops accepting connections and drains. True
+    /// when it was already asked.
+    pub fn stop(&self) -> bool {
+        let requested = self.requested.swap(true, Ordering::Relaxed);
+        if let Err(err) = self.poller.notify() {
+            error!("Stop failed to wake up the server: {err}");
+        }
+
+        requested
+    }
+}
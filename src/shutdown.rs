@@ -0,0 +1,34 @@
+use std::io;
+
+use bite::Stop;
+
+use signal_hook::{
+    consts::{SIGINT, SIGTERM},
+    iterator::Signals,
+};
+
+pub struct Shutdown {
+    stop: Stop,
+    signals: Signals,
+}
+
+impl Shutdown {
+    pub fn new(stop: Stop) -> io::Result<Shutdown> {
+        let signals = Signals::new([SIGTERM, SIGINT])?;
+
+        Ok(Shutdown { stop, signals })
+    }
+
+    /// Waits for SIGTERM or SIGINT, then stops the server so it can stop
+    /// accepting connections and drain. A second signal exits right away.
+    pub fn handle(&mut self) {
+        for signal in self.signals.forever() {
+            if self.stop.stop() {
+                warn!("Signal {signal} received again, exiting without draining");
+                std::process::exit(1);
+            }
+
+            info!("Signal {signal} received, shutting down");
+        }
+    }
+}
@@ -0,0 +1,321 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    fs::File,
+    io::{
+        self, BufReader,
+        ErrorKind::{BrokenPipe, InvalidData, WouldBlock},
+        IoSlice, Read, Write,
+    },
+    net::{Shutdown, SocketAddr, TcpStream},
+    sync::{Arc, Mutex},
+};
+
+#[cfg(unix)]
+use std::os::{
+    fd::{AsFd, AsRawFd, BorrowedFd, RawFd},
+    unix::net::UnixStream,
+};
+
+#[cfg(windows)]
+use std::os::windows::io::{AsRawSocket, AsSocket, BorrowedSocket, RawSocket};
+
+use crate::{http::Http, resp::Resp, supervisor::lock, websocket::WebSocket};
+
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    ServerConfig, ServerConnection,
+};
+
+/// The socket behind a connection. Readers and writers get their own clone, and
+/// encrypted, WebSocket, HTTP or RESP ones share the same state.
+pub enum Stream {
+    Tcp(TcpStream),
+    Tls(TcpStream, Arc<Mutex<ServerConnection>>),
+    WebSocket(TcpStream, Arc<Mutex<WebSocket>>),
+    Http(TcpStream, Arc<Mutex<Http>>),
+    Resp(TcpStream, Arc<Mutex<Resp>>),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+/// Where a connection comes from.
+#[derive(Clone)]
+pub enum Address {
+    Net(SocketAddr),
+    Unix(Arc<str>),
+}
+
+impl Display for Address {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Address::Net(addr) => Display::fmt(addr, f),
+            Address::Unix(path) => write!(f, "unix:{path}"),
+        }
+    }
+}
+
+impl Stream {
+    /// The stream for TLS, the handshake happens on the first reads and writes.
+    pub fn tls(socket: TcpStream, config: Arc<ServerConfig>) -> io::Result<Stream> {
+        let tls = ServerConnection::new(config).map_err(|err| io::Error::new(InvalidData, err))?;
+
+        Ok(Stream::Tls(socket, Arc::new(Mutex::new(tls))))
+    }
+
+    /// The stream for WebSocket, the upgrade happens on the first read.
+    pub fn websocket(socket: TcpStream) -> Stream {
+        Stream::WebSocket(socket, Arc::new(Mutex::new(WebSocket::new())))
+    }
+
+    /// The stream for HTTP, its requests become messages from the client id.
+    pub fn http(socket: TcpStream, id: usize) -> Stream {
+        Stream::Http(socket, Arc::new(Mutex::new(Http::new(id))))
+    }
+
+    /// The stream for Redis clients, its commands become messages from the
+    /// client id.
+    pub fn resp(socket: TcpStream, id: usize) -> Stream {
+        Stream::Resp(socket, Arc::new(Mutex::new(Resp::new(id))))
+    }
+
+    pub fn try_clone(&self) -> io::Result<Stream> {
+        match self {
+            Stream::Tcp(socket) => Ok(Stream::Tcp(socket.try_clone()?)),
+            Stream::Tls(socket, tls) => Ok(Stream::Tls(socket.try_clone()?, tls.clone())),
+            Stream::WebSocket(socket, ws) => Ok(Stream::WebSocket(socket.try_clone()?, ws.clone())),
+            Stream::Http(socket, http) => Ok(Stream::Http(socket.try_clone()?, http.clone())),
+            Stream::Resp(socket, resp) => Ok(Stream::Resp(socket.try_clone()?, resp.clone())),
+            #[cfg(unix)]
+            Stream::Unix(socket) => Ok(Stream::Unix(socket.try_clone()?)),
+        }
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Stream::Tcp(socket)
+            | Stream::Tls(socket, _)
+            | Stream::WebSocket(socket, _)
+            | Stream::Http(socket, _)
+            | Stream::Resp(socket, _) => socket.shutdown(how),
+
+            #[cfg(unix)]
+            Stream::Unix(socket) => socket.shutdown(how),
+        }
+    }
+
+    /// True when there are encrypted bytes, frames, responses or replies waiting
+    /// for the socket.
+    pub fn wants_write(&self) -> bool {
+        match self {
+            Stream::Tls(_, tls) => lock(tls).wants_write(),
+            Stream::WebSocket(_, ws) => lock(ws).wants_write(),
+            Stream::Http(_, http) => lock(http).wants_write(),
+            Stream::Resp(_, resp) => lock(resp).wants_write(),
+            _ => false,
+        }
+    }
+
+    /// True when reads could have more data than what was read from the socket,
+    /// so they need to continue until they would block.
+    pub fn is_buffered(&self) -> bool {
+        matches!(
+            self,
+            Stream::Tls(..) | Stream::WebSocket(..) | Stream::Http(..) | Stream::Resp(..)
+        )
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(socket) => socket.read(buf),
+
+            Stream::Tls(socket, tls) => {
+                let mut tls = lock(tls);
+
+                loop {
+                    // Already decrypted.
+                    match tls.reader().read(buf) {
+                        Ok(n) => return Ok(n),
+                        Err(err) if err.kind() == WouldBlock => {}
+                        Err(err) => return Err(err),
+                    }
+
+                    if tls.read_tls(socket)? == 0 {
+                        return Err(BrokenPipe.into());
+                    }
+
+                    let processed = tls.process_new_packets();
+
+                    // Handshake messages and alerts.
+                    write_tls(&mut tls, socket)?;
+
+                    if let Err(err) = processed {
+                        return Err(io::Error::new(InvalidData, err));
+                    }
+                }
+            }
+
+            Stream::WebSocket(socket, ws) => lock(ws).read(socket, buf),
+
+            Stream::Http(socket, http) => lock(http).read(socket, buf),
+
+            Stream::Resp(socket, resp) => lock(resp).read(socket, buf),
+
+            #[cfg(unix)]
+            Stream::Unix(socket) => socket.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(socket) => socket.write(buf),
+
+            Stream::Tls(socket, tls) => {
+                let mut tls = lock(tls);
+
+                // Encrypted bytes still waiting means the socket is full.
+                write_tls(&mut tls, socket)?;
+                if tls.wants_write() {
+                    return Err(WouldBlock.into());
+                }
+
+                let n = tls.writer().write(buf)?;
+                write_tls(&mut tls, socket)?;
+
+                Ok(n)
+            }
+
+            Stream::WebSocket(socket, ws) => lock(ws).write(socket, buf),
+
+            Stream::Http(socket, http) => lock(http).write(socket, buf),
+
+            Stream::Resp(socket, resp) => lock(resp).write(socket, buf),
+
+            #[cfg(unix)]
+            Stream::Unix(socket) => socket.write(buf),
+        }
+    }
+
+    /// Many frames in one write. WebSocket, HTTP and RESP frame each message
+    /// on their own, so they write only the first one.
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(socket) => socket.write_vectored(bufs),
+
+            Stream::Tls(socket, tls) => {
+                let mut tls = lock(tls);
+
+                write_tls(&mut tls, socket)?;
+                if tls.wants_write() {
+                    return Err(WouldBlock.into());
+                }
+
+                let n = tls.writer().write_vectored(bufs)?;
+                write_tls(&mut tls, socket)?;
+
+                Ok(n)
+            }
+
+            Stream::WebSocket(..) | Stream::Http(..) | Stream::Resp(..) => {
+                match bufs.iter().find(|buf| !buf.is_empty()) {
+                    Some(buf) => self.write(buf),
+                    None => Ok(0),
+                }
+            }
+
+            #[cfg(unix)]
+            Stream::Unix(socket) => socket.write_vectored(bufs),
+        }
+    }
+
+    /// Writes encrypted bytes that are waiting, as much as the socket accepts.
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(socket) => socket.flush(),
+            Stream::Tls(socket, tls) => write_tls(&mut lock(tls), socket),
+            Stream::WebSocket(socket, ws) => lock(ws).flush(socket),
+            Stream::Http(socket, http) => lock(http).flush(socket),
+            Stream::Resp(socket, resp) => lock(resp).flush(socket),
+            #[cfg(unix)]
+            Stream::Unix(socket) => socket.flush(),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl AsFd for Stream {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        match self {
+            Stream::Tcp(socket)
+            | Stream::Tls(socket, _)
+            | Stream::WebSocket(socket, _)
+            | Stream::Http(socket, _)
+            | Stream::Resp(socket, _) => socket.as_fd(),
+
+            Stream::Unix(socket) => socket.as_fd(),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl AsRawFd for Stream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.as_fd().as_raw_fd()
+    }
+}
+
+#[cfg(windows)]
+impl AsRawSocket for Stream {
+    fn as_raw_socket(&self) -> RawSocket {
+        self.as_socket().as_raw_socket()
+    }
+}
+
+#[cfg(windows)]
+impl AsSocket for Stream {
+    fn as_socket(&self) -> BorrowedSocket<'_> {
+        match self {
+            Stream::Tcp(socket)
+            | Stream::Tls(socket, _)
+            | Stream::WebSocket(socket, _)
+            | Stream::Http(socket, _)
+            | Stream::Resp(socket, _) => socket.as_socket(),
+        }
+    }
+}
+
+/// Writes what TLS wants to send until the socket would block.
+fn write_tls(tls: &mut ServerConnection, socket: &mut TcpStream) -> io::Result<()> {
+    while tls.wants_write() {
+        match tls.write_tls(socket) {
+            Ok(0) => return Err(BrokenPipe.into()),
+            Ok(_) => continue,
+            Err(err) if err.kind() == WouldBlock => break,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(())
+}
+
+/// The server config from PEM files with the certificate chain and the key.
+pub fn tls_config(cert_path: &str, key_path: &str) -> io::Result<Arc<ServerConfig>> {
+    let invalid = |err| io::Error::new(InvalidData, err);
+
+    let certs = CertificateDer::pem_reader_iter(BufReader::new(File::open(cert_path)?))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| invalid(err.to_string()))?;
+
+    let key = PrivateKeyDer::from_pem_reader(BufReader::new(File::open(key_path)?))
+        .map_err(|err| invalid(err.to_string()))?;
+
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|err| invalid(err.to_string()))?;
+
+    Ok(Arc::new(config))
+}
@@ -4,17 +4,31 @@ use std::{
 };
 
 use crate::{
+    data, namespace,
     parser::Command,
-    writer::{self, Action::QueueAll, Order},
+    supervisor::Error,
+    udp,
+    writer::{
+        self,
+        Action::{Flush, QueueAll},
+        Order,
+    },
 };
 
 use serde_json::json;
 
+/// The key and the value of a change or a call, for in process subscribers.
+pub type Change = (String, Vec<u8>);
+
 pub enum Action {
     Add(String, usize, Command),
     Del(String, usize),
     DelAll(usize),
     Call(String, Vec<u8>, usize, usize),
+    CallDatagram(String, Vec<u8>, usize, usize),
+    Local(String, Sender<Change>),
+    Flush(Sender<()>),
+    Stop,
 }
 
 pub struct Sub {
@@ -25,6 +39,7 @@ pub struct Sub {
 pub struct Subs {
     key_subs: HashMap<String, Vec<Sub>>,
     id_keys: HashMap<usize, Vec<String>>,
+    local_subs: HashMap<String, Vec<Sender<Change>>>,
     pub tx: Sender<Action>,
     rx: Receiver<Action>,
 }
@@ -38,14 +53,19 @@ impl Subs {
         Subs {
             key_subs,
             id_keys,
+            local_subs: HashMap::new(),
             tx,
             rx,
         }
     }
 
-    pub fn handle(&mut self, writer_tx: Sender<writer::Action>) {
+    pub fn handle(
+        &mut self,
+        writer_tx: Sender<writer::Action>,
+        udp_tx: Option<Sender<udp::Action>>,
+    ) -> Result<(), Error> {
         loop {
-            match self.rx.recv().unwrap() {
+            match self.rx.recv()? {
                 Action::Add(key, id, command) => {
                     let keys = self.id_keys.entry(id).or_default();
 
@@ -76,64 +96,127 @@ impl Subs {
                     }
                 }
 
+                // In process subscribers, until their receiver is gone.
+                Action::Local(key, sender) => {
+                    self.local_subs.entry(key).or_default().push(sender);
+                }
+
                 Action::Call(key, data, from_id, msg_id) => {
-                    let mut messages = Vec::<Order>::new();
-
-                    for alt_key in get_key_combinations(key.as_str()) {
-                        if let Some(subs) = self.key_subs.get(&alt_key) {
-                            for sub in subs {
-                                let data = match sub.command {
-                                    Command::SubGet => data.to_owned(),
-
-                                    Command::SubKeyValue => {
-                                        let key = key.split('.').last().unwrap();
-                                        let mut message = Vec::<u8>::new();
-
-                                        message.extend(key.as_bytes());
-                                        message.extend(" ".as_bytes());
-                                        message.extend(&data);
-                                        message
-                                    }
-
-                                    Command::SubJson => {
-                                        let key = key.split('.').last().unwrap();
-                                        let message = String::from_utf8_lossy(&data);
-                                        json!({ key: message }).to_string().into_bytes()
-                                    }
-
-                                    _ => unreachable!(),
-                                };
-
-                                messages.push(Order {
-                                    from_id,
-                                    to_id: sub.id,
-                                    msg_id,
-                                    data,
-                                });
-                            }
-                        }
+                    self.call_local(&key, &data);
+                    let messages = self.orders(&key, &data, from_id, msg_id);
+
+                    if !messages.is_empty() {
+                        writer_tx.send(QueueAll(messages))?;
                     }
+                }
+
+                // Subscribers with a UDP endpoint receive datagrams instead.
+                Action::CallDatagram(key, data, from_id, msg_id) => {
+                    self.call_local(&key, &data);
+                    let messages = self.orders(&key, &data, from_id, msg_id);
 
                     if !messages.is_empty() {
-                        writer_tx.send(QueueAll(messages)).unwrap();
+                        match &udp_tx {
+                            Some(udp_tx) => udp_tx.send(udp::Action::SendAll(messages))?,
+                            None => writer_tx.send(QueueAll(messages))?,
+                        }
                     }
                 }
+
+                // Everything before this is already on the writer.
+                Action::Flush(done) => writer_tx.send(Flush(done))?,
+
+                // The server stopped.
+                Action::Stop => return Ok(()),
             }
         }
     }
+
+    /// Sends the key and the value to the in process subscribers of the key and
+    /// its parents.
+    fn call_local(&mut self, key: &str, data: &[u8]) {
+        if self.local_subs.is_empty() {
+            return;
+        }
+
+        for alt_key in get_key_combinations(key) {
+            if let Some(senders) = self.local_subs.get_mut(&alt_key) {
+                senders.retain(|x| x.send((key.to_owned(), data.to_owned())).is_ok());
+
+                if senders.is_empty() {
+                    self.local_subs.remove(&alt_key);
+                }
+            }
+        }
+    }
+
+    /// The messages for all the subscribers of the key and its parents.
+    fn orders(&self, key: &str, data: &[u8], from_id: usize, msg_id: usize) -> Vec<Order> {
+        let mut messages = Vec::<Order>::new();
+
+        for alt_key in get_key_combinations(key) {
+            if let Some(subs) = self.key_subs.get(&alt_key) {
+                for sub in subs {
+                    let data = match sub.command {
+                        Command::SubGet => data.to_owned(),
+
+                        Command::SubKeyValue => {
+                            let key = namespace::strip(key);
+                            let key = key.split('.').next_back().unwrap();
+                            let mut message = Vec::<u8>::new();
+
+                            message.extend(key.as_bytes());
+                            message.extend(" ".as_bytes());
+                            message.extend(data);
+                            message
+                        }
+
+                        Command::SubFullKey => {
+                            let mut message = namespace::strip(key).as_bytes().to_vec();
+
+                            message.extend(" ".as_bytes());
+                            message.extend(data);
+                            message
+                        }
+
+                        Command::SubJson => {
+                            let key = namespace::strip(key);
+                            let key = key.split('.').next_back().unwrap();
+                            json!({ key: data::json_value(data) })
+                                .to_string()
+                                .into_bytes()
+                        }
+
+                        _ => unreachable!(),
+                    };
+
+                    messages.push(Order {
+                        from_id,
+                        to_id: sub.id,
+                        msg_id,
+                        data,
+                    });
+                }
+            }
+        }
+
+        messages
+    }
 }
 
-/// "data.inner.value" -> ["data.inner.value", "data.inner", "data"]
+/// "data.inner.value" -> ["data.inner.value", "data.inner", "data"], always in
+/// the namespace of the key.
 fn get_key_combinations(key: &str) -> Vec<String> {
     let mut parent_keys = Vec::<String>::new();
 
+    let (current, key) = namespace::split(key);
     let keys: Vec<&str> = key.split('.').collect();
     let len = keys.len();
 
     for i in 0..len {
         let end = len - i;
         let str = keys[..end].join(".");
-        parent_keys.push(str);
+        parent_keys.push(namespace::scope(current, &str));
     }
 
     parent_keys
@@ -0,0 +1,170 @@
+use std::{
+    any::Any,
+    collections::VecDeque,
+    fmt::{self, Display, Formatter},
+    io,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{RecvError, SendError},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::server::Stop;
+
+/// Panics allowed on a worker during the window before the server stops.
+const MAX_RESTARTS: usize = 5;
+const RESTART_WINDOW: Duration = Duration::from_secs(60);
+
+/// Why a worker can't continue.
+#[derive(Debug)]
+pub enum Error {
+    /// Another worker is gone, its channel closed.
+    Disconnected,
+    Io(io::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Error::Disconnected => write!(f, "channel disconnected"),
+            Error::Io(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl<T> From<SendError<T>> for Error {
+    fn from(_: SendError<T>) -> Error {
+        Error::Disconnected
+    }
+}
+
+impl From<RecvError> for Error {
+    fn from(_: RecvError) -> Error {
+        Error::Disconnected
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+/// Locks the mutex even when a panicking worker poisoned it. The worker is
+/// restarted, and the data behind the lock is still the best we have.
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Runs the workers, restarting the ones that panic. A worker that fails, or
+/// that panics too often, stops the server.
+#[derive(Clone)]
+pub struct Supervisor {
+    stop: Stop,
+    failure: Arc<Mutex<Option<String>>>,
+    stopping: Arc<AtomicBool>,
+    threads: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl Supervisor {
+    pub fn new(stop: Stop) -> Supervisor {
+        Supervisor {
+            stop,
+            failure: Arc::new(Mutex::new(None)),
+            stopping: Arc::new(AtomicBool::new(false)),
+            threads: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Runs the work on a named thread. The work keeps its state between
+    /// restarts, a panic only loses the message being handled.
+    pub fn spawn<F>(&self, name: &'static str, mut work: F) -> io::Result<()>
+    where
+        F: FnMut() -> Result<(), Error> + Send + 'static,
+    {
+        let supervisor = self.clone();
+
+        let thread = thread::Builder::new().name(name.into()).spawn(move || {
+            let mut panics = VecDeque::<Instant>::new();
+
+            loop {
+                let reason = match panic::catch_unwind(AssertUnwindSafe(&mut work)) {
+                    Ok(Ok(())) => return,
+
+                    // The others could be gone already.
+                    Ok(Err(err)) if supervisor.stopping.load(Ordering::Relaxed) => {
+                        debug!("{name} stopped: {err}");
+                        return;
+                    }
+
+                    Ok(Err(err)) => format!("{name} failed: {err}"),
+
+                    Err(panic) => {
+                        let reason = format!("{name} panicked: {}", panic_message(&*panic));
+
+                        panics.push_back(Instant::now());
+                        panics.retain(|x| x.elapsed() < RESTART_WINDOW);
+
+                        if panics.len() <= MAX_RESTARTS {
+                            error!("{reason}, restarting");
+                            continue;
+                        }
+
+                        format!("{reason}, {} times in {RESTART_WINDOW:?}", panics.len())
+                    }
+                };
+
+                supervisor.fail(reason);
+
+                return;
+            }
+        })?;
+
+        lock(&self.threads).push(thread);
+
+        Ok(())
+    }
+
+    /// From now on the workers are being stopped, and the ones that can't
+    /// continue aren't failures.
+    pub fn stopping(&self) {
+        self.stopping.store(true, Ordering::Relaxed);
+    }
+
+    /// Waits for the workers to finish.
+    pub fn join(&self) {
+        let threads: Vec<JoinHandle<()>> = lock(&self.threads).drain(..).collect();
+
+        for thread in threads {
+            let _ = thread.join();
+        }
+    }
+
+    /// The reason of the first failure, when the server stopped because of
+    /// one.
+    pub fn failure(&self) -> Option<String> {
+        lock(&self.failure).clone()
+    }
+
+    /// Stops the server, remembering why.
+    pub fn fail(&self, reason: String) {
+        error!("{reason}, stopping the server");
+
+        lock(&self.failure).get_or_insert(reason);
+        self.stop.stop();
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => match panic.downcast_ref::<String>() {
+            Some(message) => message,
+            None => "unknown",
+        },
+    }
+}
@@ -0,0 +1,193 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+    io::ErrorKind::{Interrupted, WouldBlock},
+    net::{SocketAddr, UdpSocket},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc,
+    },
+    time::SystemTime,
+};
+
+use crate::{
+    message::{stamp_header, Message},
+    parser::{self, parse, Action::ParseDatagram, Command},
+    stream::Address,
+    supervisor::Error,
+    writer::{self, Action::QueueAll, Order},
+};
+
+use polling::{Event, Poller};
+
+/// The biggest payload for a datagram.
+const MAX_DATAGRAM: usize = 65507;
+
+pub enum Action {
+    Read,
+    Token(usize, String),
+    Drop(usize),
+    SendAll(Vec<Order>),
+    Stop,
+}
+
+/// Unreliable datagrams for clients with a TCP connection. A client asks for a
+/// token with `u` on TCP, then sends `u <token>` from its UDP socket, and from
+/// that endpoint it can send `!` calls, and receive the `!` calls of its
+/// subscriptions.
+pub struct Udp {
+    poller: Arc<Poller>,
+    pub socket: Arc<UdpSocket>,
+    pub key: usize,
+    tokens: HashMap<usize, String>,
+    endpoints: HashMap<usize, SocketAddr>,
+    ids: HashMap<SocketAddr, usize>,
+    pub tx: Sender<Action>,
+    rx: Receiver<Action>,
+}
+
+impl Udp {
+    pub fn bind(poller: Arc<Poller>, address: &str, key: usize) -> std::io::Result<Udp> {
+        let socket = UdpSocket::bind(address)?;
+        socket.set_nonblocking(true)?;
+
+        let (tx, rx) = channel::<Action>();
+
+        Ok(Udp {
+            poller,
+            socket: Arc::new(socket),
+            key,
+            tokens: HashMap::new(),
+            endpoints: HashMap::new(),
+            ids: HashMap::new(),
+            tx,
+            rx,
+        })
+    }
+
+    pub fn handle(
+        &mut self,
+        parser_tx: Sender<parser::Action>,
+        writer_tx: Sender<writer::Action>,
+    ) -> Result<(), Error> {
+        loop {
+            match self.rx.recv()? {
+                Action::Read => {
+                    let mut buffer = [0; 65535];
+
+                    loop {
+                        let (size, addr) = match self.socket.recv_from(&mut buffer) {
+                            Ok(received) => received,
+                            Err(ref err) if err.kind() == WouldBlock => break,
+                            Err(ref err) if err.kind() == Interrupted => continue,
+                            Err(err) => {
+                                info!("UDP read failed: {err}");
+                                break;
+                            }
+                        };
+
+                        // Datagrams are complete messages, or garbage.
+                        let message = match Message::from_protocol(buffer[..size].to_vec()) {
+                            Ok(message) if message.size as usize == size => message,
+                            _ => continue,
+                        };
+
+                        match self.ids.get(&addr) {
+                            Some(id) if *id == message.from as usize => {
+                                parser_tx.send(ParseDatagram(message, Address::Net(addr)))?;
+                            }
+
+                            Some(_) => {}
+
+                            None => self.bind_endpoint(message, addr),
+                        }
+                    }
+
+                    self.poller
+                        .modify(&*self.socket, Event::readable(self.key))?;
+                }
+
+                Action::Token(id, token) => {
+                    self.tokens.insert(id, token);
+                }
+
+                // The connection is gone, and the id could be reused.
+                Action::Drop(id) => {
+                    self.tokens.remove(&id);
+
+                    if let Some(addr) = self.endpoints.remove(&id) {
+                        self.ids.remove(&addr);
+                    }
+                }
+
+                // The server stopped, the socket closes.
+                Action::Stop => return Ok(()),
+
+                // Datagrams when possible, the rest goes through TCP.
+                Action::SendAll(orders) => {
+                    let mut reliable = Vec::<Order>::new();
+
+                    for order in orders {
+                        let addr = match self.endpoints.get(&order.to_id) {
+                            Some(addr) if order.data.len() + 6 <= MAX_DATAGRAM => *addr,
+
+                            _ => {
+                                reliable.push(order);
+                                continue;
+                            }
+                        };
+
+                        let data =
+                            stamp_header(order.data, order.from_id as u32, order.msg_id as u32);
+
+                        // Unreliable, a full socket just loses the datagram.
+                        let _ = self.socket.send_to(&data, addr);
+                    }
+
+                    if !reliable.is_empty() {
+                        writer_tx.send(QueueAll(reliable))?;
+                    }
+                }
+            }
+        }
+    }
+
+    /// `u <token>` from an unknown endpoint binds it to the client id.
+    fn bind_endpoint(&mut self, message: Message, addr: SocketAddr) {
+        let id = message.from as usize;
+        let parsed = parse(&message.data);
+
+        let valid = match self.tokens.get(&id) {
+            Some(token) => parsed.command == Command::Udp && parsed.key == *token,
+            None => false,
+        };
+
+        if !valid {
+            return;
+        }
+
+        self.tokens.remove(&id);
+
+        if let Some(old) = self.endpoints.insert(id, addr) {
+            self.ids.remove(&old);
+        }
+        self.ids.insert(addr, id);
+
+        info!("Connection #{id} bound to UDP {addr}");
+
+        let reply = stamp_header("OK".into(), message.from, message.id);
+        let _ = self.socket.send_to(&reply, addr);
+    }
+}
+
+/// A token hard to guess, to bind a UDP endpoint.
+pub fn token(id: usize) -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_usize(id);
+
+    if let Ok(time) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        hasher.write_u128(time.as_nanos());
+    }
+
+    format!("{:016x}", hasher.finish())
+}
@@ -0,0 +1,278 @@
+use std::{
+    io::{
+        self,
+        ErrorKind::{BrokenPipe, InvalidData, WouldBlock},
+        Read, Write,
+    },
+    net::TcpStream,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha1::{Digest, Sha1};
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const MAX_HANDSHAKE: usize = 8192;
+const MAX_PAYLOAD: usize = 65535;
+const BUFFER_SIZE: usize = 4096;
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+/// The close status for data the endpoint can't accept.
+const UNSUPPORTED: u16 = 1003;
+
+/// WebSocket state for a connection. The payloads of binary messages are read
+/// and written as a continuous stream, so the BITE frames travel inside them.
+/// Text messages close the connection.
+pub struct WebSocket {
+    upgraded: bool,
+    closed: bool,
+    input: Vec<u8>,
+    payload: Vec<u8>,
+    output: Vec<u8>,
+    early: Vec<u8>,
+}
+
+impl WebSocket {
+    pub fn new() -> WebSocket {
+        WebSocket {
+            upgraded: false,
+            closed: false,
+            input: Vec::new(),
+            payload: Vec::new(),
+            output: Vec::new(),
+            early: Vec::new(),
+        }
+    }
+
+    /// True when there are frames waiting for the socket.
+    pub fn wants_write(&self) -> bool {
+        !self.output.is_empty()
+    }
+
+    pub fn read(&mut self, socket: &mut TcpStream, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if !self.payload.is_empty() {
+                let n = buf.len().min(self.payload.len());
+                buf[..n].copy_from_slice(&self.payload[..n]);
+                self.payload.drain(..n);
+
+                return Ok(n);
+            }
+
+            if self.closed {
+                return Ok(0);
+            }
+
+            let mut chunk = [0; BUFFER_SIZE];
+            let n = socket.read(&mut chunk)?;
+            if n == 0 {
+                return Ok(0);
+            }
+
+            self.input.extend_from_slice(&chunk[..n]);
+
+            if self.upgraded {
+                self.decode()?;
+            } else {
+                self.upgrade()?;
+            }
+
+            // Handshake responses, pongs and closes.
+            self.flush(socket)?;
+        }
+    }
+
+    pub fn write(&mut self, socket: &mut TcpStream, buf: &[u8]) -> io::Result<usize> {
+        // Messages before the handshake wait for it.
+        if !self.upgraded {
+            self.early.extend(frame(BINARY, buf));
+            return Ok(buf.len());
+        }
+
+        // Frames still waiting means the socket is full.
+        self.flush(socket)?;
+        if self.wants_write() {
+            return Err(WouldBlock.into());
+        }
+
+        let buf = &buf[..buf.len().min(MAX_PAYLOAD)];
+        self.output.extend(frame(BINARY, buf));
+        self.flush(socket)?;
+
+        Ok(buf.len())
+    }
+
+    /// Writes the frames that are waiting until the socket would block.
+    pub fn flush(&mut self, socket: &mut TcpStream) -> io::Result<()> {
+        while !self.output.is_empty() {
+            match socket.write(&self.output) {
+                Ok(0) => return Err(BrokenPipe.into()),
+                Ok(n) => drop(self.output.drain(..n)),
+                Err(err) if err.kind() == WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Answers the HTTP upgrade request once it's complete.
+    fn upgrade(&mut self) -> io::Result<()> {
+        let end = match self.input.windows(4).position(|x| x == b"\r\n\r\n") {
+            Some(end) => end + 4,
+
+            None if self.input.len() > MAX_HANDSHAKE => {
+                return Err(invalid("handshake too big"));
+            }
+
+            None => return Ok(()),
+        };
+
+        let request = String::from_utf8_lossy(&self.input[..end]).to_string();
+        self.input.drain(..end);
+
+        let key = request
+            .lines()
+            .filter_map(|x| x.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("sec-websocket-key"))
+            .map(|(_, value)| value.trim().to_owned());
+
+        let key = match key {
+            Some(key) if request.starts_with("GET ") => key,
+            _ => return Err(invalid("not an upgrade request")),
+        };
+
+        let mut sha1 = Sha1::new();
+        sha1.update(key.as_bytes());
+        sha1.update(GUID.as_bytes());
+        let accept = STANDARD.encode(sha1.finalize());
+
+        let response = format!(
+            "HTTP/1.1 101 Switching Protocols\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {accept}\r\n\r\n"
+        );
+
+        self.output.extend(response.as_bytes());
+        self.output.append(&mut self.early);
+        self.upgraded = true;
+
+        // Frames could have arrived with the request.
+        self.decode()
+    }
+
+    /// Moves the payloads of complete frames from the input.
+    fn decode(&mut self) -> io::Result<()> {
+        while let Some((opcode, payload, size)) = parse_frame(&self.input)? {
+            self.input.drain(..size);
+
+            match opcode {
+                CONTINUATION | BINARY => self.payload.extend(payload),
+
+                // The BITE frames are binary, text is unsupported data.
+                TEXT => {
+                    self.output.extend(frame(CLOSE, &UNSUPPORTED.to_be_bytes()));
+                    self.closed = true;
+
+                    return Ok(());
+                }
+
+                PING => self.output.extend(frame(PONG, &payload)),
+
+                PONG => {}
+
+                CLOSE => {
+                    self.output.extend(frame(CLOSE, &[]));
+                    self.closed = true;
+
+                    return Ok(());
+                }
+
+                _ => return Err(invalid("unknown opcode")),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// An unmasked frame, like servers send them.
+fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::<u8>::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode);
+
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+
+        len if len <= 0xFFFF => {
+            frame.push(126);
+            frame.extend((len as u16).to_be_bytes());
+        }
+
+        len => {
+            frame.push(127);
+            frame.extend((len as u64).to_be_bytes());
+        }
+    }
+
+    frame.extend(payload);
+    frame
+}
+
+/// The opcode, the unmasked payload and the size of the first frame, or None
+/// when it isn't complete yet. Clients always mask their frames.
+fn parse_frame(input: &[u8]) -> io::Result<Option<(u8, Vec<u8>, usize)>> {
+    if input.len() < 2 {
+        return Ok(None);
+    }
+
+    let opcode = input[0] & 0x0F;
+    let masked = input[1] & 0x80 != 0;
+
+    if !masked {
+        return Err(invalid("unmasked frame from a client"));
+    }
+
+    let (len, mut start) = match input[1] & 0x7F {
+        126 if input.len() >= 4 => (u16::from_be_bytes([input[2], input[3]]) as usize, 4),
+
+        127 if input.len() >= 10 => {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&input[2..10]);
+            (u64::from_be_bytes(bytes) as usize, 10)
+        }
+
+        126 | 127 => return Ok(None),
+
+        len => (len as usize, 2),
+    };
+
+    if len > MAX_PAYLOAD {
+        return Err(invalid("frame too big"));
+    }
+
+    if input.len() < start + 4 + len {
+        return Ok(None);
+    }
+
+    let mask = &input[start..start + 4];
+    start += 4;
+
+    let payload = input[start..start + len]
+        .iter()
+        .enumerate()
+        .map(|(i, x)| x ^ mask[i % 4])
+        .collect();
+
+    Ok(Some((opcode, payload, start + len)))
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(InvalidData, format!("WebSocket: {reason}"))
+}
@@ -7,7 +7,12 @@ use std::{
     time::Instant,
 };
 
-use crate::{cleaner, connection::Connection, message::stamp_header};
+use crate::{
+    cleaner,
+    connection::{Connection, Limits},
+    message::stamp_header,
+    supervisor::{lock, Error},
+};
 
 use polling::{Event, Poller};
 
@@ -15,6 +20,8 @@ pub enum Action {
     Queue(Order),
     QueueAll(Vec<Order>),
     Write(usize),
+    Flush(Sender<()>),
+    Stop,
 }
 
 pub struct Order {
@@ -27,92 +34,143 @@ pub struct Order {
 pub struct Writer {
     poller: Arc<Poller>,
     writers: Arc<Mutex<HashMap<usize, Connection>>>,
+    limits: Limits,
     pub tx: Sender<Action>,
     rx: Receiver<Action>,
 }
 
 impl Writer {
-    pub fn new(poller: Arc<Poller>, writers: Arc<Mutex<HashMap<usize, Connection>>>) -> Writer {
+    pub fn new(
+        poller: Arc<Poller>,
+        writers: Arc<Mutex<HashMap<usize, Connection>>>,
+        limits: Limits,
+    ) -> Writer {
         let (tx, rx) = channel::<Action>();
 
         Writer {
             poller,
             writers,
+            limits,
             tx,
             rx,
         }
     }
 
-    pub fn handle(&self, cleaner_tx: Sender<cleaner::Action>) {
+    pub fn handle(&self, cleaner_tx: Sender<cleaner::Action>) -> Result<(), Error> {
         loop {
-            match self.rx.recv().unwrap() {
+            match self.rx.recv()? {
                 Action::Queue(order) => {
-                    if let Some(connection) = self.writers.lock().unwrap().get_mut(&order.to_id) {
-                        connection.send_queue.push(stamp_header(
-                            order.data,
-                            order.from_id as u32,
-                            order.msg_id as u32,
-                        ));
-
-                        self.poll_writable(connection);
+                    let id = order.to_id;
+
+                    let closed = match lock(&self.writers).get_mut(&id) {
+                        Some(connection) => !self.queue(connection, order),
+                        None => false,
+                    };
+
+                    if closed {
+                        cleaner_tx.send(cleaner::Action::Drop(id))?;
                     }
                 }
 
                 Action::QueueAll(orders) => {
-                    let mut writers = self.writers.lock().unwrap();
+                    let mut closed = Vec::<usize>::new();
+
+                    let mut writers = lock(&self.writers);
                     for order in orders {
                         if let Some(connection) = writers.get_mut(&order.to_id) {
-                            connection.send_queue.push(stamp_header(
-                                order.data,
-                                order.from_id as u32,
-                                order.msg_id as u32,
-                            ));
-
-                            self.poll_writable(connection);
+                            if !self.queue(connection, order) {
+                                closed.push(connection.id);
+                            }
                         }
                     }
+                    drop(writers);
+
+                    for id in closed {
+                        cleaner_tx.send(cleaner::Action::Drop(id))?;
+                    }
                 }
 
                 Action::Write(id) => {
                     let mut closed = false;
 
-                    if let Some(connection) = self.writers.lock().unwrap().get_mut(&id) {
-                        if !connection.send_queue.is_empty() {
-                            let data = connection.send_queue.remove(0);
+                    if let Some(connection) = lock(&self.writers).get_mut(&id) {
+                        if !connection.send_queue.is_empty() || connection.socket.wants_write() {
+                            match connection.try_write() {
+                                Ok(0) => {}
 
-                            if let Err(err) = connection.try_write(data) {
-                                info!("Connection #{id} broken, write failed: {err}");
-                            }
+                                Ok(_) => connection.last_write = Instant::now(),
 
-                            connection.last_write = Instant::now();
+                                Err(err) => info!("Connection #{id} broken, write failed: {err}"),
+                            }
                         }
 
                         if connection.closed {
-                            closed = true;
-                        } else if !connection.send_queue.is_empty() {
+                            // Waiting for the cleaner.
+                        } else if !connection.send_queue.is_empty()
+                            || connection.socket.wants_write()
+                        {
                             self.poll_writable(connection);
                         } else {
                             self.poll_none(connection);
                         }
+
+                        closed = connection.closed;
                     }
 
                     if closed {
-                        cleaner_tx.send(cleaner::Action::Drop(id)).unwrap();
+                        cleaner_tx.send(cleaner::Action::Drop(id))?;
                     }
                 }
+
+                // Everything before this is already on the send queues.
+                // The main thread could have stopped waiting already.
+                Action::Flush(done) => {
+                    let _ = done.send(());
+                }
+
+                // The server stopped.
+                Action::Stop => return Ok(()),
             }
         }
     }
 
+    /// Stamps and queues the order respecting the limits. Returns false when
+    /// the connection needs to be dropped because of an overflow.
+    fn queue(&self, connection: &mut Connection, order: Order) -> bool {
+        // Already waiting for the cleaner.
+        if connection.closed {
+            return true;
+        }
+
+        let data = stamp_header(order.data, order.from_id as u32, order.msg_id as u32);
+
+        if !connection.queue(data, &self.limits) {
+            info!("Connection #{} closed, send queue overflow", connection.id);
+
+            return false;
+        }
+
+        self.poll_writable(connection);
+
+        !connection.closed
+    }
+
     fn poll_writable(&self, connection: &mut Connection) {
-        self.poller
-            .modify(&connection.socket, Event::writable(connection.id))
-            .unwrap();
+        let event = Event::writable(connection.id);
+        self.poll(connection, event);
     }
 
     fn poll_none(&self, connection: &mut Connection) {
-        self.poller
-            .modify(&connection.socket, Event::none(connection.id))
-            .unwrap();
+        let event = Event::none(connection.id);
+        self.poll(connection, event);
+    }
+
+    /// A socket the poller can't watch is closed.
+    fn poll(&self, connection: &mut Connection, event: Event) {
+        if let Err(err) = self.poller.modify(&connection.socket, event) {
+            connection.closed = true;
+
+            info!("Connection #{} closed, poll failed: {err}", connection.id);
+        }
     }
 }
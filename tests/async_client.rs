@@ -0,0 +1,87 @@
+#![cfg(feature = "async")]
+
+mod common;
+
+use std::{future::Future, time::Duration};
+
+use bite::{async_client::AsyncClient, client::Sub};
+use common::{TestServer, TIMEOUT};
+use tokio::{
+    runtime,
+    sync::mpsc::unbounded_channel,
+    time::{sleep, timeout, Instant},
+};
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    let runtime = runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    runtime.block_on(future)
+}
+
+#[test]
+fn commands_and_subscriptions() {
+    let server = TestServer::start();
+    let address = server.address().to_string();
+
+    block_on(async {
+        let client = AsyncClient::connect(&address).await.unwrap();
+        let other = AsyncClient::connect(&address).await.unwrap();
+
+        let (tx, mut rx) = unbounded_channel();
+        client.on_message(move |message| {
+            let _ = tx.send(message.data);
+        });
+
+        client.set("room", b"  Hall").await.unwrap();
+        assert_eq!(client.get("room").await.unwrap(), b"  Hall");
+        assert_eq!(client.inc("visits").await.unwrap(), 1);
+        assert_eq!(
+            client.get_list(&["room", "missing"]).await.unwrap(),
+            [Some(b"  Hall".to_vec()), None]
+        );
+
+        client.subscribe(Sub::KeyValue, "player").await.unwrap();
+        other.set("player.1", b"Adros").await.unwrap();
+
+        let received = timeout(TIMEOUT, rx.recv()).await.unwrap();
+        assert_eq!(received.unwrap(), b"1 Adros");
+    });
+}
+
+#[test]
+fn async_reconnects_and_replays_the_session() {
+    let first = TestServer::start();
+    let address = first.address().to_string();
+
+    block_on(async {
+        let client = AsyncClient::connect(&address).await.unwrap();
+
+        let (tx, mut rx) = unbounded_channel();
+        client.on_message(move |message| {
+            let _ = tx.send(message.data);
+        });
+
+        client.namespace("game").await.unwrap();
+        client.subscribe(Sub::Get, "room").await.unwrap();
+
+        first.server.stop().unwrap();
+        let _second = TestServer::with(|builder| builder.address(&address));
+
+        let started = Instant::now();
+        while client.get("room").await.is_err() {
+            assert!(started.elapsed() < TIMEOUT, "reconnected");
+            sleep(Duration::from_millis(50)).await;
+        }
+
+        let other = AsyncClient::connect(&address).await.unwrap();
+        other.namespace("game").await.unwrap();
+        other.set("room", b"Hall").await.unwrap();
+
+        let received = timeout(TIMEOUT, rx.recv()).await.unwrap();
+        assert_eq!(received.unwrap(), b"Hall");
+        assert_eq!(client.get("room").await.unwrap(), b"Hall");
+    });
+}
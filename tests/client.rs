@@ -0,0 +1,197 @@
+mod common;
+
+use std::{
+    sync::mpsc::channel,
+    thread,
+    time::{Duration, Instant},
+};
+
+use bite::client::{Client, Sub};
+use common::{TestServer, TIMEOUT};
+
+#[test]
+fn values_are_never_errors() {
+    let server = TestServer::start();
+    let client = Client::connect(&server.address().to_string()).unwrap();
+
+    client.set("answer", b"NO").unwrap();
+    client.set("empty", b"").unwrap();
+
+    assert_eq!(client.get("answer").unwrap(), b"NO");
+    assert_eq!(client.get("empty").unwrap(), b"");
+    assert_eq!(client.get("missing").unwrap(), b"");
+
+    client.set("spaces", b"  two").unwrap();
+    client.append("spaces", b" more").unwrap();
+    assert_eq!(client.get("spaces").unwrap(), b"  two more");
+
+    assert_eq!(
+        client.get_list(&["answer", "empty", "missing"]).unwrap(),
+        [Some(b"NO".to_vec()), Some(vec![]), None]
+    );
+
+    client.set_if_none("new", b"value").unwrap();
+    client.set_if_none("new", b"other").unwrap();
+    assert_eq!(client.get("new").unwrap(), b"value");
+}
+
+#[test]
+fn subscriptions_call_the_handler() {
+    let server = TestServer::start();
+    let client = Client::connect(&server.address().to_string()).unwrap();
+    let other = Client::connect(&server.address().to_string()).unwrap();
+
+    let (tx, rx) = channel();
+    client.on_message(move |message| {
+        let _ = tx.send(message.data);
+    });
+
+    client.subscribe(Sub::Get, "room").unwrap();
+    client.subscribe(Sub::FullKey, "player").unwrap();
+
+    other.set("room", b"Hall").unwrap();
+    assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), b"Hall");
+
+    other.set("player.1", b"Adros").unwrap();
+    assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), b"player.1 Adros");
+
+    other.call("room", b"Knock").unwrap();
+    assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), b"Knock");
+
+    client.unsubscribe("room").unwrap();
+    other.set("room", b"Empty").unwrap();
+    other.set("player.2", b"Eve").unwrap();
+    assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), b"player.2 Eve");
+}
+
+#[test]
+fn reconnects_and_replays_the_session() {
+    let first = TestServer::start();
+    let address = first.address().to_string();
+    let client = Client::connect(&address).unwrap();
+
+    let (tx, rx) = channel();
+    client.on_message(move |message| {
+        let _ = tx.send(message.data);
+    });
+
+    client.namespace("game").unwrap();
+    client.subscribe(Sub::Get, "room").unwrap();
+
+    // The pending requests fail while the server is gone.
+    first.server.stop().unwrap();
+    assert!(client.get("room").is_err());
+
+    let _second = TestServer::with(|builder| builder.address(&address));
+
+    let started = Instant::now();
+    while client.get("room").is_err() {
+        assert!(started.elapsed() < TIMEOUT, "reconnected");
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    let other = Client::connect(&address).unwrap();
+    other.namespace("game").unwrap();
+    other.set("room", b"Hall").unwrap();
+
+    assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), b"Hall");
+    assert_eq!(client.get("room").unwrap(), b"Hall");
+}
+
+#[test]
+fn json_into_keys() {
+    let server = TestServer::start();
+    let client = Client::connect(&server.address().to_string()).unwrap();
+
+    let json = r#"{ "name": "Andrés", "likes": ["Bananas", "Apples"] }"#;
+    client.set_json("adros", json).unwrap();
+    client.set_json("adros", r#"{ "age": 36 }"#).unwrap();
+
+    assert_eq!(client.get("adros.likes.1").unwrap(), b"Apples");
+    assert_eq!(client.get("adros.age").unwrap(), b"36");
+
+    client.replace_json("adros", r#"{ "age": 37 }"#).unwrap();
+    assert_eq!(client.get("adros.name").unwrap(), b"");
+    assert_eq!(client.get("adros.age").unwrap(), b"37");
+
+    assert!(client.set_json("adros", "not json").is_err());
+}
+
+#[test]
+fn patches() {
+    let server = TestServer::start();
+    let client = Client::connect(&server.address().to_string()).unwrap();
+
+    client
+        .set_json("adros", r#"{ "age": 36, "likes": ["Bananas"] }"#)
+        .unwrap();
+
+    client
+        .merge_patch("adros", r#"{ "age": 37, "likes": null }"#)
+        .unwrap();
+    assert_eq!(client.get("adros.age").unwrap(), b"37");
+    assert_eq!(client.get("adros.likes.0").unwrap(), b"");
+
+    let test =
+        r#"[{ "op": "test", "path": "/age", "value": 36 }, { "op": "remove", "path": "/age" }]"#;
+    assert!(client.json_patch("adros", test).is_err());
+    assert_eq!(client.get("adros.age").unwrap(), b"37");
+
+    let test =
+        r#"[{ "op": "test", "path": "/age", "value": 37 }, { "op": "remove", "path": "/age" }]"#;
+    client.json_patch("adros", test).unwrap();
+    assert_eq!(client.get("adros.age").unwrap(), b"");
+}
+
+#[test]
+fn key_pages_are_never_errors() {
+    let server = TestServer::start();
+    let client = Client::connect(&server.address().to_string()).unwrap();
+
+    client.set("DENIED", b"").unwrap();
+    assert_eq!(client.keys("DENIED", 10, "").unwrap(), ["DENIED"]);
+
+    for key in ["player.1.name", "player.2.name", "room"] {
+        client.set(key, b"value").unwrap();
+    }
+
+    assert_eq!(
+        client.keys("player", 1, "player.1.name").unwrap(),
+        ["player.2.name"]
+    );
+    assert!(client
+        .keys("player", 1, "player.2.name")
+        .unwrap()
+        .is_empty());
+
+    assert_eq!(client.children(".", 2, "").unwrap(), ["DENIED", "player"]);
+    assert_eq!(client.children(".", 2, "player").unwrap(), ["room"]);
+
+    assert!(client.keys("player", 0, "").is_err());
+}
+
+#[test]
+fn queries_up_to_a_depth() {
+    let server = TestServer::start();
+    let client = Client::connect(&server.address().to_string()).unwrap();
+
+    for key in ["player.1.name", "player.1.pos.x", "player.10.name"] {
+        client.set(key, b"value").unwrap();
+    }
+
+    let keys = |depth| -> Vec<String> {
+        let key_values = client.key_values("player.1", depth).unwrap();
+        key_values.into_iter().map(|(key, _)| key).collect()
+    };
+
+    assert_eq!(keys(None), ["name", "x"]);
+    assert_eq!(keys(Some(1)), ["name"]);
+
+    let json: serde_json::Value =
+        serde_json::from_str(&client.jtrim("player.1", Some(1)).unwrap()).unwrap();
+    assert_eq!(json, serde_json::json!({ "name": "value" }));
+
+    let json: serde_json::Value =
+        serde_json::from_str(&client.json("player.1", None).unwrap()).unwrap();
+    assert_eq!(json["player"]["1"]["pos"]["x"], "value");
+}
@@ -0,0 +1,636 @@
+mod common;
+
+use std::{
+    net::{SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+use bite::message::{get_values, stamp_header, Message};
+use common::{u64_reply, Conn, TestServer, TIMEOUT};
+use serde_json::json;
+
+#[test]
+fn set_get_and_delete() {
+    let server = TestServer::start();
+    let mut conn = server.connect();
+
+    assert_eq!(conn.text("s data.name BITE"), "OK");
+    assert_eq!(conn.text("g data.name"), "BITE");
+
+    assert_eq!(conn.text("s data.name Simplest database ever"), "OK");
+    assert_eq!(conn.text("g data.name"), "Simplest database ever");
+
+    assert_eq!(conn.text("d data.name"), "OK");
+    assert_eq!(conn.text("g data.name"), "");
+}
+
+#[test]
+fn values_after_their_size() {
+    let server = TestServer::start();
+    let mut conn = server.connect();
+
+    // The spaces at the start are part of the value.
+    assert_eq!(conn.text("s: key \0\x05  abc"), "OK");
+    assert_eq!(conn.text("g key"), "  abc");
+
+    assert_eq!(conn.text("+: key \0\x01 "), "OK");
+    assert_eq!(conn.text("g key"), "  abc ");
+
+    // A size that isn't the rest of the message.
+    assert_eq!(conn.text("s: key \0\x09  abc"), "NO");
+    assert_eq!(conn.text("s: key"), "NO");
+}
+
+#[test]
+fn set_if_none() {
+    let server = TestServer::start();
+    let mut conn = server.connect();
+
+    assert_eq!(conn.text("s? key first"), "OK");
+    assert_eq!(conn.text("s? key second"), "OK");
+    assert_eq!(conn.text("g key"), "first");
+}
+
+#[test]
+fn set_list() {
+    let server = TestServer::start();
+    let mut conn = server.connect();
+
+    let reply = conn.text("sl | data.name BITE|data.why Simplest, ever|data.author.name Andrés");
+    assert_eq!(reply, "OK");
+
+    assert_eq!(conn.text("g data.name"), "BITE");
+    assert_eq!(conn.text("g data.why"), "Simplest, ever");
+    assert_eq!(conn.text("g data.author.name"), "Andrés");
+
+    // Only the keys can't have the namespace separator.
+    assert_eq!(conn.text("sl | binary \u{1F}|other value"), "OK");
+    assert_eq!(conn.text("g binary"), "\u{1F}");
+    assert_eq!(conn.text("sl | bad\u{1F}key value"), "NO");
+}
+
+#[test]
+fn get_list() {
+    let server = TestServer::start();
+    let mut conn = server.connect();
+
+    conn.request("sl | data.name BITE|data.why Simplest, ever|data.empty");
+    assert_eq!(conn.text("g data.empty"), "");
+
+    // In the same order, and missing keys apart from empty values.
+    let reply = conn.request("gl , data.why,data.missing,data.empty, data.name");
+    assert_eq!(
+        get_values(&reply).unwrap(),
+        [
+            Some(b"Simplest, ever".to_vec()),
+            None,
+            Some(vec![]),
+            Some(b"BITE".to_vec())
+        ]
+    );
+
+    // Only the keys of the namespace.
+    conn.request("n game1");
+    conn.request("s data.name Game");
+    let reply = conn.request("gl | data.name|data.why");
+    assert_eq!(get_values(&reply).unwrap(), [Some(b"Game".to_vec()), None]);
+
+    // Too big for one message.
+    let big = "x".repeat(40000);
+    conn.request(&format!("s big.1 {big}"));
+    conn.request(&format!("s big.2 {big}"));
+    let reply = conn.request("gl | big.1");
+    assert_eq!(get_values(&reply).unwrap(), [Some(big.into_bytes())]);
+    assert_eq!(conn.text("gl | big.1|big.2"), "NO");
+}
+
+#[test]
+fn inc() {
+    let server = TestServer::start();
+    let mut conn = server.connect();
+
+    assert_eq!(u64_reply(&conn.request("+1 counter")), 1);
+    assert_eq!(u64_reply(&conn.request("+1 counter")), 2);
+    assert_eq!(u64_reply(&conn.request("g counter")), 2);
+
+    // Not a number becomes 0.
+    conn.request("s text hello");
+    assert_eq!(u64_reply(&conn.request("+1 text")), 1);
+}
+
+#[test]
+fn inc_stops_at_the_maximum() {
+    let server = TestServer::start();
+    let mut conn = server.connect();
+
+    let mut command = b"s max ".to_vec();
+    command.extend(u64::MAX.to_be_bytes());
+    let id = conn.id;
+    conn.send_as(id, &command);
+    conn.recv();
+
+    assert_eq!(u64_reply(&conn.request("+1 max")), u64::MAX);
+}
+
+#[test]
+fn append() {
+    let server = TestServer::start();
+    let mut conn = server.connect();
+
+    assert_eq!(conn.text("+ list one"), "OK");
+    assert_eq!(conn.text("+ list , two"), "OK");
+    assert_eq!(conn.text("g list"), "one, two");
+}
+
+#[test]
+fn key_values() {
+    let server = TestServer::start();
+    let mut conn = server.connect();
+
+    conn.request("s data.name BITE");
+    conn.request("s data.author.name Andrés");
+    conn.request("s other.name Not this one");
+
+    let reply = conn.request("k data");
+    let mut pairs: Vec<&[u8]> = reply.split(|x| *x == 0).collect();
+    pairs.sort();
+
+    assert_eq!(pairs, vec!["name Andrés".as_bytes(), b"name BITE"]);
+    assert_eq!(conn.text("k nothing"), "");
+}
+
+#[test]
+fn json() {
+    let server = TestServer::start();
+    let mut conn = server.connect();
+
+    conn.request("s data.name BITE");
+    conn.request("s data.author.name Andrés");
+
+    let trimmed: serde_json::Value = serde_json::from_slice(&conn.request("j data")).unwrap();
+    assert_eq!(
+        trimmed,
+        json!({ "name": "BITE", "author": { "name": "Andrés" } })
+    );
+
+    let full: serde_json::Value = serde_json::from_slice(&conn.request("js data")).unwrap();
+    assert_eq!(full["data"]["author"]["name"], "Andrés");
+
+    assert_eq!(conn.text("j data.name"), r#""BITE""#);
+    assert_eq!(conn.text("j nothing"), "{}");
+    assert_eq!(conn.text("js nothing"), "{}");
+}
+
+#[test]
+fn queries_on_segments_with_depth() {
+    let server = TestServer::start();
+    let mut conn = server.connect();
+
+    conn.request("s player.1.name Adros");
+    conn.request("s player.1.pos.x 1");
+    conn.request("s player.10.name Other");
+
+    // player.10 isn't a child of player.1.
+    let value: serde_json::Value = serde_json::from_slice(&conn.request("j player.1")).unwrap();
+    assert_eq!(value, json!({ "name": "Adros", "pos": { "x": 1 } }));
+    assert_eq!(conn.text("k player.1.pos"), "x 1");
+
+    // Only the direct children.
+    let value: serde_json::Value = serde_json::from_slice(&conn.request("j player.1 1")).unwrap();
+    assert_eq!(value, json!({ "name": "Adros" }));
+
+    let value: serde_json::Value = serde_json::from_slice(&conn.request("js player.1 1")).unwrap();
+    assert_eq!(value, json!({ "player": { "1": { "name": "Adros" } } }));
+
+    assert_eq!(conn.text("k player.1 1"), "name Adros");
+    assert_eq!(conn.text("k . 2"), "");
+    assert_eq!(conn.text("k player.1 deep"), "NO");
+}
+
+#[test]
+fn json_values() {
+    let server = TestServer::start();
+    let mut conn = server.connect();
+
+    // A key with a value and children, in any order.
+    conn.request("s data.name.first Andrés");
+    conn.request("s data BITE");
+    conn.request("s data.name Full name");
+
+    conn.request("s numbers.int 42");
+    conn.request("s numbers.float -1.5");
+    conn.request("s numbers.padded 007");
+    conn.request("+1 numbers.counter");
+
+    let id = conn.id;
+    conn.send_as(id, b"s binary \xff\x00\x01");
+    conn.recv();
+
+    let value: serde_json::Value = serde_json::from_slice(&conn.request("j")).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "data": {
+                "@value": "BITE",
+                "name": { "@value": "Full name", "first": "Andrés" }
+            },
+            "numbers": { "int": 42, "float": -1.5, "padded": "007", "counter": 1 },
+            "binary": { "@base64": "/wAB" }
+        })
+    );
+}
+
+#[test]
+fn set_json() {
+    let server = TestServer::start();
+    let mut conn = server.connect();
+
+    conn.request("s player.1984.old Gone on replace");
+
+    let document = r#"{ "name": "Adros", "age": 36, "pos": [100, 200], "@value": "root" }"#;
+    assert_eq!(conn.text(&format!("sj player.1984 {document}")), "OK");
+
+    assert_eq!(conn.text("g player.1984"), "root");
+    assert_eq!(conn.text("g player.1984.name"), "Adros");
+    assert_eq!(conn.text("g player.1984.age"), "36");
+    assert_eq!(conn.text("g player.1984.pos.1"), "200");
+    assert_eq!(conn.text("g player.1984.old"), "Gone on replace");
+
+    // What j returns goes back the same.
+    let exported = conn.text("j player.1984");
+    assert_eq!(conn.text(&format!("sj! copy {exported}")), "OK");
+    assert_eq!(conn.text("j copy"), exported);
+
+    assert_eq!(conn.text(r#"sj! player.1984 { "name": "Other" }"#), "OK");
+    let value: serde_json::Value = serde_json::from_slice(&conn.request("j player")).unwrap();
+    assert_eq!(value, json!({ "1984": { "name": "Other" } }));
+
+    assert_eq!(conn.text("sj player.1984 {not json"), "NO");
+    assert_eq!(conn.text(r#"sj player.1984 { "a\u001fb": 1 }"#), "NO");
+}
+
+#[test]
+fn merge_patch() {
+    let server = TestServer::start();
+    let mut conn = server.connect();
+
+    conn.request(r#"sj player { "name": "Adros", "pos": { "x": 1, "y": 2 } }"#);
+    conn.request("+1 player.score");
+
+    let patch = r#"{ "name": "Andrés", "pos": { "y": null, "z": 3 }, "likes": ["Bananas"] }"#;
+    assert_eq!(conn.text(&format!("jm player {patch}")), "OK");
+
+    assert_eq!(conn.text("g player.name"), "Andrés");
+    assert_eq!(conn.text("g player.pos.y"), "");
+    assert_eq!(conn.text("g player.pos.z"), "3");
+    assert_eq!(conn.text("g player.likes.0"), "Bananas");
+
+    // Untouched values keep their bytes.
+    assert_eq!(u64_reply(&conn.request("g player.score")), 1);
+
+    assert_eq!(conn.text("jm player {not json"), "NO");
+}
+
+#[test]
+fn json_patch() {
+    let server = TestServer::start();
+    let mut conn = server.connect();
+
+    conn.request(r#"sj player { "name": "Adros", "pos": { "x": 1 }, "hp": 10 }"#);
+
+    let patch = r#"[
+        { "op": "test", "path": "/hp", "value": 10 },
+        { "op": "replace", "path": "/hp", "value": 9 },
+        { "op": "add", "path": "/pos/y", "value": 2 },
+        { "op": "move", "from": "/name", "path": "/alias" },
+        { "op": "copy", "from": "/pos", "path": "/spawn" },
+        { "op": "remove", "path": "/pos/x" }
+    ]"#;
+    assert_eq!(conn.text(&format!("jp player {patch}")), "OK");
+
+    let value: serde_json::Value = serde_json::from_slice(&conn.request("j player")).unwrap();
+    assert_eq!(
+        value,
+        json!({ "alias": "Adros", "hp": 9, "pos": { "y": 2 }, "spawn": { "x": 1, "y": 2 } })
+    );
+
+    // A failed test changes nothing.
+    let patch = r#"[
+        { "op": "replace", "path": "/hp", "value": 0 },
+        { "op": "test", "path": "/hp", "value": 10 }
+    ]"#;
+    assert_eq!(conn.text(&format!("jp player {patch}")), "NO");
+    assert_eq!(conn.text("g player.hp"), "9");
+
+    assert_eq!(
+        conn.text(r#"jp player [{ "op": "remove", "path": "/nothing" }]"#),
+        "NO"
+    );
+}
+
+#[test]
+fn key_listing() {
+    let server = TestServer::start();
+    let mut conn = server.connect();
+
+    for key in [
+        "player.1",
+        "player.1.name",
+        "player.1.pos.x",
+        "player.10",
+        "room",
+    ] {
+        conn.request(&format!("s {key} value"));
+    }
+
+    let keys = |conn: &mut common::Conn, command: &str| -> Vec<String> {
+        let reply = conn.text(command);
+        reply
+            .split('\0')
+            .filter(|x| !x.is_empty())
+            .map(String::from)
+            .collect()
+    };
+
+    // On segment boundaries, player.10 isn't a child of player.1.
+    assert_eq!(
+        keys(&mut conn, "gk player.1"),
+        ["player.1", "player.1.name", "player.1.pos.x"]
+    );
+
+    // Each child once, with or without a value of its own.
+    assert_eq!(
+        keys(&mut conn, "gc player.1"),
+        ["player.1.name", "player.1.pos"]
+    );
+    assert_eq!(keys(&mut conn, "gc ."), ["player", "room"]);
+
+    // Pages, after the last key of the previous one.
+    assert_eq!(keys(&mut conn, "gk . 2"), ["player.1", "player.1.name"]);
+    assert_eq!(
+        keys(&mut conn, "gk . 2 player.1.name"),
+        ["player.1.pos.x", "player.10"]
+    );
+    assert_eq!(keys(&mut conn, "gk . 2 player.10"), ["room"]);
+    assert!(keys(&mut conn, "gk . 2 room").is_empty());
+
+    assert_eq!(conn.text("gk player 0"), "NO");
+
+    // Only the keys of the namespace.
+    conn.request("n game1");
+    conn.request("s player.1 value");
+    assert_eq!(keys(&mut conn, "gk"), ["player.1"]);
+
+    // Pages on the children, after the last child of the previous one.
+    for key in ["player-x", "player.2.name", "room.1", "room.2.name"] {
+        conn.request(&format!("s {key} value"));
+    }
+
+    assert_eq!(keys(&mut conn, "gc . 2"), ["player", "player-x"]);
+    assert_eq!(keys(&mut conn, "gc . 2 player-x"), ["room"]);
+    assert!(keys(&mut conn, "gc . 2 room").is_empty());
+    assert_eq!(keys(&mut conn, "gc player"), ["player.1", "player.2"]);
+}
+
+#[test]
+fn children_pages() {
+    let server = TestServer::start();
+    let mut conn = server.connect();
+
+    // More children than a page, some only with children of their own, and
+    // some that sort before the keys of a shorter one, like "b-x" and "b.x".
+    let mut expected = Vec::<String>::new();
+    for i in 0..120 {
+        let child = format!("list.{i:03}");
+        let key = match i % 3 {
+            0 => child.to_owned(),
+            1 => format!("{child}.name"),
+            _ => format!("{child}-x.{i}"),
+        };
+
+        conn.request(&format!("s {key} value"));
+        conn.request(&format!("s {child}.a.b value"));
+
+        expected.push(child.to_owned());
+        if i % 3 == 2 {
+            expected.push(format!("{child}-x"));
+        }
+    }
+    expected.sort();
+
+    let mut children = Vec::<String>::new();
+    let mut after = String::new();
+
+    loop {
+        let reply = conn.text(&format!("gc list 7 {after}"));
+        let page: Vec<String> = reply
+            .split('\0')
+            .filter(|x| !x.is_empty())
+            .map(String::from)
+            .collect();
+
+        match page.last() {
+            Some(last) => after = last.to_owned(),
+            None => break,
+        }
+
+        assert!(page.len() <= 7);
+        children.extend(page);
+    }
+
+    assert_eq!(children, expected);
+}
+
+#[test]
+fn wrong_commands() {
+    let server = TestServer::start();
+    let mut conn = server.connect();
+
+    assert_eq!(conn.text("nope key value"), "NO");
+    assert_eq!(conn.text("s"), "NO");
+    assert_eq!(conn.text("g"), "NO");
+}
+
+#[test]
+fn namespaces() {
+    let server = TestServer::start();
+    let mut game1 = server.connect();
+    let mut game2 = server.connect();
+
+    assert_eq!(game1.text("n game1"), "OK");
+    assert_eq!(game2.text("n game2"), "OK");
+
+    game1.request("s player.name Adros");
+    game2.request("s player.name Other");
+
+    assert_eq!(game1.text("g player.name"), "Adros");
+    assert_eq!(game2.text("g player.name"), "Other");
+
+    // Back to the default namespace.
+    assert_eq!(game1.text("n"), "OK");
+    assert_eq!(game1.text("g player.name"), "");
+
+    // A dot would make the namespace look like a parent key.
+    assert_eq!(game1.text("n game.1"), "NO");
+}
+
+#[test]
+fn udp_token() {
+    let server = TestServer::start();
+    let mut conn = server.connect();
+    assert_eq!(conn.text("u"), "NO");
+
+    let server = TestServer::with(|builder| builder.udp("127.0.0.1:0"));
+    let mut conn = server.connect();
+    let token = conn.text("u");
+
+    assert_eq!(token.len(), 16);
+    assert!(token.chars().all(|x| x.is_ascii_hexdigit()));
+}
+
+/// A UDP socket for the client, with the address of the server.
+fn udp_socket(server: &TestServer) -> (UdpSocket, SocketAddr) {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    (socket, server.addresses().udp.unwrap())
+}
+
+/// The next datagram, or none when nothing arrives in a little while.
+fn udp_recv(socket: &UdpSocket, wait: Duration) -> Option<Message> {
+    socket.set_read_timeout(Some(wait)).unwrap();
+
+    let mut buffer = [0; 65535];
+    let size = socket.recv(&mut buffer).ok()?;
+
+    Message::from_protocol(buffer[..size].to_vec()).ok()
+}
+
+/// Binds the socket to the connection with a fresh token.
+fn udp_bind(conn: &mut Conn, socket: &UdpSocket, server: SocketAddr) {
+    let token = conn.text("u");
+    let bind = stamp_header(format!("u {token}").into_bytes(), conn.id, 1);
+    socket.send_to(&bind, server).unwrap();
+
+    let reply = udp_recv(socket, TIMEOUT).expect("a reply to the token");
+    assert_eq!((reply.from, reply.id), (conn.id, 1));
+    assert_eq!(reply.data, b"OK");
+}
+
+#[test]
+fn udp_binding() {
+    let server = TestServer::with(|builder| builder.udp("127.0.0.1:0"));
+    let mut conn = server.connect();
+    let (socket, address) = udp_socket(&server);
+    let silence = Duration::from_millis(200);
+
+    let token = conn.text("u");
+
+    // A wrong token, or the right one for another id, binds nothing.
+    let wrong = stamp_header(b"u 0000000000000000".to_vec(), conn.id, 1);
+    socket.send_to(&wrong, address).unwrap();
+    let other = stamp_header(format!("u {token}").into_bytes(), conn.id + 1, 1);
+    socket.send_to(&other, address).unwrap();
+    assert!(udp_recv(&socket, silence).is_none());
+
+    let bind = stamp_header(format!("u {token}").into_bytes(), conn.id, 2);
+    socket.send_to(&bind, address).unwrap();
+    let reply = udp_recv(&socket, TIMEOUT).unwrap();
+    assert_eq!((reply.id, &reply.data[..]), (2, &b"OK"[..]));
+
+    // The token is used once.
+    let (another, _) = udp_socket(&server);
+    let again = stamp_header(format!("u {token}").into_bytes(), conn.id, 3);
+    another.send_to(&again, address).unwrap();
+    assert!(udp_recv(&another, silence).is_none());
+}
+
+#[test]
+fn udp_calls() {
+    let server = TestServer::with(|builder| builder.udp("127.0.0.1:0"));
+    let mut subscriber = server.connect();
+    let mut reliable = server.connect();
+    let mut caller = server.connect();
+    let silence = Duration::from_millis(200);
+
+    let (subscriber_udp, address) = udp_socket(&server);
+    udp_bind(&mut subscriber, &subscriber_udp, address);
+    let (caller_udp, _) = udp_socket(&server);
+    udp_bind(&mut caller, &caller_udp, address);
+
+    subscriber.request("#g room");
+    reliable.request("#g room");
+
+    // Datagram calls have no reply, and reach subscribers with an endpoint as
+    // datagrams, while the rest get them on their connection.
+    let call = stamp_header(b"! room Hello".to_vec(), caller.id, 2);
+    caller_udp.send_to(&call, address).unwrap();
+
+    let datagram = udp_recv(&subscriber_udp, TIMEOUT).expect("the call as a datagram");
+    assert_eq!(datagram.data, b"Hello");
+    assert_eq!(reliable.recv().data, b"Hello");
+    subscriber.assert_silent();
+    assert!(udp_recv(&caller_udp, silence).is_none());
+
+    // Calls on a connection reach the endpoints as datagrams too.
+    assert_eq!(caller.text("! room Again"), "OK");
+    let datagram = udp_recv(&subscriber_udp, TIMEOUT).unwrap();
+    assert_eq!(datagram.data, b"Again");
+    assert_eq!(reliable.recv().data, b"Again");
+
+    // Everything else stays on the connection.
+    let set = stamp_header(b"s room Changed".to_vec(), caller.id, 3);
+    caller_udp.send_to(&set, address).unwrap();
+    assert!(udp_recv(&caller_udp, silence).is_none());
+    assert_eq!(caller.text("g room"), "");
+
+    assert_eq!(caller.text("s room Changed"), "OK");
+    assert_eq!(subscriber.recv().data, b"Changed");
+    assert!(udp_recv(&subscriber_udp, silence).is_none());
+}
+
+#[test]
+fn auth() {
+    let config = TestServer::start();
+    let path = config.file(
+        "auth.json",
+        r#"{
+            "secret": "shared",
+            "rules": {
+                "read": ["room.*"],
+                "write": ["player.{id}.*"],
+                "subscribe": ["room.*"]
+            },
+            "users": { "admin": { "password": "a password" } }
+        }"#,
+    );
+
+    let server = TestServer::with(|builder| builder.auth_file(&path));
+
+    let mut client = server.connect();
+    assert_eq!(client.greeting, b"AUTH");
+    assert_eq!(client.text("g room.name"), "AUTH");
+    assert_eq!(client.text("a wrong"), "NO");
+    assert_eq!(client.text("a shared"), "OK");
+
+    let own = format!("s player.{}.name Adros", client.id);
+    assert_eq!(client.text(&own), "OK");
+    assert_eq!(client.text("s player.0.name Not mine"), "DENIED");
+    assert_eq!(client.text("g room.name"), "");
+    assert_eq!(client.text("g secret.key"), "DENIED");
+
+    // Subscriptions with a message call the subscribers like a write.
+    assert_eq!(client.text("#g room.chat"), "OK");
+    assert_eq!(client.text("#g room.chat Spoofed"), "DENIED");
+    assert_eq!(client.text("#- room.chat Spoofed"), "DENIED");
+
+    let mut admin = server.connect();
+    assert_eq!(admin.text("a admin a password"), "OK");
+    assert_eq!(admin.text("s secret.key value"), "OK");
+
+    // Disconnected after too many failures.
+    let mut attacker = server.connect();
+    for _ in 0..3 {
+        attacker.send("a wrong");
+    }
+
+    assert!(attacker.is_closed());
+}
@@ -0,0 +1,213 @@
+#![allow(dead_code)]
+
+use std::{
+    env, fs,
+    io::{ErrorKind, Read, Write},
+    net::{SocketAddr, TcpStream},
+    path::PathBuf,
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use bite::{
+    message::{get_u32, stamp_header, Message},
+    Addresses, Builder, Server,
+};
+
+/// Time to wait for anything from the server before failing the test.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+static DIRS: AtomicUsize = AtomicUsize::new(0);
+
+/// A server with all its threads on an ephemeral port, with the snapshots on
+/// its own temporary directory.
+pub struct TestServer {
+    pub server: Server,
+    pub dir: PathBuf,
+}
+
+impl TestServer {
+    pub fn start() -> TestServer {
+        TestServer::with(|builder| builder)
+    }
+
+    /// Starts with more config on the builder.
+    pub fn with(config: impl FnOnce(Builder) -> Builder) -> TestServer {
+        let i = DIRS.fetch_add(1, Ordering::Relaxed);
+        let dir = env::temp_dir().join(format!("bite-test-{}-{i}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        TestServer::start_on(dir, config)
+    }
+
+    /// Starts again with the same directory, loading the last snapshot.
+    pub fn restart(self) -> TestServer {
+        let dir = self.dir.clone();
+        self.server.stop().unwrap();
+
+        TestServer::start_on(dir, |builder| builder)
+    }
+
+    fn start_on(dir: PathBuf, config: impl FnOnce(Builder) -> Builder) -> TestServer {
+        let db = dir.join("db.bin");
+        let builder = Server::builder()
+            .address("127.0.0.1:0")
+            .persistence(Some(db.to_str().unwrap()))
+            .drain_timeout(Duration::from_secs(1));
+
+        let server = config(builder).start().unwrap();
+
+        TestServer { server, dir }
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.server.local_addr()
+    }
+
+    /// Where the other listeners are, they bind "127.0.0.1:0" too.
+    pub fn addresses(&self) -> Addresses {
+        self.server.addresses()
+    }
+
+    pub fn connect(&self) -> Conn {
+        Conn::connect(self.address())
+    }
+
+    /// A file on the directory of the server.
+    pub fn file(&self, name: &str, content: &str) -> String {
+        let path = self.dir.join(name);
+        fs::write(&path, content).unwrap();
+        path.to_str().unwrap().into()
+    }
+}
+
+/// A connection speaking the BITE framing directly.
+pub struct Conn {
+    socket: TcpStream,
+    pub id: u32,
+    pub greeting: Vec<u8>,
+    msg_id: u32,
+}
+
+impl Conn {
+    /// Connects and reads the id.
+    pub fn connect(address: SocketAddr) -> Conn {
+        let socket = TcpStream::connect(address).unwrap();
+        socket.set_read_timeout(Some(TIMEOUT)).unwrap();
+
+        let mut conn = Conn {
+            socket,
+            id: 0,
+            greeting: Vec::new(),
+            msg_id: 0,
+        };
+
+        let message = conn.recv_raw().unwrap();
+        assert_eq!(message.id, 0, "the first message is the id");
+
+        conn.id = message.from;
+        conn.greeting = message.data;
+
+        conn
+    }
+
+    /// Sends the command with the client id, returns the message id.
+    pub fn send(&mut self, command: &str) -> u32 {
+        let id = self.id;
+        self.send_as(id, command.as_bytes())
+    }
+
+    /// Sends the bytes with any client id.
+    pub fn send_as(&mut self, from: u32, data: &[u8]) -> u32 {
+        self.msg_id += 1;
+        let message = stamp_header(data.to_vec(), from, self.msg_id);
+        self.socket.write_all(&message).unwrap();
+
+        self.msg_id
+    }
+
+    /// Sends the bytes as they are, without a header.
+    pub fn send_raw(&mut self, bytes: &[u8]) {
+        self.socket.write_all(bytes).unwrap();
+    }
+
+    /// Sends the command and waits for its reply.
+    pub fn request(&mut self, command: &str) -> Vec<u8> {
+        let msg_id = self.send(command);
+
+        loop {
+            let message = self.recv();
+            if message.from == self.id && message.id == msg_id {
+                return message.data;
+            }
+        }
+    }
+
+    /// The reply as text.
+    pub fn text(&mut self, command: &str) -> String {
+        String::from_utf8(self.request(command)).unwrap()
+    }
+
+    /// The next message that isn't a ping.
+    pub fn recv(&mut self) -> Message {
+        loop {
+            let message = self.recv_raw().expect("a message from the server");
+            if !is_ping(&message) {
+                return message;
+            }
+        }
+    }
+
+    /// The next message, or none on timeout or when the server closes.
+    pub fn recv_raw(&mut self) -> Option<Message> {
+        let mut header = [0; 6];
+        self.socket.read_exact(&mut header).ok()?;
+
+        let size = get_u32(&header[4..6]) as usize;
+        let mut data = header.to_vec();
+        data.resize(size, 0);
+        self.socket.read_exact(&mut data[6..]).ok()?;
+
+        Message::from_protocol(data).ok()
+    }
+
+    /// Waits a little, failing when anything arrives.
+    pub fn assert_silent(&mut self) {
+        self.socket
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+
+        let mut byte = [0];
+        let read = self.socket.read(&mut byte);
+        self.socket.set_read_timeout(Some(TIMEOUT)).unwrap();
+
+        match read {
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            read => panic!("expected nothing, got {read:?}"),
+        }
+    }
+
+    /// True when the server closed the connection.
+    pub fn is_closed(&mut self) -> bool {
+        let mut buffer = [0; 64];
+
+        loop {
+            match self.socket.read(&mut buffer) {
+                Ok(0) => return true,
+                Ok(_) => continue,
+                Err(err) if err.kind() == ErrorKind::ConnectionReset => return true,
+                Err(_) => return false,
+            }
+        }
+    }
+}
+
+/// Heartbeat pings, from the server with an empty message.
+pub fn is_ping(message: &Message) -> bool {
+    message.from == 0 && message.id == 0 && message.data.is_empty()
+}
+
+pub fn u64_reply(reply: &[u8]) -> u64 {
+    u64::from_be_bytes(reply.try_into().unwrap())
+}
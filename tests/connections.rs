@@ -0,0 +1,339 @@
+mod common;
+
+use std::{
+    fs, str,
+    sync::mpsc::channel,
+    thread,
+    time::{Duration, Instant},
+};
+
+use bite::{Limits, Overflow};
+use common::{is_ping, Conn, TestServer, TIMEOUT};
+
+/// Values published to a client that doesn't read, enough to fill the socket
+/// buffers and then its send queue.
+const STALLED_VALUES: usize = 400;
+
+#[test]
+fn unique_ids() {
+    let server = TestServer::start();
+    let first = server.connect();
+    let second = server.connect();
+
+    assert_ne!(first.id, 0);
+    assert_ne!(first.id, second.id);
+    assert_eq!(first.greeting, b"");
+}
+
+#[test]
+fn wrong_client_id_disconnects() {
+    let server = TestServer::start();
+    let mut conn = server.connect();
+    let mut other = server.connect();
+
+    conn.send_as(other.id, b"s key value");
+    assert!(conn.is_closed());
+
+    // Nobody else is affected.
+    assert_eq!(other.text("g key"), "");
+}
+
+#[test]
+fn messages_smaller_than_the_header_disconnect() {
+    let server = TestServer::start();
+    let mut conn = server.connect();
+
+    // A size of 3 on the header.
+    let id = conn.id as u16;
+    conn.send_raw(&[(id >> 8) as u8, id as u8, 0, 1, 0, 3]);
+    assert!(conn.is_closed());
+}
+
+#[test]
+fn pipelined_and_split_messages() {
+    let server = TestServer::start();
+    let mut conn = server.connect();
+
+    let id = conn.id;
+    let mut batch = Vec::new();
+    for i in 1..=3 {
+        let command = format!("s key{i} value{i}");
+        batch.extend(bite::message::stamp_header(command.into_bytes(), id, i));
+    }
+
+    // Several messages in one write, and one message in several.
+    let (first, rest) = batch.split_at(10);
+    conn.send_raw(first);
+    std::thread::sleep(Duration::from_millis(50));
+    conn.send_raw(rest);
+
+    for i in 1..=3 {
+        let message = conn.recv();
+        assert_eq!(message.id, i);
+        assert_eq!(message.data, b"OK");
+    }
+
+    assert_eq!(conn.text("g key3"), "value3");
+}
+
+#[test]
+fn heartbeat_pings_idle_clients() {
+    let server = TestServer::with(|builder| builder.heartbeat(Duration::from_millis(100)));
+    let mut conn = server.connect();
+
+    let start = Instant::now();
+    loop {
+        let message = conn.recv_raw().expect("a ping before the timeout");
+        if is_ping(&message) {
+            break;
+        }
+
+        assert!(start.elapsed() < TIMEOUT);
+    }
+
+    // Still connected after the ping.
+    assert_eq!(conn.text("s key value"), "OK");
+}
+
+#[test]
+fn snapshot_reload_across_restarts() {
+    let server = TestServer::start();
+    let mut conn = server.connect();
+
+    conn.request("s data.name BITE");
+    conn.request("s data.author.name Andrés");
+    conn.request("d data.name");
+    conn.request("n game1");
+    conn.request("s player.name Adros");
+
+    let server = server.restart();
+    let mut conn = server.connect();
+
+    assert_eq!(conn.text("g data.name"), "");
+    assert_eq!(conn.text("g data.author.name"), "Andrés");
+    assert_eq!(conn.text("g player.name"), "");
+
+    conn.request("n game1");
+    assert_eq!(conn.text("g player.name"), "Adros");
+}
+
+#[test]
+fn stop_closes_the_connections() {
+    let server = TestServer::start();
+    let mut conn = server.connect();
+    let address = server.address();
+
+    conn.request("s key value");
+    server.server.stop().unwrap();
+
+    assert!(conn.is_closed());
+    assert!(std::net::TcpStream::connect(address).is_err());
+}
+
+#[test]
+fn stop_joins_the_workers() {
+    // The heartbeat and the snapshots sleep for longer than the test waits.
+    let server = TestServer::with(|builder| builder.heartbeat(Duration::from_secs(60)));
+    let local = server.server.local();
+    let db = server.dir.join("db.bin");
+
+    server.connect().request("s key value");
+
+    let (tx, rx) = channel();
+    thread::spawn(move || tx.send(server.server.stop().is_ok()));
+    assert_eq!(rx.recv_timeout(TIMEOUT), Ok(true), "every thread stopped");
+
+    // Nothing is left to snapshot the data of a stopped server.
+    let snapshot = fs::read(&db).unwrap();
+    local.set("key", b"changed");
+    thread::sleep(Duration::from_secs(5));
+    assert_eq!(fs::read(&db).unwrap(), snapshot);
+}
+
+#[test]
+fn panicking_workers_restart() {
+    let server = TestServer::start();
+    let (tx, rx) = channel();
+    let mut runs = 0;
+
+    server
+        .server
+        .supervise("panicking", move || {
+            runs += 1;
+            tx.send(runs).unwrap();
+            assert!(runs > 3, "run {runs} panics");
+            Ok(())
+        })
+        .unwrap();
+
+    let runs: Vec<u32> = (0..4).map(|_| rx.recv_timeout(TIMEOUT).unwrap()).collect();
+    assert_eq!(runs, [1, 2, 3, 4], "the same work runs again");
+
+    assert_eq!(server.connect().text("s key value"), "OK");
+    server.server.stop().unwrap();
+}
+
+#[test]
+fn panicking_too_often_stops_the_server() {
+    let server = TestServer::start();
+    let mut conn = server.connect();
+
+    server
+        .server
+        .supervise("panicking", || panic!("always"))
+        .unwrap();
+
+    let (tx, rx) = channel();
+    thread::spawn(move || tx.send(server.server.wait()));
+
+    let err = rx.recv_timeout(TIMEOUT).unwrap().unwrap_err();
+    assert!(
+        err.to_string().contains("panicking panicked: always"),
+        "{err}"
+    );
+    assert!(conn.is_closed());
+}
+
+#[test]
+fn drop_oldest_keeps_the_newest() {
+    let (received, _) = stalled_client(Overflow::DropOldest);
+
+    assert!(received.len() < STALLED_VALUES, "some were dropped");
+    assert!(received.windows(2).all(|x| x[0] < x[1]), "in order");
+
+    // The queue ends with the last ones published.
+    let last: Vec<usize> = (STALLED_VALUES - 8..STALLED_VALUES).collect();
+    assert!(received.ends_with(&last));
+}
+
+#[test]
+fn drop_newest_keeps_the_oldest() {
+    let (received, _) = stalled_client(Overflow::DropNewest);
+
+    assert!(received.len() < STALLED_VALUES, "some were dropped");
+    assert!(received.windows(2).all(|x| x[0] < x[1]), "in order");
+
+    // The first ones are never dropped, the last one is.
+    assert!(received.starts_with(&[0, 1, 2, 3, 4, 5, 6, 7]));
+    assert!(received.last() < Some(&(STALLED_VALUES - 1)));
+}
+
+#[test]
+fn overflow_disconnects() {
+    let (received, mut stalled) = stalled_client(Overflow::Disconnect);
+
+    // Everything until the queue filled up, then nothing.
+    assert!(received.len() < STALLED_VALUES, "some were dropped");
+    assert_eq!(received, (0..received.len()).collect::<Vec<_>>());
+    assert!(stalled.is_closed());
+}
+
+#[test]
+fn large_replies_to_slow_readers_stay_intact() {
+    let server = TestServer::start();
+    let mut conn = server.connect();
+
+    let value: String = (0..60_000)
+        .map(|i| (b'a' + (i % 26) as u8) as char)
+        .collect();
+    conn.request(&format!("s big {value}"));
+
+    // More than the socket buffers hold, so the writes end up partial.
+    let msg_ids: Vec<u32> = (0..60).map(|_| conn.send("g big")).collect();
+    thread::sleep(Duration::from_millis(200));
+
+    for (i, msg_id) in msg_ids.into_iter().enumerate() {
+        if i % 10 == 0 {
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        let message = conn.recv();
+        assert_eq!(message.id, msg_id);
+        assert!(message.data == value.as_bytes(), "reply {i} is intact");
+    }
+}
+
+/// Publishes numbered values to a client that doesn't read until the end,
+/// with room for a few messages on its queue. The numbers it got in the end,
+/// and the client.
+fn stalled_client(overflow: Overflow) -> (Vec<usize>, Conn) {
+    let server = TestServer::with(|builder| {
+        builder.limits(Limits {
+            max_messages: 8,
+            overflow,
+            ..Limits::default()
+        })
+    });
+
+    let mut stalled = server.connect();
+    let mut setter = server.connect();
+
+    stalled.request("#g big");
+
+    let padding = "x".repeat(60_000);
+    for i in 0..STALLED_VALUES {
+        setter.request(&format!("s big {i:06}{padding}"));
+    }
+
+    // The subscriptions reach the queue after the replies to the setter.
+    thread::sleep(Duration::from_millis(500));
+
+    let mut received = Vec::<usize>::new();
+    while let Some(message) = stalled.recv_raw() {
+        if !is_ping(&message) {
+            let number = str::from_utf8(&message.data[..6]).unwrap();
+            received.push(number.parse().unwrap());
+        }
+    }
+
+    (received, stalled)
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket_only_replaces_sockets() {
+    use std::{fs, os::unix::net::UnixStream};
+
+    let server = TestServer::start();
+    let file = server.file("notes.txt", "important");
+
+    let started = bite::Server::builder()
+        .address("127.0.0.1:0")
+        .persistence(None)
+        .unix(&file, None)
+        .start();
+
+    assert!(started.is_err());
+    assert_eq!(fs::read_to_string(&file).unwrap(), "important");
+
+    // An old socket from a previous server is replaced.
+    let path = server.dir.join("bite.sock");
+    let path = path.to_str().unwrap();
+
+    let first = TestServer::with(|builder| builder.unix(path, None));
+    first.server.stop().unwrap();
+
+    let _second = TestServer::with(|builder| builder.unix(path, None));
+    assert!(UnixStream::connect(path).is_ok());
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket_has_its_mode() {
+    use std::{fs, os::unix::fs::PermissionsExt, os::unix::net::UnixStream};
+
+    let server = TestServer::start();
+    let path = server.dir.join("private.sock");
+    let path = path.to_str().unwrap();
+
+    let _private = TestServer::with(|builder| builder.unix(path, Some(0o600)));
+
+    let mode = fs::metadata(path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    assert!(UnixStream::connect(path).is_ok());
+
+    // The private directory where it was bound is gone.
+    for entry in fs::read_dir(&server.dir).unwrap() {
+        assert!(!entry.unwrap().file_type().unwrap().is_dir());
+    }
+}
@@ -0,0 +1,88 @@
+// Inputs that used to panic, and a sweep of random ones, through the same
+// entry points as the fuzz targets.
+
+use bite::{fuzz, message::stamp_header};
+
+#[test]
+fn framing_regressions() {
+    // Sizes smaller than the header, zero and bigger than the data.
+    fuzz::framing(&[0, 0, 1, 0, 1, 0, 3]);
+    fuzz::framing(&[5, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0]);
+    fuzz::framing(&[255, 0, 1, 0, 1, 255, 255, 1]);
+    fuzz::framing(&[]);
+
+    // Valid messages split in every way.
+    let mut stream = stamp_header(b"s key value".to_vec(), 1, 1);
+    stream.extend(stamp_header(b"g key".to_vec(), 1, 2));
+
+    for chunk in 0..=255 {
+        let mut bytes = vec![chunk];
+        bytes.extend(&stream);
+        fuzz::framing(&bytes);
+    }
+}
+
+#[test]
+fn parser_regressions() {
+    fuzz::parse(b"");
+    fuzz::parse(b" ");
+    fuzz::parse(b"sl");
+    fuzz::parse(b"sl |");
+    fuzz::parse(b"sl | ");
+    fuzz::parse(b"sl | |||");
+    fuzz::parse(b"sl \xff key value\xffother value");
+    fuzz::parse(b"sl \xc3\xa9 key value");
+    fuzz::parse(b"s \x1f value");
+    fuzz::parse(b"\xff\xfe \xff\xfe \xff");
+    fuzz::parse(b"k . x");
+    fuzz::parse(b"j .. 18446744073709551616");
+    fuzz::parse(b"gk . 0");
+    fuzz::parse(b"gc . 1 \x1f");
+    fuzz::parse(b"sj . {");
+    fuzz::parse(b"jp key [{}]");
+    fuzz::parse(b"s: key \xff\xff");
+    fuzz::parse(b"s: key \0");
+    fuzz::parse(b":");
+}
+
+#[test]
+fn json_regressions() {
+    // A value where a parent was expected, when the keys aren't sorted.
+    fuzz::json(b"a.b y\na x");
+    fuzz::json(b"a.b.c z\na.b y\na x");
+    fuzz::json(b"a\na.b\na..b\n.\n..");
+    fuzz::json(b"");
+    fuzz::json(b"\n\n");
+    fuzz::json(b"~1/0 x\n/ y");
+    fuzz::json(br#"[{"op":"move","from":"","path":"/a"},{"op":"add","path":"/","value":1}]"#);
+    fuzz::json(br#"[{"op":"copy","from":"/0","path":"/0/op"},{"op":"remove","path":"/-"}]"#);
+    fuzz::json(br#"{"a":{"b":null},"@base64":"!!","@value":[null,{}]}"#);
+}
+
+#[test]
+fn random_inputs() {
+    let mut random = 0x2545_F491_4F6C_DD1D_u64;
+    let mut next = move || {
+        random ^= random << 13;
+        random ^= random >> 7;
+        random ^= random << 17;
+        random
+    };
+
+    // Small alphabets, so the interesting bytes repeat.
+    let alphabet = b" .|\n\x1f\0\xff:absgjkl?+1#!-";
+
+    for _ in 0..20_000 {
+        let len = (next() % 64) as usize;
+        let bytes: Vec<u8> = (0..len)
+            .map(|_| match next() % 4 {
+                0 => next() as u8,
+                _ => alphabet[(next() % alphabet.len() as u64) as usize],
+            })
+            .collect();
+
+        fuzz::framing(&bytes);
+        fuzz::parse(&bytes);
+        fuzz::json(&bytes);
+    }
+}
@@ -0,0 +1,417 @@
+mod common;
+
+use std::{
+    env, fs,
+    io::{Read, Write},
+    net::TcpStream,
+    process,
+    sync::Arc,
+};
+
+use bite::message::{get_u32, stamp_header, Message};
+use common::{is_ping, TestServer, TIMEOUT};
+use rustls::{pki_types::ServerName, ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+fn connect(address: &str) -> TcpStream {
+    let socket = TcpStream::connect(address).unwrap();
+    socket.set_read_timeout(Some(TIMEOUT)).unwrap();
+    socket
+}
+
+/// Reads until the end of the HTTP head.
+fn read_head(socket: &mut TcpStream) -> String {
+    let mut head = Vec::<u8>::new();
+    let mut byte = [0];
+
+    while !head.ends_with(b"\r\n\r\n") {
+        socket.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+
+    String::from_utf8(head).unwrap()
+}
+
+/// A masked binary frame, like clients send them.
+fn ws_frame(payload: &[u8]) -> Vec<u8> {
+    let mask = [7, 1, 9, 8];
+    let mut frame = vec![0x82];
+
+    match payload.len() {
+        len if len < 126 => frame.push(0x80 | len as u8),
+        len => {
+            frame.push(0x80 | 126);
+            frame.extend((len as u16).to_be_bytes());
+        }
+    }
+
+    frame.extend(mask);
+    frame.extend(payload.iter().enumerate().map(|(i, x)| x ^ mask[i % 4]));
+    frame
+}
+
+/// The BITE message on the next frame from the server.
+fn ws_recv(socket: &mut TcpStream) -> Message {
+    let mut head = [0; 2];
+    socket.read_exact(&mut head).unwrap();
+    assert_eq!(head[0], 0x82, "a final binary frame");
+
+    let len = match head[1] {
+        126 => {
+            let mut len = [0; 2];
+            socket.read_exact(&mut len).unwrap();
+            u16::from_be_bytes(len) as usize
+        }
+
+        len => len as usize,
+    };
+
+    let mut payload = vec![0; len];
+    socket.read_exact(&mut payload).unwrap();
+
+    Message::from_protocol(payload).unwrap()
+}
+
+/// Sends the upgrade request with the example key of RFC 6455, and reads the
+/// head of the response.
+fn ws_upgrade(socket: &mut TcpStream) -> String {
+    socket
+        .write_all(
+            b"GET / HTTP/1.1\r\n\
+              Host: bite\r\n\
+              Upgrade: websocket\r\n\
+              Connection: Upgrade\r\n\
+              Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+              Sec-WebSocket-Version: 13\r\n\r\n",
+        )
+        .unwrap();
+
+    read_head(socket)
+}
+
+#[test]
+fn websocket_upgrade_and_binary_frames() {
+    let server = TestServer::with(|builder| builder.websocket("127.0.0.1:0"));
+    let address = server.addresses().websocket.unwrap().to_string();
+    let mut socket = connect(&address);
+
+    let head = ws_upgrade(&mut socket);
+    assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+    assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+    // The id first, like on TCP.
+    let greeting = ws_recv(&mut socket);
+    assert_eq!(greeting.id, 0);
+    let id = greeting.from;
+
+    socket
+        .write_all(&ws_frame(&stamp_header(b"s key value".to_vec(), id, 1)))
+        .unwrap();
+
+    let reply = ws_recv(&mut socket);
+    assert_eq!((reply.from, reply.id), (id, 1));
+    assert_eq!(reply.data, b"OK");
+
+    // A message split across two frames, after the header.
+    let message = stamp_header(b"g key".to_vec(), id, 2);
+    let (first, second) = message.split_at(8);
+    socket.write_all(&ws_frame(first)).unwrap();
+    socket.write_all(&ws_frame(second)).unwrap();
+
+    let reply = ws_recv(&mut socket);
+    assert_eq!(reply.id, 2);
+    assert_eq!(reply.data, b"value");
+}
+
+#[test]
+fn websocket_text_frames_close() {
+    let server = TestServer::with(|builder| builder.websocket("127.0.0.1:0"));
+    let address = server.addresses().websocket.unwrap().to_string();
+    let mut socket = connect(&address);
+
+    ws_upgrade(&mut socket);
+    let id = ws_recv(&mut socket).from;
+
+    let mut text = ws_frame(&stamp_header(b"s key value".to_vec(), id, 1));
+    text[0] = 0x81;
+    socket.write_all(&text).unwrap();
+
+    // A close frame with 1003, unsupported data, and nothing else.
+    let mut close = [0; 4];
+    socket.read_exact(&mut close).unwrap();
+    assert_eq!(close, [0x88, 2, 0x03, 0xEB]);
+    assert_eq!(socket.read(&mut [0]).unwrap(), 0);
+}
+
+/// The next BITE message over any stream that isn't a ping.
+fn read_message(socket: &mut impl Read) -> Message {
+    loop {
+        let mut data = vec![0; 6];
+        socket.read_exact(&mut data).unwrap();
+
+        data.resize(get_u32(&data[4..6]) as usize, 0);
+        socket.read_exact(&mut data[6..]).unwrap();
+
+        let message = Message::from_protocol(data).unwrap();
+        if !is_ping(&message) {
+            return message;
+        }
+    }
+}
+
+#[test]
+fn tls_with_a_self_signed_certificate() {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+    let mut roots = RootCertStore::empty();
+    roots.add(cert.cert.der().clone()).unwrap();
+
+    let dir = env::temp_dir().join(format!("bite-tls-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let cert_path = dir.join("cert.pem").to_str().unwrap().to_string();
+    let key_path = dir.join("key.pem").to_str().unwrap().to_string();
+    fs::write(&cert_path, cert.cert.pem()).unwrap();
+    fs::write(&key_path, cert.signing_key.serialize_pem()).unwrap();
+
+    let server = TestServer::with(|builder| builder.tls("127.0.0.1:0", &cert_path, &key_path));
+    let address = server.addresses().tls.unwrap().to_string();
+
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let name = ServerName::try_from("localhost").unwrap();
+    let tls = ClientConnection::new(Arc::new(config), name).unwrap();
+    let mut socket = StreamOwned::new(tls, connect(&address));
+
+    let id = read_message(&mut socket);
+    assert_eq!(id.id, 0, "the first message is the id");
+
+    socket
+        .write_all(&stamp_header(b"s secure yes".to_vec(), id.from, 1))
+        .unwrap();
+    let reply = read_message(&mut socket);
+    assert_eq!((reply.id, &reply.data[..]), (1, &b"OK"[..]));
+
+    socket
+        .write_all(&stamp_header(b"g secure".to_vec(), id.from, 2))
+        .unwrap();
+    let reply = read_message(&mut socket);
+    assert_eq!((reply.id, &reply.data[..]), (2, &b"yes"[..]));
+}
+
+/// Sends a request that closes the connection, and reads the whole response.
+fn http(address: &str, request: &str) -> String {
+    let mut socket = TcpStream::connect(address).unwrap();
+    socket.set_read_timeout(Some(TIMEOUT)).unwrap();
+
+    let request = request.replace('\n', "\r\n");
+    socket.write_all(request.as_bytes()).unwrap();
+
+    let mut response = String::new();
+    socket.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn http_values_are_never_errors() {
+    let server = TestServer::with(|builder| builder.http("127.0.0.1:0"));
+    let address = server.addresses().http.unwrap().to_string();
+    let mut conn = server.connect();
+
+    conn.request("s denied DENIED");
+    conn.request("s empty");
+
+    let response = http(&address, "GET /keys/denied HTTP/1.1\nConnection: close\n\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("\r\n\r\nDENIED"));
+
+    let response = http(&address, "GET /keys/empty HTTP/1.1\nConnection: close\n\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+
+    // The body byte for byte, even the spaces at the start.
+    let put = "PUT /keys/spaces HTTP/1.1\nContent-Length: 12\nConnection: close\n\n  two spaces";
+    assert!(http(&address, put).starts_with("HTTP/1.1 200 OK\r\n"));
+
+    let response = http(&address, "GET /keys/spaces HTTP/1.1\nConnection: close\n\n");
+    assert!(response.ends_with("\r\n\r\n  two spaces"));
+    assert_eq!(conn.text("g spaces"), "  two spaces");
+
+    let response = http(
+        &address,
+        "GET /keys/missing HTTP/1.1\nConnection: close\n\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+}
+
+#[test]
+fn http_keys_json_and_events() {
+    let server = TestServer::with(|builder| builder.http("127.0.0.1:0"));
+    let address = server.addresses().http.unwrap().to_string();
+
+    let put = "PUT /keys/data.name HTTP/1.1\nContent-Length: 4\nConnection: close\n\nBITE";
+    assert!(http(&address, put).starts_with("HTTP/1.1 200 OK\r\n"));
+
+    let response = http(
+        &address,
+        "GET /keys/data.name HTTP/1.1\nConnection: close\n\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("\r\n\r\nBITE"));
+
+    let response = http(&address, "GET /json/data HTTP/1.1\nConnection: close\n\n");
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.contains("Content-Type: application/json"));
+    let json: serde_json::Value = serde_json::from_str(body).unwrap();
+    assert_eq!(json, serde_json::json!({ "name": "BITE" }));
+
+    // Updates of the key and its children as they happen.
+    let mut events = connect(&address);
+    events
+        .write_all(b"GET /events/data HTTP/1.1\r\n\r\n")
+        .unwrap();
+
+    let head = read_head(&mut events);
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(head.contains("Content-Type: text/event-stream\r\n"));
+
+    let put = "PUT /keys/data.why HTTP/1.1\nContent-Length: 8\nConnection: close\n\nSimplest";
+    assert!(http(&address, put).starts_with("HTTP/1.1 200 OK\r\n"));
+
+    let expected = "data: data.why Simplest\n\n";
+    let mut event = vec![0; expected.len()];
+    events.read_exact(&mut event).unwrap();
+    assert_eq!(String::from_utf8_lossy(&event), expected);
+
+    let delete = "DELETE /keys/data.name HTTP/1.1\nConnection: close\n\n";
+    assert!(http(&address, delete).starts_with("HTTP/1.1 200 OK\r\n"));
+
+    let response = http(
+        &address,
+        "GET /keys/data.name HTTP/1.1\nConnection: close\n\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+}
+
+/// Sends the command as a RESP array.
+fn resp_send(socket: &mut TcpStream, args: &[&str]) {
+    let mut command = format!("*{}\r\n", args.len());
+    for arg in args {
+        command.push_str(&format!("${}\r\n{arg}\r\n", arg.len()));
+    }
+
+    socket.write_all(command.as_bytes()).unwrap();
+}
+
+/// Reads exactly the expected reply.
+fn resp_expect(socket: &mut TcpStream, expected: &str) {
+    let mut reply = vec![0; expected.len()];
+    socket.read_exact(&mut reply).unwrap();
+
+    assert_eq!(String::from_utf8_lossy(&reply), expected);
+}
+
+#[test]
+fn resp_like_redis_cli() {
+    let server = TestServer::with(|builder| builder.resp("127.0.0.1:0"));
+    let address = server.addresses().resp.unwrap().to_string();
+    let mut redis = connect(&address);
+    let mut subscriber = connect(&address);
+    let mut conn = server.connect();
+
+    resp_send(&mut redis, &["PING"]);
+    resp_expect(&mut redis, "+PONG\r\n");
+
+    resp_send(&mut redis, &["SET", "data.name", "BITE"]);
+    resp_expect(&mut redis, "+OK\r\n");
+    resp_send(&mut redis, &["GET", "data.name"]);
+    resp_expect(&mut redis, "$4\r\nBITE\r\n");
+
+    // The same keys as the binary protocol.
+    assert_eq!(conn.text("g data.name"), "BITE");
+
+    resp_send(&mut redis, &["INCR", "data.visits"]);
+    resp_expect(&mut redis, ":1\r\n");
+    resp_send(&mut redis, &["INCR", "data.visits"]);
+    resp_expect(&mut redis, ":2\r\n");
+    resp_send(&mut redis, &["GET", "data.visits"]);
+    resp_expect(&mut redis, "$1\r\n2\r\n");
+
+    resp_send(&mut subscriber, &["SUBSCRIBE", "data"]);
+    resp_expect(
+        &mut subscriber,
+        "*3\r\n$9\r\nsubscribe\r\n$4\r\ndata\r\n:1\r\n",
+    );
+
+    // Writes from any protocol reach the channel.
+    conn.request("s data.name Andrés");
+    resp_expect(
+        &mut subscriber,
+        "*3\r\n$7\r\nmessage\r\n$4\r\ndata\r\n$7\r\nAndrés\r\n",
+    );
+}
+
+#[test]
+fn resp_values_are_never_errors() {
+    let server = TestServer::with(|builder| builder.resp("127.0.0.1:0"));
+    let address = server.addresses().resp.unwrap().to_string();
+    let mut redis = connect(&address);
+
+    resp_send(&mut redis, &["SET", "key", "NO"]);
+    resp_expect(&mut redis, "+OK\r\n");
+    resp_send(&mut redis, &["GET", "key"]);
+    resp_expect(&mut redis, "$2\r\nNO\r\n");
+
+    resp_send(&mut redis, &["APPEND", "key", "PE"]);
+    resp_expect(&mut redis, ":4\r\n");
+
+    // Empty values exist.
+    resp_send(&mut redis, &["SET", "empty", ""]);
+    resp_expect(&mut redis, "+OK\r\n");
+    resp_send(&mut redis, &["GET", "empty"]);
+    resp_expect(&mut redis, "$0\r\n\r\n");
+
+    // Values byte for byte, even the spaces at the start.
+    resp_send(&mut redis, &["SET", "spaces", "  ab"]);
+    resp_expect(&mut redis, "+OK\r\n");
+    resp_send(&mut redis, &["APPEND", "spaces", " c"]);
+    resp_expect(&mut redis, ":6\r\n");
+    resp_send(&mut redis, &["GET", "spaces"]);
+    resp_expect(&mut redis, "$6\r\n  ab c\r\n");
+
+    resp_send(&mut redis, &["DEL", "key", "empty", "spaces", "missing"]);
+    resp_expect(&mut redis, ":3\r\n");
+    resp_send(&mut redis, &["GET", "key"]);
+    resp_expect(&mut redis, "$-1\r\n");
+}
+
+#[test]
+fn resp_setnx_and_channels() {
+    let server = TestServer::with(|builder| builder.resp("127.0.0.1:0"));
+    let address = server.addresses().resp.unwrap().to_string();
+    let mut redis = connect(&address);
+    let mut subscriber = connect(&address);
+
+    resp_send(&mut redis, &["SETNX", "empty", ""]);
+    resp_expect(&mut redis, ":1\r\n");
+    resp_send(&mut redis, &["SETNX", "empty", "value"]);
+    resp_expect(&mut redis, ":0\r\n");
+
+    // Each message on its own channel, even with the same last segment.
+    resp_send(&mut subscriber, &["SUBSCRIBE", "a.x", "b.x", "b"]);
+    resp_expect(
+        &mut subscriber,
+        "*3\r\n$9\r\nsubscribe\r\n$3\r\na.x\r\n:1\r\n\
+         *3\r\n$9\r\nsubscribe\r\n$3\r\nb.x\r\n:2\r\n\
+         *3\r\n$9\r\nsubscribe\r\n$1\r\nb\r\n:3\r\n",
+    );
+
+    resp_send(&mut redis, &["PUBLISH", "b.x", "one"]);
+    resp_expect(&mut redis, ":0\r\n");
+    resp_send(&mut redis, &["PUBLISH", "a.x", " two"]);
+    resp_expect(&mut redis, ":0\r\n");
+
+    resp_expect(
+        &mut subscriber,
+        "*3\r\n$7\r\nmessage\r\n$3\r\nb.x\r\n$3\r\none\r\n\
+         *3\r\n$7\r\nmessage\r\n$1\r\nb\r\n$3\r\none\r\n\
+         *3\r\n$7\r\nmessage\r\n$3\r\na.x\r\n$4\r\n two\r\n",
+    );
+}
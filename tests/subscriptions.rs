@@ -0,0 +1,146 @@
+mod common;
+
+use common::TestServer;
+
+#[test]
+fn fan_out_to_every_subscriber() {
+    let server = TestServer::start();
+    let mut subscribers: Vec<_> = (0..3).map(|_| server.connect()).collect();
+    let mut setter = server.connect();
+
+    for subscriber in subscribers.iter_mut() {
+        assert_eq!(subscriber.text("#g room"), "OK");
+    }
+
+    let msg_id = setter.send("s room.score 10");
+
+    for subscriber in subscribers.iter_mut() {
+        let message = subscriber.recv();
+
+        // With the header of the client that changed it.
+        assert_eq!(message.from, setter.id);
+        assert_eq!(message.id, msg_id);
+        assert_eq!(message.data, b"10");
+    }
+}
+
+#[test]
+fn kinds_of_subscriptions() {
+    let server = TestServer::start();
+    let mut get = server.connect();
+    let mut key_value = server.connect();
+    let mut json = server.connect();
+    let mut full_key = server.connect();
+    let mut setter = server.connect();
+
+    get.request("#g parent");
+    key_value.request("#k parent");
+    json.request("#j parent");
+    full_key.request("#f parent");
+
+    setter.request("s parent.child.id 42");
+
+    assert_eq!(get.recv().data, b"42");
+    assert_eq!(key_value.recv().data, b"id 42");
+    assert_eq!(full_key.recv().data, b"parent.child.id 42");
+
+    let value: serde_json::Value = serde_json::from_slice(&json.recv().data).unwrap();
+    assert_eq!(value, serde_json::json!({ "id": 42 }));
+}
+
+#[test]
+fn only_the_key_and_its_children() {
+    let server = TestServer::start();
+    let mut subscriber = server.connect();
+    let mut setter = server.connect();
+
+    subscriber.request("#g room.1");
+
+    setter.request("s room.2 other room");
+    setter.request("s room other parent");
+    setter.request("s room.1.player here");
+
+    assert_eq!(subscriber.recv().data, b"here");
+    subscriber.assert_silent();
+}
+
+#[test]
+fn json_writes_call_every_changed_leaf() {
+    let server = TestServer::start();
+    let mut subscriber = server.connect();
+    let mut setter = server.connect();
+
+    subscriber.request("#k player");
+    setter.request(r#"sj player { "name": "Adros", "pos": { "x": 1 } }"#);
+
+    let mut received = vec![subscriber.recv().data, subscriber.recv().data];
+    received.sort();
+    assert_eq!(received, vec![b"name Adros".to_vec(), b"x 1".to_vec()]);
+
+    setter.request(r#"jm player { "name": "Adros", "pos": { "x": 2 } }"#);
+    assert_eq!(subscriber.recv().data, b"x 2");
+    subscriber.assert_silent();
+}
+
+#[test]
+fn calls_without_changing_the_data() {
+    let server = TestServer::start();
+    let mut subscriber = server.connect();
+    let mut caller = server.connect();
+
+    caller.request("s key Something");
+    subscriber.request("#g key");
+
+    assert_eq!(caller.text("! key New value"), "OK");
+    assert_eq!(subscriber.recv().data, b"New value");
+    assert_eq!(caller.text("g key"), "Something");
+}
+
+#[test]
+fn unsubscribe_with_a_last_message() {
+    let server = TestServer::start();
+    let mut subscriber = server.connect();
+    let mut other = server.connect();
+    let mut setter = server.connect();
+
+    subscriber.request("#g key");
+    other.request("#g key");
+
+    assert_eq!(subscriber.text("#- key Goodbye"), "OK");
+    assert_eq!(subscriber.recv().data, b"Goodbye");
+    assert_eq!(other.recv().data, b"Goodbye");
+
+    setter.request("s key after");
+    assert_eq!(other.recv().data, b"after");
+    subscriber.assert_silent();
+}
+
+#[test]
+fn namespaces_are_isolated() {
+    let server = TestServer::start();
+    let mut subscriber = server.connect();
+    let mut setter = server.connect();
+
+    subscriber.request("n game1");
+    subscriber.request("#g room");
+
+    setter.request("s room.name default");
+    setter.request("n game1");
+    setter.request("s room.name game1");
+
+    assert_eq!(subscriber.recv().data, b"game1");
+    subscriber.assert_silent();
+}
+
+#[test]
+fn in_process_subscribers() {
+    let server = TestServer::start();
+    let changes = server.server.local().subscribe("room");
+    let mut setter = server.connect();
+
+    setter.request("s room.name BITE");
+
+    let (key, value) = changes.recv_timeout(common::TIMEOUT).unwrap();
+    assert_eq!(key, "room.name");
+    assert_eq!(value, b"BITE");
+}